@@ -1,6 +1,8 @@
 //! INFO: Simple wrapping of repeated drawing processes
 use cairo::{Context, ImageSurface};
 
+use crate::foreign_toplevel::{PICKER_PADDING, PICKER_ROW_HEIGHT, PickerLayout};
+
 /// Draw a basic interactive selection background
 pub fn draw_base(canvas: &mut [u8], w: i32, h: i32) -> cairo::Context {
     let cairo_surface = unsafe {
@@ -18,3 +20,99 @@ pub fn draw_base(canvas: &mut [u8], w: i32, h: i32) -> cairo::Context {
     cr.paint().unwrap();
     cr
 }
+
+/// Draw the remaining seconds of a delayed capture centered at (cx, cy) on a transparent canvas
+pub fn draw_countdown(canvas: &mut [u8], w: i32, h: i32, center: (f64, f64), text: &str) {
+    let cairo_surface = unsafe {
+        ImageSurface::create_for_data_unsafe(
+            canvas.as_mut_ptr(),
+            cairo::Format::ARgb32,
+            w,
+            h,
+            w * 4,
+        )
+        .expect("创建 Cairo ImageSurface 失败")
+    };
+    let cr = Context::new(&cairo_surface).expect("创建 Cairo 画布失败");
+    cr.select_font_face(
+        "sans-serif",
+        cairo::FontSlant::Normal,
+        cairo::FontWeight::Bold,
+    );
+    cr.set_font_size(h as f64 / 8.0);
+    let Ok(extents) = cr.text_extents(text) else {
+        return;
+    };
+    // 文字居中，使用深色描边保证在任意背景上可见
+    cr.move_to(
+        center.0 - extents.width() / 2.0 - extents.x_bearing(),
+        center.1 - extents.height() / 2.0 - extents.y_bearing(),
+    );
+    cr.text_path(text);
+    cr.set_source_rgba(0.0, 0.0, 0.0, 0.6);
+    cr.set_line_width(h as f64 / 200.0);
+    cr.stroke_preserve().ok();
+    cr.set_source_rgba(1.0, 1.0, 1.0, 0.9);
+    cr.fill().ok();
+}
+
+/// Draw the window list of the interactive window picker, `layout` is in logical coordinates
+/// and `scale` converts them to buffer pixels
+pub fn draw_window_list(
+    canvas: &mut [u8],
+    w: i32,
+    h: i32,
+    scale: f64,
+    layout: &PickerLayout,
+    labels: &[String],
+    highlighted: usize,
+) {
+    let cairo_surface = unsafe {
+        ImageSurface::create_for_data_unsafe(
+            canvas.as_mut_ptr(),
+            cairo::Format::ARgb32,
+            w,
+            h,
+            w * 4,
+        )
+        .expect("创建 Cairo ImageSurface 失败")
+    };
+    let cr = Context::new(&cairo_surface).expect("创建 Cairo 画布失败");
+    cr.set_source_rgba(0.0, 0.0, 0.0, 0.3);
+    cr.paint().ok();
+    cr.scale(scale, scale);
+
+    cr.rectangle(layout.x, layout.y, layout.width, layout.height);
+    cr.set_source_rgba(0.12, 0.12, 0.12, 0.9);
+    cr.fill().ok();
+
+    cr.select_font_face(
+        "sans-serif",
+        cairo::FontSlant::Normal,
+        cairo::FontWeight::Normal,
+    );
+    cr.set_font_size(PICKER_ROW_HEIGHT * 0.45);
+    for (i, label) in labels.iter().enumerate() {
+        let Some(top) = layout.row_y(i) else {
+            continue;
+        };
+        if i == highlighted {
+            cr.rectangle(layout.x, top, layout.width, PICKER_ROW_HEIGHT);
+            cr.set_source_rgba(0.25, 0.45, 0.8, 0.9);
+            cr.fill().ok();
+        }
+        // 超出列表宽度的文字被裁掉
+        cr.save().ok();
+        cr.rectangle(
+            layout.x + PICKER_PADDING,
+            top,
+            layout.width - 2.0 * PICKER_PADDING,
+            PICKER_ROW_HEIGHT,
+        );
+        cr.clip();
+        cr.move_to(layout.x + PICKER_PADDING, top + PICKER_ROW_HEIGHT * 0.65);
+        cr.set_source_rgba(1.0, 1.0, 1.0, 0.95);
+        cr.show_text(label).ok();
+        cr.restore().ok();
+    }
+}
@@ -1,13 +1,24 @@
 //! INFO: Define cli parameters, organize and convert cli parameters, and provide `FoamConfig` structure
 use chrono::Local;
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use directories::UserDirs;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::{frame_queue::GifOptions, save_helper::encoder, select_rect::SelectRect};
+
+/// 录制的默认帧率
+pub const DEFAULT_FPS: u32 = 30;
 
 #[derive(Parser)]
-#[command(version, long_about = None)]
+#[command(version, long_about = None, args_conflicts_with_subcommands = true)]
 struct CliArgs {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The directory path where the output file is located. The default is the XDG user image path
     #[arg(short = 'p', long)]
     path: Option<PathBuf>,
@@ -16,6 +27,15 @@ struct CliArgs {
     #[arg(short = 'n', long, default_value_t = Self::default_name())]
     name: String,
 
+    /// Write the image to FILE instead of PATH/NAME, `-` streams it to stdout (png unless --format is given)
+    #[arg(short = 'o', long = "output-file", value_name = "FILE|-", conflicts_with_all = ["path", "name", "recorder", "multi", "print_geometry"])]
+    output_file: Option<PathBuf>,
+
+    /// Output image format (png, jpg, webp, qoi, bmp, ppm, pam), replaces the extension of the name.
+    /// Without it the format follows the extension, webp is lossless unless --quality is given
+    #[arg(long, value_name = "FORMAT", value_parser = parse_format, conflicts_with = "recorder")]
+    format: Option<ImageType>,
+
     /// Whether to display the mouse when taking a screenshot. The default value is false
     #[arg(long, default_value_t = false)]
     show_cursor: bool,
@@ -24,6 +44,15 @@ struct CliArgs {
     #[arg(long, default_value_t = false)]
     no_copy: bool,
 
+    /// Also write NAME.json next to the image with the canvas origin and scale, and each output's region,
+    /// scale, transform and capture time, to map image pixels back to desktop coordinates. Not written with `-o -`
+    #[arg(long, default_value_t = false, conflicts_with_all = ["recorder", "print_geometry"])]
+    sidecar: bool,
+
+    /// Do not embed the capture time, output names, scales, selection, compositor and version into the image
+    #[arg(long, default_value_t = false)]
+    no_metadata: bool,
+
     /// Whether to freeze the screen before taking a screenshot, the default is false
     #[arg(long, default_value_t = false)]
     no_freeze: bool,
@@ -39,6 +68,152 @@ struct CliArgs {
     /// disable desktop notify, the default is false
     #[arg(long, default_value_t = false)]
     no_notify: bool,
+
+    /// Record the selected region to a gif instead of taking a screenshot, press `Esc`/`q` to stop
+    #[arg(long, default_value_t = false)]
+    recorder: bool,
+
+    /// Stop the recording automatically after the given number of seconds
+    #[arg(long, requires = "recorder")]
+    duration: Option<f64>,
+
+    /// Maximum frames per second captured while recording
+    #[arg(long, default_value_t = DEFAULT_FPS, value_parser = clap::value_parser!(u32).range(1..=60))]
+    fps: u32,
+
+    /// Capture a single window. Without a value the windows are listed on the overlay and picked with
+    /// the mouse or the keyboard, with a value the window whose app_id or title matches is captured
+    /// without interaction. Requires ext-foreign-toplevel-list
+    #[arg(long, value_name = "APP_ID|TITLE", num_args = 0..=1, default_missing_value = "", conflicts_with = "recorder")]
+    window: Option<String>,
+
+    /// Capture the output with the given name (such as DP-1) immediately, without the overlay
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["recorder", "window"])]
+    output: Option<String>,
+
+    /// Capture the region in the slurp/grim format "X,Y WxH" without the overlay, `-` reads it from stdin
+    #[arg(long, value_name = "X,Y WxH", value_parser = parse_geometry, conflicts_with_all = ["recorder", "window", "output"])]
+    geometry: Option<SelectRect>,
+
+    /// Only select a region and print it instead of capturing. FORMAT is `slurp` (default), `json`,
+    /// or a template with %x %y %w %h (logical coordinates), %o (output name) and %s (output scale)
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "slurp", conflicts_with_all = ["recorder", "window", "output", "geometry"])]
+    print_geometry: Option<String>,
+
+    /// Capture every output composited into one image, without the overlay
+    #[arg(long, default_value_t = false, conflicts_with_all = ["recorder", "window", "output", "geometry", "print_geometry"])]
+    all_outputs: bool,
+
+    /// How outputs with different scale factors are composited: `max` upscales everything to the largest scale, `logical` downscales to logical size
+    #[arg(long, value_enum, default_value_t = ScalePolicy::Max)]
+    scale_policy: ScalePolicy,
+
+    /// Scale of the image composited by --all-outputs, overrides --scale-policy
+    #[arg(long, value_name = "FACTOR", requires = "all_outputs")]
+    all_outputs_scale: Option<f64>,
+
+    /// Fill the gaps between outputs of non-rectangular layouts, as #RRGGBB or #RRGGBBAA. The default is transparent
+    #[arg(long, value_name = "COLOR", value_parser = parse_color)]
+    background: Option<[u8; 4]>,
+
+    /// Wait the given number of seconds before capturing, to screenshot menus and tooltips
+    #[arg(long, value_name = "SECS")]
+    delay: Option<f64>,
+
+    /// Start the delay after the region is selected, then capture that region without freezing
+    #[arg(long, default_value_t = false, requires = "delay", conflicts_with_all = ["recorder", "window", "output", "geometry", "all_outputs", "print_geometry"])]
+    delay_after_select: bool,
+
+    /// Show the remaining seconds of --delay. `overlay` draws on the selection and needs --delay-after-select, otherwise a notification is used
+    #[arg(long, value_enum, requires = "delay")]
+    countdown: Option<CountdownStyle>,
+
+    /// Capture the region selected last time without the overlay. Press `r` in interactive mode to load it for adjusting instead
+    #[arg(long, default_value_t = false, conflicts_with_all = ["recorder", "window", "output", "geometry", "print_geometry", "all_outputs", "delay_after_select"])]
+    last_region: bool,
+
+    /// Keep the overlay after saving to take several captures in a row, each saved under an auto-incremented file name.
+    /// Adjust the selection and press `s` to save it, `Esc` quits
+    #[arg(long, default_value_t = false, conflicts_with_all = ["recorder", "window", "output", "geometry", "print_geometry", "all_outputs", "last_region", "delay_after_select", "full_screen"])]
+    multi: bool,
+
+    /// Share one palette across all gif frames, smaller file and no color flicker between frames
+    #[arg(long, default_value_t = false, requires = "recorder")]
+    gif_global_palette: bool,
+
+    /// Apply Floyd-Steinberg dithering when reducing gif frames to 256 colors
+    #[arg(long, default_value_t = false, requires = "recorder")]
+    gif_dither: bool,
+
+    /// Save png with 16 bits per channel, keeps the precision of 10-bit outputs
+    #[arg(long, default_value_t = false)]
+    png_16bit: bool,
+
+    /// JPEG or WebP quality from 1 to 100. JPEG defaults to 100, WebP is lossless without it.
+    /// Other formats are lossless and reject it
+    #[arg(long, value_name = "1-100", value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: Option<u8>,
+
+    /// Deflate level of png files, `fast` trades size for speed
+    #[arg(long, value_enum, value_name = "LEVEL", default_value_t = PngCompression::Default)]
+    png_compression: PngCompression,
+
+    /// Row filter of png files, `adaptive` picks the smallest filter per row like cairo did
+    #[arg(long, value_enum, value_name = "FILTER", default_value_t = PngFilter::Adaptive)]
+    png_filter: PngFilter,
+
+    /// Let the compositor copy frames into udmabuf backed dmabufs instead of shm, requires /dev/udmabuf
+    #[arg(long, default_value_t = false)]
+    dmabuf: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Keep the wayland connection open and take the captures requested by other foamshot invocations,
+    /// which forward their options over a unix socket in $XDG_RUNTIME_DIR
+    Daemon,
+}
+
+/// `-` 时从 stdin 读取一行，便于 `slurp | foamshot --geometry -`
+fn parse_geometry(value: &str) -> Result<SelectRect, String> {
+    if value != "-" {
+        return SelectRect::from_geometry(value);
+    }
+    let mut line = String::new();
+    io::stdin()
+        .read_line(&mut line)
+        .map_err(|e| format!("failed to read geometry from stdin: {}", e))?;
+    SelectRect::from_geometry(&line)
+}
+
+/// 只接受可用于截图的格式，gif 仅用于录制
+fn parse_format(value: &str) -> Result<ImageType, String> {
+    match encoder::by_name(value) {
+        Some(e) if e.encode.is_some() => Ok(e.image_type),
+        Some(e) => Err(format!("{} is only supported with --recorder", e.name)),
+        None => Err(format!(
+            "unknown format \"{}\", supported: {}",
+            value,
+            encoder::names()
+        )),
+    }
+}
+
+/// 解析 `#RRGGBB` 或 `#RRGGBBAA`，返回 RGBA
+pub fn parse_color(value: &str) -> Result<[u8; 4], String> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+        return Err(format!(
+            "invalid color \"{}\", expected #RRGGBB or #RRGGBBAA",
+            value
+        ));
+    }
+    let mut rgba = [0, 0, 0, 255];
+    for (i, c) in rgba.iter_mut().enumerate().take(hex.len() / 2) {
+        *c = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("invalid color \"{}\"", value))?;
+    }
+    Ok(rgba)
 }
 
 impl CliArgs {
@@ -47,11 +222,61 @@ impl CliArgs {
     }
 }
 
-#[derive(Default, Debug, Clone)]
+/// 输出格式，对应的编码器在 `save_helper::encoder::ENCODERS` 中注册
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ImageType {
     #[default]
     Png,
     Jpg,
+    Gif,
+    Webp,
+    Qoi,
+    Bmp,
+    Ppm,
+    Pam,
+}
+
+/// 合成不同缩放的输出时使用的缩放
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ScalePolicy {
+    /// 放大到参与合成的输出中最大的缩放，不损失高分屏的细节
+    #[default]
+    Max,
+    /// 缩小到逻辑尺寸
+    Logical,
+}
+
+/// png 的压缩等级
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PngCompression {
+    /// 最快，文件最大
+    Fast,
+    #[default]
+    Default,
+    /// 最慢，文件最小
+    Best,
+}
+
+/// png 每行使用的过滤器
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    Avg,
+    Paeth,
+    /// 每行选择结果最小的过滤器
+    #[default]
+    Adaptive,
+}
+
+/// 延迟截图时倒计时的显示方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CountdownStyle {
+    /// 可替换的桌面通知
+    Notify,
+    /// 在 layer surface 的选区上绘制
+    Overlay,
 }
 
 #[derive(Debug)]
@@ -59,12 +284,18 @@ pub enum ImageType {
 pub struct FoamConfig {
     /// 输出路径
     pub output_path: PathBuf,
+    /// 是否将图片写入标准输出，此时 `output_path` 为 `-`
+    pub stdout: bool,
     /// 输出类型，默认为 png
     pub image_type: ImageType,
     /// 截图是否显示鼠标
     pub cursor: bool,
     /// 截图后是否自动复制到剪贴板
     pub auto_copy: bool,
+    /// 是否在图片中写入截图的来源信息
+    pub metadata: bool,
+    /// 是否在图片旁写入描述截图的 json
+    pub sidecar: bool,
 
     /// TODO: 截图后是否进入编辑模式
     pub edit: bool,
@@ -74,6 +305,55 @@ pub struct FoamConfig {
     pub full_screen: bool,
 
     pub allow_notify: bool,
+
+    /// 是否为录制模式
+    pub recorder: bool,
+    /// 录制时长，None 表示直到按下 Esc/q
+    pub duration: Option<Duration>,
+    /// 录制的最大帧率
+    pub fps: u32,
+    /// 录制输出 gif 的编码选项
+    pub gif: GifOptions,
+    /// 窗口捕获的匹配条件，为空时交互式选择窗口
+    pub window: Option<String>,
+    /// 非交互捕获的输出名称
+    pub output_name: Option<String>,
+    /// 非交互捕获的区域，全局逻辑坐标
+    pub geometry: Option<SelectRect>,
+    /// 只选择区域并按该格式输出几何信息，不截图
+    pub print_geometry: Option<String>,
+    /// 是否捕获所有输出并合成为一张图片
+    pub all_outputs: bool,
+    /// 合成不同缩放的输出时使用的缩放策略
+    pub scale_policy: ScalePolicy,
+    /// 合成所有输出时使用的缩放，优先于 `scale_policy`
+    pub all_outputs_scale: Option<f64>,
+    /// 合成图片中输出之间空隙的填充色 (RGBA)，None 时透明
+    pub background: Option<[u8; 4]>,
+    /// 截图前的延迟
+    pub delay: Option<Duration>,
+    /// 是否在选区确定后才开始延迟
+    pub delay_after_select: bool,
+    /// 延迟时倒计时的显示方式，None 时不显示
+    pub countdown: Option<CountdownStyle>,
+    /// 是否非交互地捕获上次的选区
+    pub last_region: bool,
+    /// 是否连续截图，保存后回到等待选区
+    pub multi: bool,
+    /// 格式化后请求的文件名，连续截图时以它为基础重新编号
+    pub requested_name: String,
+    /// 是否使用 dmabuf 接收 copy 的帧
+    pub dmabuf: bool,
+    /// png 是否保存为每通道 16 位
+    pub png_16bit: bool,
+    /// 有损格式的质量，None 时使用各格式的默认值
+    pub quality: Option<u8>,
+    /// png 的压缩等级
+    pub png_compression: PngCompression,
+    /// png 的行过滤器
+    pub png_filter: PngFilter,
+    /// 是否作为守护进程运行，只在本进程中有效，不会转发
+    pub daemon: bool,
 }
 
 impl Default for FoamConfig {
@@ -86,28 +366,114 @@ impl FoamConfig {
     pub fn new() -> Self {
         let args = CliArgs::parse();
 
+        let stdout = args.output_file.as_deref() == Some(Path::new("-"));
+        // -o 指定文件时拆分为目录与文件名，之后与 -p/-n 的处理相同
+        let (path, name) = match args.output_file.filter(|_| !stdout) {
+            Some(file) => (
+                Some(
+                    file.parent()
+                        .filter(|p| !p.as_os_str().is_empty())
+                        .map_or_else(|| PathBuf::from("."), Path::to_path_buf),
+                ),
+                file.file_name()
+                    .map_or(args.name, |n| n.to_string_lossy().to_string()),
+            ),
+            None => (args.path, args.name),
+        };
+
         // 构造完整的输出路径
         let formatted_path =
-            Self::format_path(args.path.unwrap_or(Self::generate_default_output_path()));
-        let formatted_name = Self::replace_time_specifiers(&args.name);
-        let (final_path, final_name) = Self::validate_path(&formatted_path, &formatted_name);
-        let mut output_path = final_path;
-        output_path.push(final_name);
+            Self::format_path(path.unwrap_or(Self::generate_default_output_path()));
+        let mut formatted_name = Self::replace_time_specifiers(&name);
+        // 录制模式下统一输出为 gif
+        if args.recorder {
+            formatted_name = Path::new(&formatted_name)
+                .with_extension("gif")
+                .to_string_lossy()
+                .to_string();
+        }
+        let image_type = Self::detect_image_type(&mut formatted_name, args.format, args.recorder)
+            .unwrap_or_else(|msg| {
+                CliArgs::command()
+                    .error(ErrorKind::InvalidValue, msg)
+                    .exit()
+            });
+        if let Err(msg) = Self::check_quality(args.quality, image_type) {
+            CliArgs::command()
+                .error(ErrorKind::ArgumentConflict, msg)
+                .exit()
+        }
 
-        let image_type = Self::detect_image_type(&mut output_path);
+        // NOTE: 写入标准输出时不接触文件系统
+        let output_path = if stdout {
+            PathBuf::from("-")
+        } else {
+            let (final_path, final_name) = Self::validate_path(&formatted_path, &formatted_name);
+            final_path.join(final_name)
+        };
+
+        let requested_name = formatted_name;
 
         FoamConfig {
             output_path,
+            stdout,
             image_type,
             cursor: args.show_cursor,
             edit: args.edit,
             auto_copy: !args.no_copy,
+            metadata: !args.no_metadata,
+            sidecar: args.sidecar,
             freeze: !args.no_freeze,
             full_screen: args.full_screen,
             allow_notify: !args.no_notify,
+            recorder: args.recorder,
+            duration: args
+                .duration
+                .filter(|d| d.is_finite() && *d > 0.0)
+                .map(Duration::from_secs_f64),
+            fps: args.fps,
+            gif: GifOptions {
+                global_palette: args.gif_global_palette,
+                dither: args.gif_dither,
+                ..Default::default()
+            },
+            window: args.window,
+            output_name: args.output,
+            geometry: args.geometry,
+            print_geometry: args.print_geometry,
+            all_outputs: args.all_outputs,
+            scale_policy: args.scale_policy,
+            all_outputs_scale: args.all_outputs_scale.filter(|s| s.is_finite() && *s > 0.0),
+            background: args.background,
+            delay: args
+                .delay
+                .filter(|d| d.is_finite() && *d > 0.0)
+                .map(Duration::from_secs_f64),
+            delay_after_select: args.delay_after_select,
+            countdown: args.countdown,
+            last_region: args.last_region,
+            multi: args.multi,
+            requested_name,
+            dmabuf: args.dmabuf,
+            png_16bit: args.png_16bit,
+            quality: args.quality,
+            png_compression: args.png_compression,
+            png_filter: args.png_filter,
+            daemon: matches!(args.command, Some(Command::Daemon)),
         }
     }
 
+    /// 连续截图时换到下一个可用的文件名，沿用 `validate_path` 的编号
+    pub fn next_output_path(&mut self) {
+        let dir = self
+            .output_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let (final_path, final_name) = Self::validate_path(&dir, &self.requested_name);
+        self.output_path = final_path.join(final_name);
+    }
+
     fn format_path(path: PathBuf) -> PathBuf {
         let path_str = path.to_string_lossy().to_string();
         let formatted_path = Self::replace_time_specifiers(&path_str);
@@ -129,11 +495,9 @@ impl FoamConfig {
     }
 
     fn generate_default_output_path() -> PathBuf {
-        let path = UserDirs::new()
+        UserDirs::new()
             .and_then(|ud| ud.picture_dir().map(|p| p.to_path_buf()))
-            .unwrap_or_else(|| PathBuf::from("."));
-
-        path
+            .unwrap_or_else(|| PathBuf::from("."))
     }
 
     fn validate_path(dir_path: &Path, filename: &str) -> (PathBuf, String) {
@@ -197,41 +561,105 @@ impl FoamConfig {
         (final_path, final_name)
     }
 
-    fn detect_image_type(path: &mut PathBuf) -> ImageType {
-        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-            match ext.to_lowercase().as_str() {
-                "jpg" | "jpeg" => ImageType::Jpg,
-                "png" => ImageType::Png,
-                _ => {
-                    // 如果无法识别后缀，将其设置为 .png
-                    if let Some(parent) = path.parent() {
-                        if let Some(file_stem) = path.file_stem() {
-                            let mut new_path = parent.to_path_buf();
-                            new_path.push(format!("{}.png", file_stem.to_string_lossy()));
-                            *path = new_path;
-                        } else {
-                            let mut new_path = parent.to_path_buf();
-                            new_path.push("screenshot.png");
-                            *path = new_path;
-                        }
-                    }
-                    ImageType::Png
-                }
-            }
-        } else {
-            // 如果没有后缀，添加 .png
-            if let Some(parent) = path.parent() {
-                if let Some(file_stem) = path.file_stem() {
-                    let mut new_path = parent.to_path_buf();
-                    new_path.push(format!("{}.png", file_stem.to_string_lossy()));
-                    *path = new_path;
-                } else {
-                    let mut new_path = parent.to_path_buf();
-                    new_path.push("screenshot.png");
-                    *path = new_path;
-                }
+    /// `--quality` 只用于支持有损编码的格式
+    fn check_quality(quality: Option<u8>, image_type: ImageType) -> Result<(), String> {
+        let encoder = encoder::by_type(image_type);
+        if quality.is_none() || encoder.lossy {
+            return Ok(());
+        }
+        let lossy: Vec<&str> = encoder::ENCODERS
+            .iter()
+            .filter(|e| e.lossy)
+            .map(|e| e.name)
+            .collect();
+        Err(format!(
+            "--quality only applies to {}, {} is always encoded lossless",
+            lossy.join("/"),
+            encoder.name
+        ))
+    }
+
+    /// 根据后缀或 `--format` 确定输出格式，必要时修改文件名的后缀
+    /// NOTE: 没有后缀时使用 png，无法识别的后缀直接报错而不是改写
+    fn detect_image_type(
+        name: &mut String,
+        format: Option<ImageType>,
+        recorder: bool,
+    ) -> Result<ImageType, String> {
+        let path = Path::new(name.as_str());
+        let ext = path.extension().and_then(|e| e.to_str());
+
+        if let Some(image_type) = format {
+            let wanted = encoder::by_type(image_type);
+            if !ext.is_some_and(|ext| {
+                encoder::by_extension(ext).is_some_and(|e| e.image_type == image_type)
+            }) {
+                *name = path
+                    .with_extension(wanted.extensions[0])
+                    .to_string_lossy()
+                    .to_string();
             }
-            ImageType::Png
+            return Ok(image_type);
         }
+
+        let Some(ext) = ext else {
+            *name = path.with_extension("png").to_string_lossy().to_string();
+            return Ok(ImageType::Png);
+        };
+        match encoder::by_extension(ext) {
+            Some(e) if e.encode.is_some() || recorder => Ok(e.image_type),
+            Some(e) => Err(format!(
+                "{} is only supported with --recorder, use --format to pick another format",
+                e.name
+            )),
+            None => Err(format!(
+                "unknown extension \".{}\" in \"{}\", use --format or one of: {}",
+                ext,
+                name,
+                encoder::names()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quality_is_accepted_for_lossy_formats() {
+        for image_type in [ImageType::Jpg, ImageType::Webp] {
+            assert!(FoamConfig::check_quality(Some(80), image_type).is_ok());
+        }
+        for image_type in [
+            ImageType::Png,
+            ImageType::Qoi,
+            ImageType::Bmp,
+            ImageType::Ppm,
+            ImageType::Pam,
+        ] {
+            let err = FoamConfig::check_quality(Some(80), image_type).unwrap_err();
+            assert!(
+                err.starts_with("--quality only applies to jpg/webp"),
+                "{}",
+                err
+            );
+            assert!(FoamConfig::check_quality(None, image_type).is_ok());
+        }
+    }
+
+    #[test]
+    fn bare_window_flag_picks_interactively() {
+        let window = |args: &[&str]| {
+            CliArgs::try_parse_from(["foamshot"].iter().chain(args))
+                .unwrap()
+                .window
+        };
+        assert_eq!(window(&["--window"]), Some(String::new()));
+        assert_eq!(
+            window(&["--window", "firefox"]),
+            Some("firefox".to_string())
+        );
+        assert_eq!(window(&[]), None);
     }
 }
@@ -0,0 +1,581 @@
+//! INFO: `foamshot daemon` keeps the wayland connection and bound globals warm,
+//! other invocations forward their `FoamConfig` over a unix socket and receive the session result
+use clap::ValueEnum;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::time::Duration;
+use wayland_client::{EventQueue, backend::WaylandError};
+
+use crate::{
+    config::{self, CountdownStyle, FoamConfig, ImageType, PngCompression, PngFilter, ScalePolicy},
+    foamcore::{FoamShot, SessionResult},
+    frame_queue::GifOptions,
+    save_helper::encoder,
+    select_rect::SelectRect,
+};
+
+/// 读取请求的超时，避免异常的客户端阻塞守护进程
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// `$XDG_RUNTIME_DIR/foamshot.sock`
+pub fn socket_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join("foamshot.sock"))
+}
+
+/// 守护进程运行时将配置转发给它并等待结果，没有运行或无法转发时返回 None
+pub fn forward(config: &FoamConfig) -> Option<SessionResult> {
+    // NOTE: 回复只能携带文本，写入标准输出的图片在本进程中截取
+    if config.stdout {
+        return None;
+    }
+    let request = encode_config(config)?;
+    let path = socket_path()?;
+    let mut stream = UnixStream::connect(&path).ok()?;
+    debug!("forward to daemon {}", path.display());
+
+    let result = (|| {
+        stream.write_all(request.as_bytes())?;
+        // NOTE: 关闭写端表示请求结束
+        stream.shutdown(std::net::Shutdown::Write)?;
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply)?;
+        Ok::<_, io::Error>(reply)
+    })();
+    Some(match result {
+        Ok(reply) => decode_reply(&reply),
+        Err(e) => Err(format!("failed to talk to foamshot daemon: {}", e)),
+    })
+}
+
+/// 绑定 socket 并保持 wayland 连接，依次处理每个请求
+pub fn serve(config: FoamConfig) {
+    let Some(path) = socket_path() else {
+        eprintln!("XDG_RUNTIME_DIR is not set");
+        std::process::exit(1);
+    };
+    // NOTE: 能连接上说明已有守护进程，否则是上次遗留的 socket
+    if UnixStream::connect(&path).is_ok() {
+        eprintln!("foamshot daemon is already running on {}", path.display());
+        std::process::exit(1);
+    }
+    std::fs::remove_file(&path).ok();
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("failed to bind {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let (mut event_queue, mut shot_foam) = FoamShot::connect(config);
+    if let Err(e) = shot_foam.wait_output_info(&mut event_queue) {
+        eprintln!("foamshot daemon failed to start: {}", e);
+        std::fs::remove_file(&path).ok();
+        std::process::exit(1);
+    }
+    info!("foamshot daemon listening on {}", path.display());
+
+    loop {
+        let mut stream = match wait_request(&listener, &mut event_queue, &mut shot_foam) {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("foamshot daemon stopped: {}", e);
+                std::fs::remove_file(&path).ok();
+                std::process::exit(1);
+            }
+        };
+
+        let result = read_request(&mut stream).and_then(|request| decode_config(&request));
+        let result = match result {
+            Ok(config) => {
+                debug!("daemon request: {:?}", config);
+                shot_foam.wlctx.set_config(config);
+                let result = shot_foam.run_session(&mut event_queue);
+                shot_foam.reset_session();
+                result
+            }
+            Err(e) => Err(format!("invalid request: {}", e)),
+        };
+        if let Err(e) = stream.write_all(encode_reply(&result).as_bytes()) {
+            warn!("failed to reply to client: {}", e);
+        }
+    }
+}
+
+/// 空闲时同时等待 wayland 事件与新的连接，避免合成器的事件堆积
+fn wait_request(
+    listener: &UnixListener,
+    event_queue: &mut EventQueue<FoamShot>,
+    shot_foam: &mut FoamShot,
+) -> Result<UnixStream, String> {
+    loop {
+        event_queue
+            .dispatch_pending(shot_foam)
+            .map_err(|e| e.to_string())?;
+        event_queue.flush().map_err(|e| e.to_string())?;
+        let Some(guard) = event_queue.prepare_read() else {
+            continue;
+        };
+
+        let mut fds = [
+            libc::pollfd {
+                fd: guard.connection_fd().as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: listener.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e.to_string());
+        }
+
+        if fds[0].revents != 0 {
+            match guard.read() {
+                Ok(_) => {}
+                Err(WaylandError::Io(e)) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        if fds[1].revents & libc::POLLIN != 0 {
+            match listener.accept() {
+                Ok((stream, _)) => return Ok(stream),
+                Err(e) => warn!("failed to accept client: {}", e),
+            }
+        }
+    }
+}
+
+fn read_request(stream: &mut UnixStream) -> Result<String, String> {
+    let mut request = String::new();
+    stream
+        .set_read_timeout(Some(READ_TIMEOUT))
+        .and_then(|_| stream.read_to_string(&mut request))
+        .map_err(|e| e.to_string())?;
+    Ok(request)
+}
+
+/// NOTE: 请求为每行一个 `key=value`，值中的 `\` 与换行被转义，None 的字段省略
+/// 相对路径按客户端的工作目录解析，路径不是 UTF-8 时不转发
+fn encode_config(config: &FoamConfig) -> Option<String> {
+    let output_path = std::path::absolute(&config.output_path).ok()?;
+    let mut fields = vec![
+        ("output_path", output_path.to_str()?.to_string()),
+        (
+            "image_type",
+            encoder::by_type(config.image_type).name.to_string(),
+        ),
+        ("cursor", config.cursor.to_string()),
+        ("auto_copy", config.auto_copy.to_string()),
+        ("metadata", config.metadata.to_string()),
+        ("sidecar", config.sidecar.to_string()),
+        ("edit", config.edit.to_string()),
+        ("freeze", config.freeze.to_string()),
+        ("full_screen", config.full_screen.to_string()),
+        ("allow_notify", config.allow_notify.to_string()),
+        ("recorder", config.recorder.to_string()),
+        ("fps", config.fps.to_string()),
+        ("gif_global_palette", config.gif.global_palette.to_string()),
+        ("gif_dither", config.gif.dither.to_string()),
+        ("gif_speed", config.gif.speed.to_string()),
+        ("all_outputs", config.all_outputs.to_string()),
+        ("scale_policy", value_name(config.scale_policy)),
+        ("delay_after_select", config.delay_after_select.to_string()),
+        ("last_region", config.last_region.to_string()),
+        ("multi", config.multi.to_string()),
+        ("requested_name", config.requested_name.clone()),
+        ("dmabuf", config.dmabuf.to_string()),
+        ("png_16bit", config.png_16bit.to_string()),
+        ("png_compression", value_name(config.png_compression)),
+        ("png_filter", value_name(config.png_filter)),
+    ];
+    let optional = [
+        (
+            "duration",
+            config.duration.map(|d| d.as_secs_f64().to_string()),
+        ),
+        ("window", config.window.clone()),
+        ("output_name", config.output_name.clone()),
+        (
+            "geometry",
+            config
+                .geometry
+                .as_ref()
+                .map(|rect| rect.format_geometry("slurp", "", 1.0)),
+        ),
+        ("print_geometry", config.print_geometry.clone()),
+        (
+            "all_outputs_scale",
+            config.all_outputs_scale.map(|s| s.to_string()),
+        ),
+        (
+            "background",
+            config
+                .background
+                .map(|[r, g, b, a]| format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)),
+        ),
+        ("delay", config.delay.map(|d| d.as_secs_f64().to_string())),
+        ("countdown", config.countdown.map(value_name)),
+        ("quality", config.quality.map(|q| q.to_string())),
+    ];
+    fields.extend(
+        optional
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?))),
+    );
+
+    Some(
+        fields
+            .into_iter()
+            .map(|(key, value)| {
+                format!(
+                    "{}={}\n",
+                    key,
+                    value.replace('\\', "\\\\").replace('\n', "\\n")
+                )
+            })
+            .collect(),
+    )
+}
+
+/// 在守护进程中解码客户端的配置
+fn decode_config(request: &str) -> Result<FoamConfig, String> {
+    let mut fields = HashMap::new();
+    for line in request.lines() {
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("invalid line \"{}\"", line))?;
+        fields.insert(key, unescape(value));
+    }
+
+    let flag = |key: &str| fields.get(key).is_some_and(|v| v == "true");
+    let number = |key: &str| -> Result<Option<f64>, String> {
+        fields
+            .get(key)
+            .map(|v| v.parse().map_err(|_| format!("invalid {} \"{}\"", key, v)))
+            .transpose()
+    };
+    let secs = |key: &str| -> Result<Option<Duration>, String> {
+        Ok(number(key)?
+            .filter(|d| d.is_finite() && *d > 0.0)
+            .map(Duration::from_secs_f64))
+    };
+
+    let output_path = fields
+        .get("output_path")
+        .map(PathBuf::from)
+        .ok_or("missing output_path")?;
+    let image_type = match fields.get("image_type") {
+        None => ImageType::Png,
+        Some(name) => {
+            encoder::by_name(name)
+                .ok_or_else(|| format!("invalid image_type \"{}\"", name))?
+                .image_type
+        }
+    };
+
+    let requested_name = fields
+        .get("requested_name")
+        .cloned()
+        .or_else(|| Some(output_path.file_name()?.to_string_lossy().to_string()))
+        .unwrap_or_default();
+
+    Ok(FoamConfig {
+        output_path,
+        stdout: false,
+        image_type,
+        cursor: flag("cursor"),
+        auto_copy: flag("auto_copy"),
+        metadata: flag("metadata"),
+        sidecar: flag("sidecar"),
+        edit: flag("edit"),
+        freeze: flag("freeze"),
+        full_screen: flag("full_screen"),
+        allow_notify: flag("allow_notify"),
+        recorder: flag("recorder"),
+        duration: secs("duration")?,
+        fps: number("fps")?.map_or(config::DEFAULT_FPS, |f| f.clamp(1.0, 60.0) as u32),
+        gif: GifOptions {
+            global_palette: flag("gif_global_palette"),
+            dither: flag("gif_dither"),
+            speed: number("gif_speed")?.map_or(GifOptions::default().speed, |s| s as i32),
+        },
+        window: fields.get("window").cloned(),
+        output_name: fields.get("output_name").cloned(),
+        geometry: fields
+            .get("geometry")
+            .map(|v| SelectRect::from_geometry(v))
+            .transpose()?,
+        print_geometry: fields.get("print_geometry").cloned(),
+        all_outputs: flag("all_outputs"),
+        scale_policy: fields
+            .get("scale_policy")
+            .map(|v| ScalePolicy::from_str(v, false))
+            .transpose()?
+            .unwrap_or_default(),
+        all_outputs_scale: number("all_outputs_scale")?.filter(|s| s.is_finite() && *s > 0.0),
+        background: fields
+            .get("background")
+            .map(|v| config::parse_color(v))
+            .transpose()?,
+        delay: secs("delay")?,
+        delay_after_select: flag("delay_after_select"),
+        countdown: fields
+            .get("countdown")
+            .map(|v| CountdownStyle::from_str(v, false))
+            .transpose()?,
+        last_region: flag("last_region"),
+        multi: flag("multi"),
+        requested_name,
+        dmabuf: flag("dmabuf"),
+        png_16bit: flag("png_16bit"),
+        quality: number("quality")?.map(|q| q.clamp(1.0, 100.0) as u8),
+        png_compression: fields
+            .get("png_compression")
+            .map(|v| PngCompression::from_str(v, false))
+            .transpose()?
+            .unwrap_or_default(),
+        png_filter: fields
+            .get("png_filter")
+            .map(|v| PngFilter::from_str(v, false))
+            .transpose()?
+            .unwrap_or_default(),
+        // NOTE: 守护进程中的会话同样需要为之后的请求绑定 global
+        daemon: true,
+    })
+}
+
+/// NOTE: 回复的第一行为 `ok`、`cancel` 或 `error`，之后为输出内容或错误信息
+fn encode_reply(result: &SessionResult) -> String {
+    match result {
+        Ok(Some(out)) => format!("ok\n{}", out),
+        Ok(None) => "cancel\n".to_string(),
+        Err(e) => format!("error\n{}", e),
+    }
+}
+
+fn decode_reply(reply: &str) -> SessionResult {
+    let (status, body) = reply.split_once('\n').unwrap_or((reply, ""));
+    match status {
+        "ok" => Ok(Some(body.to_string())),
+        "cancel" => Ok(None),
+        "error" => Err(body.to_string()),
+        _ => Err("foamshot daemon closed the connection".to_string()),
+    }
+}
+
+fn value_name<T: ValueEnum>(value: T) -> String {
+    value
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{PngCompression, PngFilter};
+
+    /// 每个字段都不是默认值，字段是否被转发由比较结果体现
+    fn sample_config() -> FoamConfig {
+        FoamConfig {
+            output_path: PathBuf::from("/tmp/shots/a b\\c\nd=e.webp"),
+            stdout: false,
+            image_type: ImageType::Webp,
+            cursor: true,
+            auto_copy: true,
+            metadata: true,
+            sidecar: true,
+            edit: true,
+            freeze: true,
+            full_screen: true,
+            allow_notify: true,
+            recorder: true,
+            duration: Some(Duration::from_millis(1500)),
+            fps: 12,
+            gif: GifOptions {
+                global_palette: true,
+                dither: true,
+                speed: 7,
+            },
+            window: Some("fire=fox\\\nx".to_string()),
+            output_name: Some("DP-1".to_string()),
+            geometry: Some(SelectRect::from_geometry("-10,20 300x200").unwrap()),
+            print_geometry: Some("%x %y\n%w".to_string()),
+            all_outputs: true,
+            scale_policy: ScalePolicy::Logical,
+            all_outputs_scale: Some(1.5),
+            background: Some([1, 2, 3, 4]),
+            delay: Some(Duration::from_millis(2250)),
+            delay_after_select: true,
+            countdown: Some(CountdownStyle::Overlay),
+            last_region: true,
+            multi: true,
+            requested_name: "a b\\c\nd=e.webp".to_string(),
+            dmabuf: true,
+            png_16bit: true,
+            quality: Some(80),
+            png_compression: PngCompression::Best,
+            png_filter: PngFilter::Paeth,
+            daemon: false,
+        }
+    }
+
+    #[test]
+    fn config_roundtrip_covers_every_field() {
+        let sent = sample_config();
+        let request = encode_config(&sent).unwrap();
+        let received = decode_config(&request).unwrap();
+
+        // NOTE: 不使用 `..`，新增字段时这里无法编译，必须同时处理编码与解码
+        let FoamConfig {
+            output_path,
+            stdout,
+            image_type,
+            cursor,
+            auto_copy,
+            metadata,
+            sidecar,
+            edit,
+            freeze,
+            full_screen,
+            allow_notify,
+            recorder,
+            duration,
+            fps,
+            gif,
+            window,
+            output_name,
+            geometry,
+            print_geometry,
+            all_outputs,
+            scale_policy,
+            all_outputs_scale,
+            background,
+            delay,
+            delay_after_select,
+            countdown,
+            last_region,
+            multi,
+            requested_name,
+            dmabuf,
+            png_16bit,
+            quality,
+            png_compression,
+            png_filter,
+            daemon,
+        } = received;
+
+        assert_eq!(output_path, sent.output_path);
+        // 写入标准输出的请求不会转发，守护进程中的会话总是 daemon
+        assert!(!stdout);
+        assert!(daemon);
+        assert_eq!(image_type, sent.image_type);
+        assert_eq!(cursor, sent.cursor);
+        assert_eq!(auto_copy, sent.auto_copy);
+        assert_eq!(metadata, sent.metadata);
+        assert_eq!(sidecar, sent.sidecar);
+        assert_eq!(edit, sent.edit);
+        assert_eq!(freeze, sent.freeze);
+        assert_eq!(full_screen, sent.full_screen);
+        assert_eq!(allow_notify, sent.allow_notify);
+        assert_eq!(recorder, sent.recorder);
+        assert_eq!(duration, sent.duration);
+        assert_eq!(fps, sent.fps);
+        assert_eq!(format!("{:?}", gif), format!("{:?}", sent.gif));
+        assert_eq!(window, sent.window);
+        assert_eq!(output_name, sent.output_name);
+        assert_eq!(format!("{:?}", geometry), format!("{:?}", sent.geometry));
+        assert_eq!(print_geometry, sent.print_geometry);
+        assert_eq!(all_outputs, sent.all_outputs);
+        assert_eq!(scale_policy, sent.scale_policy);
+        assert_eq!(all_outputs_scale, sent.all_outputs_scale);
+        assert_eq!(background, sent.background);
+        assert_eq!(delay, sent.delay);
+        assert_eq!(delay_after_select, sent.delay_after_select);
+        assert_eq!(countdown, sent.countdown);
+        assert_eq!(last_region, sent.last_region);
+        assert_eq!(multi, sent.multi);
+        assert_eq!(requested_name, sent.requested_name);
+        assert_eq!(dmabuf, sent.dmabuf);
+        assert_eq!(png_16bit, sent.png_16bit);
+        assert_eq!(quality, sent.quality);
+        assert_eq!(png_compression, sent.png_compression);
+        assert_eq!(png_filter, sent.png_filter);
+    }
+
+    #[test]
+    fn omitted_fields_decode_to_defaults() {
+        let config = decode_config("output_path=/tmp/a.png\n").unwrap();
+        assert_eq!(config.image_type, ImageType::Png);
+        assert_eq!(config.requested_name, "a.png");
+        assert!(!config.cursor);
+        assert!(config.duration.is_none());
+        assert_eq!(config.fps, config::DEFAULT_FPS);
+        assert!(config.geometry.is_none());
+        assert_eq!(config.scale_policy, ScalePolicy::default());
+        assert_eq!(config.png_filter, PngFilter::default());
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        assert!(decode_config("").is_err());
+        assert!(decode_config("output_path=/tmp/a.png\nno separator\n").is_err());
+        assert!(decode_config("output_path=/tmp/a.png\nimage_type=tiff\n").is_err());
+        assert!(decode_config("output_path=/tmp/a.png\ndelay=soon\n").is_err());
+        assert!(decode_config("output_path=/tmp/a.png\nscale_policy=min\n").is_err());
+    }
+
+    #[test]
+    fn unescape_reverses_escaping() {
+        assert_eq!(unescape("plain"), "plain");
+        assert_eq!(unescape("a\\nb"), "a\nb");
+        assert_eq!(unescape("a\\\\nb"), "a\\nb");
+        assert_eq!(unescape("trailing\\"), "trailing\\");
+        for value in ["a\nb", "a\\b", "\\n", "x=y\\\n\n"] {
+            let escaped = value.replace('\\', "\\\\").replace('\n', "\\n");
+            assert!(!escaped.contains('\n'));
+            assert_eq!(unescape(&escaped), value);
+        }
+    }
+
+    #[test]
+    fn reply_roundtrip() {
+        for result in [
+            Ok(Some("/tmp/a.png\n/tmp/b.png".to_string())),
+            Ok(None),
+            Err("no window matches\nfoo".to_string()),
+        ] {
+            assert_eq!(decode_reply(&encode_reply(&result)), result);
+        }
+        assert!(decode_reply("").is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//! INFO: udmabuf backed dmabuf allocation for screencopy
+//! The buffer lives in a sealed memfd, exported as a dmabuf via `/dev/udmabuf`, so no GPU is needed
+//! and the copied frame can be read back through a plain mmap of the memfd
+use memmap2::{Mmap, MmapOptions};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use wayland_client::protocol::{wl_buffer::WlBuffer, wl_shm::Format};
+
+/// `_IOW('u', 0x42, struct udmabuf_create)`
+const UDMABUF_CREATE: libc::c_ulong = 0x4018_7542;
+/// `_IOW('b', 0, struct dma_buf_sync)`
+const DMA_BUF_IOCTL_SYNC: libc::c_ulong = 0x4008_6200;
+const DMA_BUF_SYNC_READ: u64 = 1;
+const DMA_BUF_SYNC_START: u64 = 0;
+const DMA_BUF_SYNC_END: u64 = 4;
+/// udmabuf 只能导出线性布局
+pub const DRM_FORMAT_MOD_LINEAR: u64 = 0;
+
+const DRM_FORMAT_ARGB8888: u32 = fourcc(b"AR24");
+const DRM_FORMAT_XRGB8888: u32 = fourcc(b"XR24");
+
+#[repr(C)]
+struct UdmabufCreate {
+    memfd: u32,
+    flags: u32,
+    offset: u64,
+    size: u64,
+}
+
+#[repr(C)]
+struct DmaBufSync {
+    flags: u64,
+}
+
+const fn fourcc(code: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*code)
+}
+
+/// 将 drm fourcc 转换为 wl_shm 的格式，两者只有 argb8888/xrgb8888 的取值不同
+pub fn fourcc_to_shm(code: u32) -> Option<Format> {
+    match code {
+        DRM_FORMAT_ARGB8888 => Some(Format::Argb8888),
+        DRM_FORMAT_XRGB8888 => Some(Format::Xrgb8888),
+        _ => Format::try_from(code).ok(),
+    }
+}
+
+/// `/dev/udmabuf` 设备
+pub struct UdmabufAllocator {
+    dev: File,
+}
+
+impl UdmabufAllocator {
+    pub fn open() -> io::Result<Self> {
+        let dev = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/udmabuf")?;
+        Ok(Self { dev })
+    }
+
+    /// 分配至少 `size` 字节的 dmabuf
+    pub fn allocate(&self, size: usize) -> io::Result<UdmaBuffer> {
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let size = size.div_ceil(page) * page;
+
+        let name = c"foamshot-udmabuf";
+        let memfd = unsafe {
+            libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING)
+        };
+        if memfd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let memfd = unsafe { OwnedFd::from_raw_fd(memfd) };
+
+        if unsafe { libc::ftruncate(memfd.as_raw_fd(), size as libc::off_t) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // NOTE: udmabuf 要求 memfd 设置 F_SEAL_SHRINK
+        if unsafe { libc::fcntl(memfd.as_raw_fd(), libc::F_ADD_SEALS, libc::F_SEAL_SHRINK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let create = UdmabufCreate {
+            memfd: memfd.as_raw_fd() as u32,
+            flags: 0x01, // UDMABUF_FLAGS_CLOEXEC
+            offset: 0,
+            size: size as u64,
+        };
+        let dmabuf = unsafe {
+            libc::ioctl(
+                self.dev.as_raw_fd(),
+                UDMABUF_CREATE,
+                &create as *const UdmabufCreate,
+            )
+        };
+        if dmabuf < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let dmabuf = unsafe { OwnedFd::from_raw_fd(dmabuf) };
+
+        let map = unsafe {
+            MmapOptions::new()
+                .len(size)
+                .map(&File::from(memfd.try_clone()?))?
+        };
+
+        Ok(UdmaBuffer { memfd, dmabuf, map })
+    }
+}
+
+/// 一块 udmabuf 内存，`dmabuf` 交给合成器写入，`map` 用于读回
+pub struct UdmaBuffer {
+    #[allow(unused)]
+    memfd: OwnedFd,
+    pub dmabuf: OwnedFd,
+    map: Mmap,
+}
+
+impl UdmaBuffer {
+    /// 读取内容，前后通过 DMA_BUF_IOCTL_SYNC 保证 cpu 读到的是合成器写入后的数据
+    pub fn read(&self, len: usize) -> Vec<u8> {
+        self.sync(DMA_BUF_SYNC_START | DMA_BUF_SYNC_READ);
+        let data = self.map[..len.min(self.map.len())].to_vec();
+        self.sync(DMA_BUF_SYNC_END | DMA_BUF_SYNC_READ);
+        data
+    }
+
+    fn sync(&self, flags: u64) {
+        let sync = DmaBufSync { flags };
+        unsafe {
+            libc::ioctl(
+                self.dmabuf.as_raw_fd(),
+                DMA_BUF_IOCTL_SYNC,
+                &sync as *const DmaBufSync,
+            )
+        };
+    }
+}
+
+/// 用于 copy 的 dmabuf wl_buffer 及其内存
+pub struct DmabufBuffer {
+    pub buffer: WlBuffer,
+    pub mem: UdmaBuffer,
+    pub width: i32,
+    pub height: i32,
+    pub stride: i32,
+    pub format: Format,
+}
+
+impl DmabufBuffer {
+    pub fn canvas(&self) -> Vec<u8> {
+        self.mem.read(self.stride as usize * self.height as usize)
+    }
+}
+
+impl Drop for DmabufBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+    }
+}
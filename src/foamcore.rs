@@ -1,15 +1,21 @@
 //! The core of the program defines the `FoamShot` structure
 //! connects to the wayland display and executes event_loop
 //! provides the `run_main_loop` function
-use log::{debug, error};
+use log::{debug, error, warn};
 use smithay_client_toolkit::shm::Shm;
+use std::time::{Duration, Instant};
 use wayland_client::{Connection, EventQueue, globals::registry_queue_init};
 
 use crate::{
-    action::{self, Action, IsFreeze},
-    config::{FoamConfig, ImageType},
+    action::{self, Action, EditAction, IsFreeze},
+    config::{CountdownStyle, FoamConfig},
+    daemon,
+    foreign_toplevel::{ForeignToplevel, WindowPicker},
+    last_region::LastRegion,
     notify::{self, NotificationLevel},
-    save_helper, wayland_ctx,
+    portal, protocols, save_helper,
+    select_rect::SelectRect,
+    wayland_ctx,
 };
 
 #[derive(PartialEq, Eq)]
@@ -17,7 +23,7 @@ use crate::{
 /// INFO: Define the output targets to fs, divided into screenshots and recordings
 pub enum UserTarget {
     Shot = 0,
-    Recorder = 1, // TODO:
+    Recorder = 1,
 }
 
 pub struct FoamShot {
@@ -28,105 +34,48 @@ pub struct FoamShot {
     pub target: UserTarget,
 }
 
+/// 一次截图会话的结果，Ok 为需要输出到 stdout 的内容（保存路径或选区），取消时为 None
+pub type SessionResult = Result<Option<String>, String>;
+
 /// run
 pub fn run_main_loop() {
-    let connection = Connection::connect_to_env().expect("can't connect to wayland display");
-    let (globals, mut event_queue) =
-        registry_queue_init::<FoamShot>(&connection).expect("failed to get globals");
-    let qh = event_queue.handle();
-    let display = connection.display();
-    let _registry = display.get_registry(&qh, ());
-
-    let shm = Shm::bind(&globals, &qh).expect("wl_shm is not available");
-    let mut shot_foam = FoamShot::new(shm, qh);
-    debug!("{:?}", shot_foam.wlctx.config);
-
-    event_queue.roundtrip(&mut shot_foam).expect("init failed");
-
-    shot_foam.wait_copy(&mut event_queue);
-
-    // NOTE: 创建layer && surface提交
-    shot_foam.wlctx.init_base_layers();
-
-    // NOTE: 等待处理事件
-    event_queue.blocking_dispatch(&mut shot_foam).unwrap();
-
-    loop {
-        event_queue.blocking_dispatch(&mut shot_foam).unwrap();
-        match &shot_foam.action {
-            Action::Init => {}
-            Action::WaitPointerPress => {}
-            Action::ToggleFreeze(state) => {
-                match state {
-                    IsFreeze::NewFrameFreeze => {
-                        debug!("next is freeze");
-                        // 进行屏幕copy 通过计数器等待所有ready完成
-                        shot_foam.wait_copy(&mut event_queue);
-                    }
-                    IsFreeze::UnFreeze | IsFreeze::OldFrameFreeze => {
-                        debug!("next is unfreeze");
-                    }
-                }
-                // 发送下一帧，重新附加buffer
-                shot_foam.toggle_freeze(&mut event_queue);
-                shot_foam.action = Action::WaitPointerPress
-            }
-            Action::OnDraw => {}
-            Action::OnEdit(_a) => {}
-            Action::Output => {
-                if !shot_foam.wlctx.current_freeze {
-                    shot_foam.wait_copy(&mut event_queue);
-                }
+    let config = FoamConfig::new();
+    debug!("{:?}", config);
 
-                // 提前Drop掉layer surface，视觉观感更好
-                shot_foam
-                    .wlctx
-                    .foam_outputs
-                    .as_mut()
-                    .unwrap()
-                    .iter_mut()
-                    .for_each(|m| {
-                        m.layer_surface.as_mut().unwrap().destroy();
-                    });
-
-                // 如果当前的屏幕状态没有被冻结，那么输出前需要进行一次copy来获取当前的屏幕数据
-                match shot_foam.wlctx.config.image_type {
-                    ImageType::Png => {
-                        if let Err(e) = save_helper::save_to_png(&mut shot_foam.wlctx) {
-                            shot_foam.send_error("image saved error");
-                            log::error!("save to png error: {}", e);
-                            std::process::exit(0)
-                        }
-                    }
-                    ImageType::Jpg => {
-                        if let Err(e) = save_helper::save_to_jpg(&mut shot_foam.wlctx, 100) {
-                            shot_foam.send_error("image saved error");
-                            log::error!("save to jpg error: {}", e);
-                            std::process::exit(0)
-                        }
-                    }
-                }
-                println!("{}", shot_foam.wlctx.config.output_path.display());
-                save_helper::save_to_wl_clipboard(&mut shot_foam.wlctx).unwrap();
+    if config.daemon {
+        daemon::serve(config);
+        return;
+    }
+    // NOTE: 守护进程在运行时转发给它，否则在本进程中截图
+    let result = match daemon::forward(&config) {
+        Some(result) => result,
+        None => {
+            let (mut event_queue, mut shot_foam) = FoamShot::connect(config);
+            shot_foam.run_session(&mut event_queue)
+        }
+    };
+    finish(result);
+}
 
-                shot_foam.send_save_info();
-                std::process::exit(0)
-                // shot_foam.action = Action::Exit
-            }
-            Action::OnRecorder => {
-                // TODO:
-                // shot_foam.wait_copy(&mut event_queue);
-                shot_foam.wait_copy_2(&mut event_queue);
-            }
-            Action::Exit => std::process::exit(0),
+/// 输出会话结果，失败时以非零状态退出
+pub fn finish(result: SessionResult) {
+    match result {
+        Ok(Some(out)) => println!("{}", out),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1)
         }
     }
 }
 
 impl FoamShot {
     /// 创建新实例
-    pub fn new(shm: Shm, qh: wayland_client::QueueHandle<FoamShot>) -> FoamShot {
-        let config = FoamConfig::new();
+    pub fn new(
+        shm: Shm,
+        qh: wayland_client::QueueHandle<FoamShot>,
+        config: FoamConfig,
+    ) -> FoamShot {
         Self {
             wlctx: wayland_ctx::WaylandCtx::new(shm, qh, config),
             action: Action::default(),
@@ -134,10 +83,199 @@ impl FoamShot {
         }
     }
 
+    /// 连接 wayland display 并绑定 global，完成第一次 roundtrip
+    pub fn connect(config: FoamConfig) -> (EventQueue<FoamShot>, FoamShot) {
+        let connection = Connection::connect_to_env().expect("can't connect to wayland display");
+        let (globals, mut event_queue) =
+            registry_queue_init::<FoamShot>(&connection).expect("failed to get globals");
+        let qh = event_queue.handle();
+        let display = connection.display();
+        let _registry = display.get_registry(&qh, ());
+
+        let shm = Shm::bind(&globals, &qh).expect("wl_shm is not available");
+        let mut shot_foam = FoamShot::new(shm, qh, config);
+
+        event_queue.roundtrip(&mut shot_foam).expect("init failed");
+        (event_queue, shot_foam)
+    }
+
+    /// 守护进程在每次截图后调用，回到等待下一次请求的初始状态
+    pub fn reset_session(&mut self) {
+        self.action = Action::default();
+        self.target = UserTarget::Shot;
+        self.wlctx.reset_session();
+    }
+
+    /// 按配置完成一次截图、录制或选区
+    pub fn run_session(&mut self, event_queue: &mut EventQueue<FoamShot>) -> SessionResult {
+        // NOTE: --delay 在截图前等待，--delay-after-select 时改为在选区确定后等待
+        if !self.wlctx.config.delay_after_select {
+            self.wait_delay(event_queue);
+        }
+
+        // NOTE: 窗口捕获不需要 layer
+        if let Some(pattern) = self.wlctx.config.window.clone() {
+            return self.capture_window(event_queue, &pattern);
+        }
+
+        // NOTE: 指定输出时不需要 layer
+        if let Some(name) = self.wlctx.config.output_name.clone() {
+            return self.capture_named_output(event_queue, &name);
+        }
+
+        // NOTE: 指定区域时同样不需要 layer
+        if let Some(rect) = self.wlctx.config.geometry.clone() {
+            return self.capture_geometry(event_queue, rect);
+        }
+
+        // NOTE: 上次的选区同样不需要 layer
+        if self.wlctx.config.last_region {
+            return self.capture_last_region(event_queue);
+        }
+
+        // NOTE: 捕获所有输出时同样不需要 layer
+        if self.wlctx.config.all_outputs {
+            return self.capture_all_outputs(event_queue);
+        }
+
+        // NOTE: 没有捕获协议或 layer-shell 时（如 GNOME/KDE）通过 xdg-desktop-portal 截图
+        if self.wlctx.scm.backend.is_none() || self.wlctx.layer_shell.is_none() {
+            return self.capture_portal();
+        }
+
+        // NOTE: 只有冻结模式需要在创建 layer 前 copy，其余情况在选区确定后再进行区域 copy
+        if self.wlctx.current_freeze {
+            self.wait_copy(event_queue)?;
+        }
+
+        // NOTE: 创建layer && surface提交
+        self.wlctx.init_base_layers();
+
+        // NOTE: 等待处理事件
+        self.dispatch(event_queue)?;
+
+        // 连续截图时已保存的路径
+        let mut saved = Vec::new();
+        loop {
+            self.dispatch(event_queue)?;
+            match &self.action {
+                Action::Init => {}
+                Action::WaitPointerPress => {}
+                Action::ToggleFreeze(state) => {
+                    match state {
+                        IsFreeze::NewFrameFreeze => {
+                            debug!("next is freeze");
+                            // 进行屏幕copy 通过计数器等待所有ready完成
+                            self.wait_copy(event_queue)?;
+                        }
+                        IsFreeze::UnFreeze | IsFreeze::OldFrameFreeze => {
+                            debug!("next is unfreeze");
+                        }
+                    }
+                    // 发送下一帧，重新附加buffer
+                    self.toggle_freeze(event_queue)?;
+                    self.action = Action::WaitPointerPress
+                }
+                Action::OnDraw => {}
+                Action::OnEdit(_a) => {}
+                Action::Output => {
+                    // --print-geometry 只输出选区，不进行截图
+                    if let Some(format) = self.wlctx.config.print_geometry.clone() {
+                        return self.print_geometry(&format);
+                    }
+                    if self.wlctx.config.delay_after_select {
+                        self.delay_after_select(event_queue);
+                    }
+
+                    // 如果当前的屏幕状态没有被冻结，那么输出前需要进行一次copy来获取当前的屏幕数据
+                    if !self.wlctx.current_freeze {
+                        self.wait_copy_region(event_queue)?;
+                    }
+
+                    // NOTE: 连续截图时保留遮罩，保存后回到等待选区
+                    if self.wlctx.config.multi {
+                        saved.extend(self.save_image()?);
+                        self.next_shot();
+                        continue;
+                    }
+
+                    // 提前Drop掉layer surface，视觉观感更好
+                    self.destroy_layers(event_queue);
+
+                    return self.save_image();
+                }
+                Action::OnRecorder => {
+                    // 循环 copy，直到按下 Esc/q 或达到 --duration
+                    while self.action == Action::OnRecorder {
+                        self.wait_copy_2(event_queue)?;
+                    }
+                    self.wlctx.scm.stop_recorder_session();
+                    if self.action == Action::Output {
+                        return self.save_recorder();
+                    }
+                    return Ok(None);
+                }
+                Action::Exit => return Ok((!saved.is_empty()).then(|| saved.join("\n"))),
+            }
+        }
+    }
+
+    /// 销毁所有输出上的 layer surface，使遮罩立即消失
+    fn destroy_layers(&mut self, event_queue: &mut EventQueue<FoamShot>) {
+        self.wlctx
+            .foam_outputs
+            .as_mut()
+            .unwrap()
+            .values_mut()
+            .for_each(|m| {
+                if let Some(layer) = m.layer_surface.take() {
+                    layer.destroy();
+                }
+            });
+        event_queue.flush().ok();
+    }
+
+    /// 连续截图的一次保存完成后换用新的文件名，清空选区并恢复遮罩
+    fn next_shot(&mut self) {
+        self.wlctx.config.next_output_path();
+        self.wlctx.clear_selection();
+        let outputs: Vec<_> = self
+            .wlctx
+            .foam_outputs
+            .as_ref()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect();
+        for i in outputs {
+            self.wlctx.attach_with_udata(i);
+        }
+        self.action = Action::WaitPointerPress;
+    }
+
+    /// 阻塞分发一批事件，连接出错时结束会话
+    fn dispatch(&mut self, event_queue: &mut EventQueue<FoamShot>) -> Result<(), String> {
+        event_queue
+            .blocking_dispatch(self)
+            .map(|_| ())
+            .map_err(|e| {
+                error!("error in dispatch: {}", e);
+                self.session_error("wayland connection error")
+            })
+    }
+
+    /// 与 `dispatch` 相同，连接出错时返回错误而不是退出，守护进程中的会话不能结束进程
+    fn roundtrip(&mut self, event_queue: &mut EventQueue<FoamShot>) -> Result<(), String> {
+        event_queue.roundtrip(self).map(|_| ()).map_err(|e| {
+            error!("error in roundtrip: {}", e);
+            self.session_error("wayland connection error")
+        })
+    }
+
     /// 临时借用 event_queue 进行copy
     /// 发起copy请求 -> 等待全部 output 完成 -> 重置计数器 -> 缓存 canvas
-    pub fn wait_copy(&mut self, event_queue: &mut EventQueue<FoamShot>) {
-        self.check_ok();
+    pub fn wait_copy(&mut self, event_queue: &mut EventQueue<FoamShot>) -> Result<(), String> {
+        self.check_ok()?;
 
         // NOTE: 先确保屏幕为正常状态
         if self.action == Action::ToggleFreeze(IsFreeze::UnFreeze)
@@ -149,54 +287,544 @@ impl FoamShot {
 
         // NOTE: 请求全屏copy，之后该去protocols::zwlr_screencopy_manager_v1中依次处理event
         self.wlctx.request_screencopy();
+        self.wait_copy_ready(event_queue)
+    }
 
+    /// 与 `wait_copy` 相同，但只对选区所在的输出请求选区部分，用于选区已知且屏幕未冻结时
+    pub fn wait_copy_region(
+        &mut self,
+        event_queue: &mut EventQueue<FoamShot>,
+    ) -> Result<(), String> {
+        self.check_ok()?;
+        self.wlctx.unset_freeze();
+        self.wlctx.request_screencopy_region();
+        self.wait_copy_ready(event_queue)
+    }
+
+    /// 等待本轮请求的所有输出 copy 完成 -> 重置计数器 -> 缓存 canvas
+    fn wait_copy_ready(&mut self, event_queue: &mut EventQueue<FoamShot>) -> Result<(), String> {
         // 等待所有屏幕copy完成
-        while self.wlctx.scm.copy_ready != self.wlctx.foam_outputs.as_ref().unwrap().len() {
-            match event_queue.blocking_dispatch(self) {
-                Ok(_) => {}
-                Err(e) => {
-                    error!("error in wait_freeze: {}", e);
-                    self.send_error("error about wait screencopy");
-                    std::process::exit(0)
-                }
+        while !self.wlctx.scm.all_ready() {
+            if let Err(e) = event_queue.blocking_dispatch(self) {
+                error!("error in wait_freeze: {}", e);
+                return Err(self.session_error("error about wait screencopy"));
             }
         }
         // 重置计数器
-        self.wlctx.scm.copy_ready = 0;
+        self.wlctx.scm.ready.clear();
         // 存储 copy 到的数据
-        self.wlctx.storage_copy_canvas();
+        if let Err(e) = self.wlctx.storage_copy_canvas() {
+            error!("failed to store copied canvas: {}", e);
+            return Err(self.session_error(&e));
+        }
         // self.target = UserTarget::Recorder
+        Ok(())
+    }
+
+    /// 循环录制的单次 copy：对录制中的输出请求一帧，等待 `Ready` 后存入 `FrameQueue`
+    pub fn wait_copy_2(&mut self, event_queue: &mut EventQueue<FoamShot>) -> Result<(), String> {
+        self.check_ok()?;
+
+        let Some(id) = self.wlctx.fq.monitor_id else {
+            error!("no output selected for recorder");
+            self.action = Action::Exit;
+            return Ok(());
+        };
+        // NOTE: 录制中的输出被移除时保存已录制的帧
+        let Some(output) = self
+            .wlctx
+            .foam_outputs
+            .as_ref()
+            .unwrap()
+            .get(&id)
+            .and_then(|m| m.output.clone())
+        else {
+            self.action = Action::Output;
+            return Ok(());
+        };
+
+        self.wlctx.fq.throttle(self.wlctx.config.fps);
+        self.wlctx.fq.is_copy = false;
+        // 录制帧直接存入 FrameQueue，不经过 pending
+        self.wlctx.scm.pending.clear();
+        // NOTE: ext 后端在帧之间复用 session，wlr-screencopy 每帧单独请求
+        let qh = self.wlctx.qh.clone().unwrap();
+        match self.wlctx.scm.recorder_session.clone() {
+            Some(session) => {
+                if let Err(e) = protocols::capture_frame(self, &session, &qh) {
+                    error!("{}", e);
+                    return Err(self.session_error("error about recorder capture"));
+                }
+            }
+            None => {
+                self.wlctx
+                    .scm
+                    .request_copy_one(self.wlctx.config.cursor, &output, &qh, id);
+                self.wlctx.fq.in_flight = true;
+            }
+        }
+
+        // 等待当前帧完成，期间可能收到 Esc/q 改变 action
+        while !self.wlctx.fq.is_copy && self.action == Action::OnRecorder {
+            if let Err(e) = event_queue.blocking_dispatch(self) {
+                error!("error in wait_copy_2: {}", e);
+                return Err(self.session_error("error about wait screencopy"));
+            }
+        }
+        // NOTE: wlr-screencopy 的帧无法像 ext session 那样判断是否过期
+        // 停止录制前等待在途的帧结束，避免它的 Ready/Failed 落到之后的状态或下一次会话
+        while self.wlctx.fq.in_flight {
+            if let Err(e) = event_queue.blocking_dispatch(self) {
+                error!("error in wait_copy_2: {}", e);
+                return Err(self.session_error("error about wait screencopy"));
+            }
+        }
+
+        if let Some(duration) = self.wlctx.config.duration
+            && self.action == Action::OnRecorder
+            && self.wlctx.fq.elapsed() >= duration
+        {
+            debug!("recorder reached duration {:?}", duration);
+            self.action = Action::Output;
+        }
+        // NOTE: 帧保存在内存中，达到上限时停止录制
+        if self.action == Action::OnRecorder && self.wlctx.fq.is_full() {
+            self.send_warn("recording reached the memory limit, stop recording");
+            self.action = Action::Output;
+        }
+        Ok(())
+    }
+
+    /// 选区完成后的下一步，截图模式直接输出，录制模式则开始录制选区所在的输出
+    pub fn finish_select(&mut self) -> Action {
+        if !self.wlctx.config.recorder {
+            return Action::Output;
+        }
+
+        let Some(id) = self
+            .wlctx
+            .foam_outputs
+            .as_ref()
+            .unwrap()
+            .iter()
+            .find_map(|(&id, m)| m.subrect.is_some().then_some(id))
+        else {
+            self.send_warn("no region selected for recorder");
+            return Action::Exit;
+        };
+
+        debug!("start recorder on output {}", id);
+        self.wlctx.current_freeze = false;
+        self.wlctx.set_passthrough();
+        let output = &self.wlctx.foam_outputs.as_ref().unwrap()[&id];
+        let (output_transform, crop) = (output.output_transform(), output.subrect.clone());
+        self.wlctx.fq.start(id, output_transform, crop);
+        self.target = UserTarget::Recorder;
+        Action::OnRecorder
     }
 
-    /// TODO: 循环录制
-    #[allow(unused_variables)]
-    pub fn wait_copy_2(&mut self, event_queue: &mut EventQueue<FoamShot>) {
-        // self.wlctx.fq.is_copy = false;
-        // self.wlctx.scm.request_copy_one(
-        //     true,
-        //     self.wlctx
-        //         .foam_outputs
-        //         .as_ref()
-        //         .unwrap()
-        //         .first()
-        //         .unwrap()
-        //         .output
-        //         .as_ref()
-        //         .unwrap(),
-        //     self.wlctx.qh.as_ref().unwrap(),
-        //     0,
-        // );
-        //
-        // while !self.wlctx.fq.is_copy {
-        //     event_queue.blocking_dispatch(self).unwrap();
-        // }
+    /// 按配置的格式编码输出，之后写入 sidecar、复制到剪贴板并发送通知，返回保存的路径
+    /// 写入标准输出时返回 None
+    pub fn save_image(&mut self) -> SessionResult {
+        // 窗口与 portal 截图没有选区
+        if self.wlctx.single_canvas.is_none() {
+            self.remember_region();
+        }
+        if let Err(e) = save_helper::save_image(&mut self.wlctx) {
+            log::error!("save image error: {}", e);
+            return Err(self.session_error(&format!("image saved error: {}", e)));
+        }
+        // NOTE: 写入标准输出时没有可读取的文件与可输出的路径，剪贴板交给管道的下游
+        if self.wlctx.config.stdout {
+            return Ok(None);
+        }
+        if self.wlctx.config.sidecar {
+            match save_helper::save_sidecar(&self.wlctx) {
+                Ok(path) => debug!("sidecar saved in {}", path.display()),
+                Err(e) => {
+                    log::error!("save sidecar error: {}", e);
+                    self.send_warn("failed to write the json sidecar");
+                }
+            }
+        }
+        if let Err(e) = save_helper::save_to_wl_clipboard(&mut self.wlctx) {
+            log::error!("copy to clipboard error: {}", e);
+            self.send_warn("failed to copy the image to clipboard");
+        }
+
+        self.send_save_info();
+        Ok(Some(self.wlctx.config.output_path.display().to_string()))
+    }
+
+    /// 捕获一个窗口并输出，`pattern` 为空时在 overlay 上列出窗口交互式选择，否则捕获匹配的窗口
+    pub fn capture_window(
+        &mut self,
+        event_queue: &mut EventQueue<FoamShot>,
+        pattern: &str,
+    ) -> SessionResult {
+        if self.wlctx.toplevel_helper.list.is_none() {
+            return Err(self.session_error("this compositor unsupported ext foreign toplevel list"));
+        }
+        // NOTE: 等待 toplevel 及其 title/app_id 事件
+        self.roundtrip(event_queue)?;
+        self.roundtrip(event_queue)?;
+
+        let toplevel = if pattern.is_empty() {
+            match self.pick_window(event_queue)? {
+                Some(toplevel) => toplevel,
+                None => return Ok(None),
+            }
+        } else {
+            let Some(toplevel) = self.wlctx.toplevel_helper.find(pattern).cloned() else {
+                let body = format!(
+                    "no window matches \"{}\", available windows:\n{}",
+                    pattern,
+                    self.wlctx.toplevel_helper.describe()
+                );
+                return Err(self.session_error(&body));
+            };
+            toplevel
+        };
+        debug!("capture window: {} {}", toplevel.app_id, toplevel.title);
+
+        let result = match self.wlctx.scm.backend.as_ref() {
+            Some(backend) => backend.capture_toplevel(
+                self.wlctx.config.cursor,
+                &toplevel.handle,
+                self.wlctx.qh.as_ref().unwrap(),
+            ),
+            None => Err("this compositor unsupported ext image copy capture".to_string()),
+        };
+        if let Err(e) = result {
+            error!("window capture error: {}", e);
+            return Err(self.session_error(&e));
+        }
+
+        // 等待窗口copy完成
+        while self.wlctx.scm.copy_ready != 1 {
+            if self.action == Action::Exit {
+                return Err(self.session_error("window capture failed"));
+            }
+            if let Err(e) = event_queue.blocking_dispatch(self) {
+                error!("error in capture_window: {}", e);
+                return Err(self.session_error("error about wait window capture"));
+            }
+        }
+        self.wlctx.scm.copy_ready = 0;
+        self.wlctx.single_canvas = self.wlctx.scm.take_window_canvas();
+
+        self.save_image()
+    }
+
+    /// 在所有输出上列出窗口，等待鼠标点击或键盘选择，按下 Esc/q 时返回 None
+    fn pick_window(
+        &mut self,
+        event_queue: &mut EventQueue<FoamShot>,
+    ) -> Result<Option<ForeignToplevel>, String> {
+        if self.wlctx.layer_shell.is_none() {
+            return Err(self.session_error(
+                "this compositor unsupported wlr layer shell, pass --window <app_id|title> instead",
+            ));
+        }
+        let windows = self.wlctx.toplevel_helper.alive();
+        if windows.is_empty() {
+            return Err(self.session_error("no window to capture"));
+        }
+        self.wlctx.window_picker = Some(WindowPicker::new(windows));
+        self.wlctx.init_base_layers();
+
+        let picked = loop {
+            self.dispatch(event_queue)?;
+            if self.action == Action::Exit {
+                break None;
+            }
+            let picker = self.wlctx.window_picker.as_ref().unwrap();
+            if let Some(index) = picker.picked {
+                break Some(picker.windows[index].handle.clone());
+            }
+        };
+        self.wlctx.window_picker = None;
+        self.destroy_layers(event_queue);
+
+        let Some(handle) = picked else {
+            return Ok(None);
+        };
+        // NOTE: 选择期间窗口可能已经关闭
+        match self.wlctx.toplevel_helper.still_alive(&handle) {
+            Some(toplevel) => Ok(Some(toplevel.clone())),
+            None => Err(self.session_error("the picked window was closed")),
+        }
+    }
+
+    /// 等待 `--delay`，按 `--countdown` 每秒更新剩余秒数
+    pub fn wait_delay(&mut self, event_queue: &mut EventQueue<FoamShot>) {
+        let Some(delay) = self.wlctx.config.delay else {
+            return;
+        };
+        // NOTE: 还没有 layer 时 overlay 无处绘制，改用通知
+        let style = match self.wlctx.config.countdown {
+            Some(CountdownStyle::Overlay) if !self.wlctx.has_layers() => {
+                Some(CountdownStyle::Notify)
+            }
+            style => style,
+        };
+        debug!("delay {:?} before capture", delay);
+
+        let mut notification = notify::Countdown::default();
+        let end = Instant::now() + delay;
+        loop {
+            let remaining = end.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            let secs = remaining.as_secs_f64().ceil() as u64;
+            match style {
+                Some(CountdownStyle::Overlay) => self.wlctx.countdown_attach(&secs.to_string()),
+                // NOTE: 最后一秒关闭通知，保证截图时通知已经消失
+                Some(CountdownStyle::Notify) if self.wlctx.config.allow_notify => {
+                    if secs > 1 {
+                        notification.update(&format!("capture in {}s", secs));
+                    } else {
+                        notification.close();
+                    }
+                }
+                _ => {}
+            }
+            event_queue.flush().ok();
+            // 等待到显示的秒数变化
+            std::thread::sleep(remaining - Duration::from_secs(secs - 1));
+        }
+        notification.close();
+    }
+
+    /// 选区确定后清空遮罩并让出输入，延迟结束后不冻结地捕获同一区域
+    pub fn delay_after_select(&mut self, event_queue: &mut EventQueue<FoamShot>) {
+        self.wlctx.set_passthrough();
+        self.wlctx.release_keyboard();
+        event_queue.flush().ok();
+        self.wait_delay(event_queue);
+        self.wlctx.current_freeze = false;
+    }
+
+    /// 非交互地捕获指定名称的输出，不创建 layer
+    pub fn capture_named_output(
+        &mut self,
+        event_queue: &mut EventQueue<FoamShot>,
+        name: &str,
+    ) -> SessionResult {
+        self.check_ok()?;
+        self.wait_output_info(event_queue)?;
+
+        let outputs = self.wlctx.foam_outputs.as_ref().unwrap();
+        let Some(id) = outputs
+            .iter()
+            .find_map(|(&id, m)| (m.name == name).then_some(id))
+        else {
+            let names: Vec<_> = outputs.values().map(|m| m.name.as_str()).collect();
+            let body = format!(
+                "no output named \"{}\", available outputs: {}",
+                name,
+                names.join(", ")
+            );
+            return Err(self.session_error(&body));
+        };
+
+        debug!("capture output {} ({})", name, id);
+        self.wlctx.set_one_max(id);
+        self.capture_subrects(event_queue)
+    }
+
+    /// 非交互地捕获全局逻辑坐标下的区域，不创建 layer
+    pub fn capture_geometry(
+        &mut self,
+        event_queue: &mut EventQueue<FoamShot>,
+        rect: SelectRect,
+    ) -> SessionResult {
+        self.check_ok()?;
+        self.wait_output_info(event_queue)?;
+
+        debug!("capture geometry {:?}", rect);
+        if !self.wlctx.process_logical_subrects(rect) {
+            return Err(self.session_error("geometry is outside of all outputs"));
+        }
+        self.capture_subrects(event_queue)
+    }
+
+    /// 非交互地捕获上次的选区，输出布局变化时仍按全局逻辑坐标捕获并给出警告
+    pub fn capture_last_region(&mut self, event_queue: &mut EventQueue<FoamShot>) -> SessionResult {
+        let region = LastRegion::load().map_err(|e| self.session_error(&e))?;
+        self.check_ok()?;
+        self.wait_output_info(event_queue)?;
+
+        let outputs = self.wlctx.foam_outputs.as_ref().unwrap();
+        let unchanged = region.outputs.iter().all(|(name, scale)| {
+            outputs
+                .values()
+                .any(|m| m.name == *name && (m.buffer_scale() - scale).abs() < 0.01)
+        });
+        if !unchanged {
+            self.send_warn("outputs changed since the last region was saved");
+        }
+
+        debug!("capture last region {:?}", region);
+        if !self.wlctx.process_logical_subrects(region.rect) {
+            return Err(self.session_error("last region is outside of all outputs"));
+        }
+        self.capture_subrects(event_queue)
+    }
+
+    /// 交互模式下按 r 载入上次的选区并进入编辑
+    pub fn load_last_region(&mut self) {
+        let region = match LastRegion::load() {
+            Ok(region) => region,
+            Err(e) => {
+                self.send_warn(&e);
+                return;
+            }
+        };
+        if !self.wlctx.process_logical_subrects(region.rect) {
+            self.wlctx.global_rect = None;
+            self.send_warn("last region is outside of all outputs");
+            return;
+        }
+        self.wlctx.process_subrects_and_send();
+        self.action = Action::OnEdit(EditAction::None);
+    }
+
+    /// 记录本次的选区，供 `--last-region` 与 `r` 键使用
+    fn remember_region(&self) {
+        let Some((rect, _)) = self.wlctx.logical_selection() else {
+            return;
+        };
+        let outputs = self
+            .wlctx
+            .foam_outputs
+            .as_ref()
+            .unwrap()
+            .values()
+            .filter(|m| m.subrect.is_some())
+            .map(|m| (m.name.clone(), m.buffer_scale()))
+            .collect();
+        if let Err(e) = (LastRegion { rect, outputs }).save() {
+            warn!("failed to save last region: {}", e);
+        }
+    }
+
+    /// 非交互地捕获所有输出，合成为一张图片
+    pub fn capture_all_outputs(&mut self, event_queue: &mut EventQueue<FoamShot>) -> SessionResult {
+        self.check_ok()?;
+        self.wait_output_info(event_queue)?;
+
+        debug!("capture all outputs");
+        self.wlctx.set_all_max();
+        self.capture_subrects(event_queue)
+    }
+
+    /// NOTE: 等待 xdg_output 给出名称、位置与逻辑尺寸
+    pub fn wait_output_info(
+        &mut self,
+        event_queue: &mut EventQueue<FoamShot>,
+    ) -> Result<(), String> {
+        self.roundtrip(event_queue)?;
+        self.roundtrip(event_queue)
+    }
+
+    /// 对已设置子区域的输出进行区域 copy 并输出
+    fn capture_subrects(&mut self, event_queue: &mut EventQueue<FoamShot>) -> SessionResult {
+        self.wlctx.current_freeze = false;
+        self.wlctx.request_screencopy_region();
+        self.wait_copy_ready(event_queue)?;
+
+        self.save_image()
+    }
+
+    /// 将选区以全局逻辑坐标返回，由调用方输出到 stdout
+    pub fn print_geometry(&mut self, format: &str) -> SessionResult {
+        self.wlctx
+            .foam_outputs
+            .as_mut()
+            .unwrap()
+            .values_mut()
+            .for_each(|m| {
+                if let Some(layer) = m.layer_surface.take() {
+                    layer.destroy();
+                }
+            });
+
+        let outputs = self.wlctx.foam_outputs.as_ref().unwrap();
+        let Some((rect, output)) = self
+            .wlctx
+            .logical_selection()
+            .and_then(|(rect, id)| Some((rect, outputs.get(&id)?)))
+        else {
+            return Err(self.session_error("no region selected"));
+        };
+        self.remember_region();
+        Ok(Some(rect.format_geometry(
+            format,
+            &output.name,
+            output.buffer_scale(),
+        )))
+    }
+
+    /// 通过 org.freedesktop.portal.Screenshot 截图，之后沿用保存、剪贴板与通知的流程
+    pub fn capture_portal(&mut self) -> SessionResult {
+        if self.wlctx.config.recorder {
+            return Err(
+                self.session_error("recorder requires zwlr screencopy or ext image copy capture")
+            );
+        }
+        if self.wlctx.config.print_geometry.is_some() {
+            return Err(self
+                .session_error("print geometry requires wlr layer shell and a capture protocol"));
+        }
+        debug!("fallback to xdg-desktop-portal screenshot");
+
+        // NOTE: --full-screen 时不弹出 portal 的交互界面
+        let interactive = !self.wlctx.config.full_screen;
+        match portal::screenshot(interactive).and_then(|path| portal::load_canvas(&path)) {
+            Ok(canvas) => self.wlctx.single_canvas = Some(canvas),
+            Err(e) => {
+                error!("portal screenshot error: {}", e);
+                return Err(self.session_error(&e));
+            }
+        }
+
+        self.save_image()
+    }
+
+    /// 将录制的帧编码为 gif 输出
+    pub fn save_recorder(&mut self) -> SessionResult {
+        self.wlctx
+            .foam_outputs
+            .as_mut()
+            .unwrap()
+            .values_mut()
+            .for_each(|m| {
+                if let Some(layer) = m.layer_surface.take() {
+                    layer.destroy();
+                }
+            });
+
+        debug!("recorder frames: {}", self.wlctx.fq.f.len());
+        let output_path = self.wlctx.config.output_path.clone();
+        if let Err(e) = self
+            .wlctx
+            .fq
+            .to_gif(&output_path.to_string_lossy(), &self.wlctx.config.gif)
+        {
+            log::error!("save to gif error: {}", e);
+            return Err(self.session_error("recorder saved error"));
+        }
+        self.send_save_info();
+        Ok(Some(output_path.display().to_string()))
     }
 
     /// 上层调用，切换所有输出上的屏幕冻结状态，在调用前需要使用 `wait_freeze` 重新进行屏幕copy
-    pub fn toggle_freeze(&mut self, event_queue: &mut EventQueue<FoamShot>) {
+    pub fn toggle_freeze(&mut self, event_queue: &mut EventQueue<FoamShot>) -> Result<(), String> {
         // 收集 Output ID
         let outputs: Vec<_> = if let Some(foam_outputs) = self.wlctx.foam_outputs.as_mut() {
-            foam_outputs.iter().enumerate().map(|(i, _)| i).collect()
+            foam_outputs.keys().copied().collect()
         } else {
             Vec::new()
         };
@@ -205,16 +833,24 @@ impl FoamShot {
             self.wlctx.attach_with_udata(i);
         }
 
-        event_queue.blocking_dispatch(self).unwrap();
+        self.dispatch(event_queue)
     }
 
-    /// if current compositor unsupported zwl screencopy, foamshot will be exit
-    pub fn check_ok(&self) {
-        // check screencopy manager exists
-        if self.wlctx.scm.manager.is_none() {
-            self.send_error("this compositor unsupported zwl screencopy, foamshot will be exit");
-            std::process::exit(1);
+    /// if current compositor unsupported any capture backend, the session fails
+    pub fn check_ok(&self) -> Result<(), String> {
+        // check capture backend exists
+        if self.wlctx.scm.backend.is_none() {
+            return Err(self.session_error(
+                "this compositor unsupported zwlr screencopy or ext image copy capture",
+            ));
         }
+        Ok(())
+    }
+
+    /// 发送错误通知，并返回同样的内容作为会话的错误
+    pub fn session_error(&self, body: &str) -> String {
+        self.send_error(body);
+        body.to_string()
     }
 
     pub fn send_save_info(&self) {
@@ -0,0 +1,231 @@
+//! INFO: Defines the toplevel windows listed by `ext-foreign-toplevel-list-v1`, used by window capture
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::{
+    ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1,
+    ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1,
+};
+
+/// NOTE: 一个顶层窗口的信息，由 handle 的 event 填充
+#[derive(Debug, Clone)]
+pub struct ForeignToplevel {
+    pub handle: ExtForeignToplevelHandleV1,
+    pub title: String,
+    pub app_id: String,
+    pub identifier: String,
+    /// 收到 closed 后不再可用
+    pub closed: bool,
+}
+
+#[derive(Default)]
+pub struct ToplevelHelper {
+    /// u32 is wl_registry name
+    pub list: Option<(ExtForeignToplevelListV1, u32)>,
+    pub toplevels: Vec<ForeignToplevel>,
+}
+
+impl ToplevelHelper {
+    pub fn get_mut(&mut self, handle: &ExtForeignToplevelHandleV1) -> Option<&mut ForeignToplevel> {
+        self.toplevels.iter_mut().find(|t| t.handle == *handle)
+    }
+
+    /// 按 app_id 精确匹配，找不到时按标题包含匹配，均忽略大小写
+    pub fn find(&self, pattern: &str) -> Option<&ForeignToplevel> {
+        let pattern = pattern.to_lowercase();
+        let alive = || self.toplevels.iter().filter(|t| !t.closed);
+        alive()
+            .find(|t| t.app_id.to_lowercase() == pattern)
+            .or_else(|| alive().find(|t| t.title.to_lowercase().contains(&pattern)))
+    }
+
+    /// 所有未关闭的窗口，用于交互式选择
+    pub fn alive(&self) -> Vec<ForeignToplevel> {
+        self.toplevels
+            .iter()
+            .filter(|t| !t.closed)
+            .cloned()
+            .collect()
+    }
+
+    /// 选择期间窗口可能已关闭，返回仍然可用的同一个窗口
+    pub fn still_alive(&self, handle: &ExtForeignToplevelHandleV1) -> Option<&ForeignToplevel> {
+        self.toplevels
+            .iter()
+            .find(|t| t.handle == *handle && !t.closed)
+    }
+
+    /// 列出所有窗口，用于匹配失败时提示
+    pub fn describe(&self) -> String {
+        self.toplevels
+            .iter()
+            .filter(|t| !t.closed)
+            .map(|t| format!("  {}: {}", t.app_id, t.title))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// 列表每行的逻辑高度
+pub const PICKER_ROW_HEIGHT: f64 = 36.0;
+/// 列表的内边距
+pub const PICKER_PADDING: f64 = 12.0;
+/// 列表的最大逻辑宽度
+const PICKER_MAX_WIDTH: f64 = 960.0;
+
+/// NOTE: `--window` 不带参数时的交互式选择，窗口列表绘制在每个输出的 overlay 上
+/// 方向键移动选中行，Enter 或数字键确定，也可以直接点击某一行
+pub struct WindowPicker {
+    pub windows: Vec<ForeignToplevel>,
+    /// 键盘选中的行，决定列表的滚动位置
+    pub selected: usize,
+    /// 鼠标悬停的行，不影响滚动，避免列表在鼠标下移动
+    pub hovered: Option<usize>,
+    /// 确定选择的窗口下标
+    pub picked: Option<usize>,
+}
+
+impl WindowPicker {
+    pub fn new(windows: Vec<ForeignToplevel>) -> Self {
+        Self {
+            windows,
+            selected: 0,
+            hovered: None,
+            picked: None,
+        }
+    }
+
+    /// 每行显示的文字，前 9 行带有对应的数字键
+    pub fn labels(&self) -> Vec<String> {
+        self.windows
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let name = if t.title.is_empty() {
+                    &t.app_id
+                } else {
+                    &t.title
+                };
+                match i {
+                    0..9 => format!("{}  {}  ({})", i + 1, name, t.app_id),
+                    _ => format!("   {}  ({})", name, t.app_id),
+                }
+            })
+            .collect()
+    }
+
+    /// 高亮的行，鼠标悬停优先
+    pub fn highlighted(&self) -> usize {
+        self.hovered.unwrap_or(self.selected)
+    }
+
+    /// 上下移动选中行，到达两端时停止，返回是否需要重绘
+    pub fn move_selection(&mut self, delta: isize) -> bool {
+        let last = self.windows.len().saturating_sub(1);
+        let selected = self.selected.saturating_add_signed(delta).min(last);
+        let changed = selected != self.selected || self.hovered.is_some();
+        self.selected = selected;
+        self.hovered = None;
+        changed
+    }
+
+    /// 更新鼠标悬停的行，返回是否需要重绘
+    pub fn hover(&mut self, row: Option<usize>) -> bool {
+        let changed = row != self.hovered;
+        self.hovered = row;
+        changed
+    }
+
+    /// 确定第 `index` 个窗口，超出列表时忽略
+    pub fn pick(&mut self, index: usize) {
+        if index < self.windows.len() {
+            self.picked = Some(index);
+        }
+    }
+
+    /// 在 `logical_size` 的输出上的列表布局
+    pub fn layout(&self, logical_size: (i32, i32)) -> PickerLayout {
+        PickerLayout::new(logical_size, self.windows.len(), self.selected)
+    }
+}
+
+/// 列表在输出上的位置，均为 surface 的逻辑坐标
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PickerLayout {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    /// 第一个可见行的下标，窗口过多时随选中行滚动
+    pub first: usize,
+    /// 可见的行数
+    pub rows: usize,
+}
+
+impl PickerLayout {
+    pub fn new((w, h): (i32, i32), count: usize, selected: usize) -> Self {
+        let (w, h) = (w as f64, h as f64);
+        let fit = ((h - 4.0 * PICKER_PADDING) / PICKER_ROW_HEIGHT).max(1.0) as usize;
+        let rows = count.min(fit);
+        let first = (selected + 1).saturating_sub(rows);
+        let width = (w * 0.6).min(PICKER_MAX_WIDTH);
+        let height = rows as f64 * PICKER_ROW_HEIGHT + 2.0 * PICKER_PADDING;
+        Self {
+            x: (w - width) / 2.0,
+            y: (h - height) / 2.0,
+            width,
+            height,
+            first,
+            rows,
+        }
+    }
+
+    /// 第 `index` 个窗口所在行的顶部，不可见时为 None
+    pub fn row_y(&self, index: usize) -> Option<f64> {
+        (self.first..self.first + self.rows)
+            .contains(&index)
+            .then(|| self.y + PICKER_PADDING + (index - self.first) as f64 * PICKER_ROW_HEIGHT)
+    }
+
+    /// 坐标 (x, y) 所在行对应的窗口下标
+    pub fn row_at(&self, x: f64, y: f64) -> Option<usize> {
+        let top = self.y + PICKER_PADDING;
+        if x < self.x || x >= self.x + self.width || y < top {
+            return None;
+        }
+        let row = ((y - top) / PICKER_ROW_HEIGHT) as usize;
+        (row < self.rows).then_some(self.first + row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_are_centered_and_hit_tested() {
+        let layout = PickerLayout::new((1000, 800), 3, 0);
+        assert_eq!(layout.rows, 3);
+        assert_eq!(layout.width, 600.0);
+        assert_eq!(layout.x, 200.0);
+        let top = layout.y + PICKER_PADDING;
+        assert_eq!(layout.row_at(500.0, top + 1.0), Some(0));
+        assert_eq!(layout.row_at(500.0, top + PICKER_ROW_HEIGHT * 2.5), Some(2));
+        assert_eq!(layout.row_at(500.0, top + PICKER_ROW_HEIGHT * 3.0), None);
+        assert_eq!(layout.row_at(100.0, top + 1.0), None);
+        assert_eq!(layout.row_at(500.0, top - 1.0), None);
+    }
+
+    #[test]
+    fn long_lists_scroll_with_the_selection() {
+        // 200 的高度只能放下 4 行
+        let layout = PickerLayout::new((1000, 200), 10, 7);
+        assert_eq!(layout.rows, 4);
+        assert_eq!(layout.first, 4);
+        assert_eq!(layout.row_y(3), None);
+        assert_eq!(
+            layout.row_y(7),
+            Some(layout.y + PICKER_PADDING + 3.0 * PICKER_ROW_HEIGHT)
+        );
+        let top = layout.y + PICKER_PADDING;
+        assert_eq!(layout.row_at(500.0, top + 1.0), Some(4));
+        assert_eq!(PickerLayout::new((1000, 200), 10, 2).first, 0);
+    }
+}
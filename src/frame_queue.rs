@@ -1,14 +1,26 @@
 #![allow(unused)]
-use image::{ImageBuffer, Rgba, codecs::gif::GifEncoder};
-use log::debug;
+use color_quant::NeuQuant;
+use log::{debug, warn};
 use smithay_client_toolkit::shm::slot::{Buffer, SlotPool};
-use wayland_client::protocol::wl_shm::Format;
+use std::time::{Duration, Instant};
+use wayland_client::protocol::{wl_buffer::WlBuffer, wl_output::Transform, wl_shm::Format};
+
+use crate::dmabuf::DmabufBuffer;
+use crate::frame_transform::FrameTransform;
+use crate::pixel_format;
+use crate::select_rect::SubRect;
+use crate::zwlr_screencopy_mode::CanvasInfo;
 
 #[allow(unused)]
 pub struct FrameData {
+    /// `Ready` 事件给出的时间戳，单位纳秒
     pub time: u64,
     pub canvas: Option<Vec<u8>>,
     pub is_ok: bool,
+    pub width: i32,
+    pub height: i32,
+    pub stride: i32,
+    pub format: Format,
 }
 
 #[derive(Default)]
@@ -16,7 +28,29 @@ pub struct FrameQueue {
     pub f: Vec<FrameData>,
     pub pool: Option<SlotPool>,
     pub current_buffer: Option<Buffer>,
+    /// 当前 buffer 的 (width, height, stride, format)
+    pub current_info: Option<(i32, i32, i32, Format)>,
     pub is_copy: bool,
+    /// 正在录制的输出索引
+    pub monitor_id: Option<usize>,
+    /// 录制开始的时间
+    pub started_at: Option<Instant>,
+    /// dmabuf 模式下的 buffer，尺寸不变时在帧之间复用
+    pub current_dmabuf: Option<DmabufBuffer>,
+    /// 当前帧是否 copy 到 `current_dmabuf`
+    pub dmabuf_active: bool,
+    /// 当前帧的 buffer 变换
+    pub transform: FrameTransform,
+    /// 录制中输出的 transform
+    pub output_transform: Option<Transform>,
+    /// 选区在输出上的子矩形，帧到达时即裁剪，None 时保留整帧
+    pub crop: Option<SubRect>,
+    /// 已录制帧占用的字节数
+    pub bytes: usize,
+    /// 上次请求帧的时间，用于限制帧率
+    pub requested_at: Option<Instant>,
+    /// wlr-screencopy 已请求但尚未收到 `Ready`/`Failed` 的帧
+    pub in_flight: bool,
 }
 
 impl FrameQueue {
@@ -25,39 +59,163 @@ impl FrameQueue {
             f: Vec::new(), // 初始化空队列
             pool,          // 传入的SlotPool
             current_buffer: None,
+            current_info: None,
             is_copy: false,
+            monitor_id: None,
+            started_at: None,
+            current_dmabuf: None,
+            dmabuf_active: false,
+            transform: FrameTransform::default(),
+            output_transform: None,
+            crop: None,
+            bytes: 0,
+            requested_at: None,
+            in_flight: false,
         }
     }
 
-    pub fn new_buffer(&mut self, w: i32, h: i32, s: i32, f: Format) {
-        if let Some(pool) = self.pool.as_mut() {
-            // 创建 Buffer 和 Canvas
-            let (buffer, canvas) = pool.create_buffer(w, h, s, f).unwrap();
+    /// 清空已有帧，开始录制指定输出，`crop` 为选区在该输出上的子矩形
+    pub fn start(&mut self, monitor_id: usize, output_transform: Transform, crop: Option<SubRect>) {
+        self.f.clear();
+        self.output_transform = Some(output_transform);
+        self.current_buffer = None;
+        self.current_info = None;
+        self.monitor_id = Some(monitor_id);
+        self.started_at = Some(Instant::now());
+        self.crop = crop;
+        self.bytes = 0;
+        self.requested_at = None;
+    }
+
+    /// 丢弃上次会话录制的帧，保留 pool
+    pub fn reset(&mut self) {
+        self.f.clear();
+        self.current_buffer = None;
+        self.current_info = None;
+        self.is_copy = false;
+        self.monitor_id = None;
+        self.started_at = None;
+        self.current_dmabuf = None;
+        self.dmabuf_active = false;
+        self.transform = FrameTransform::default();
+        self.output_transform = None;
+        self.crop = None;
+        self.bytes = 0;
+        self.requested_at = None;
+        self.in_flight = false;
+    }
 
-            // 填充 canvas 数据为 0
-            canvas.fill(0);
+    /// 已录制的帧是否达到内存上限
+    pub fn is_full(&self) -> bool {
+        self.bytes >= MAX_RECORDED_BYTES
+    }
 
-            self.current_buffer = Some(buffer);
-        } else {
-            panic!("SlotPool is not initialized");
+    /// 按 `fps` 限制请求帧的频率，距上次请求不足一帧的间隔时等待
+    pub fn throttle(&mut self, fps: u32) {
+        let interval = Duration::from_secs(1) / fps.max(1);
+        if let Some(elapsed) = self.requested_at.map(|t| t.elapsed())
+            && elapsed < interval
+        {
+            std::thread::sleep(interval - elapsed);
         }
+        self.requested_at = Some(Instant::now());
     }
+
+    /// 录制已经持续的时间
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.map(|t| t.elapsed()).unwrap_or_default()
+    }
+
+    /// 创建接收下一帧的 shm buffer，返回其 wl_buffer
+    pub fn new_buffer(&mut self, w: i32, h: i32, s: i32, f: Format) -> Result<WlBuffer, String> {
+        let pool = self.pool.as_mut().ok_or("SlotPool is not initialized")?;
+        // 创建 Buffer 和 Canvas
+        let (buffer, canvas) = pool
+            .create_buffer(w, h, s, f)
+            .map_err(|e| format!("failed to create buffer: {}", e))?;
+
+        // 填充 canvas 数据为 0
+        canvas.fill(0);
+
+        let wl_buffer = buffer.wl_buffer().clone();
+        self.current_buffer = Some(buffer);
+        self.current_info = Some((w, h, s, f));
+        self.dmabuf_active = false;
+        self.transform = FrameTransform::default();
+        Ok(wl_buffer)
+    }
+    /// 使用 dmabuf 接收下一帧，`buffer` 为 None 时复用上一帧的 dmabuf
+    pub fn use_dmabuf(&mut self, buffer: Option<DmabufBuffer>) {
+        if let Some(buffer) = buffer {
+            self.current_dmabuf = Some(buffer);
+        }
+        let d = self.current_dmabuf.as_ref().unwrap();
+        self.current_info = Some((d.width, d.height, d.stride, d.format));
+        self.current_buffer = None;
+        self.dmabuf_active = true;
+        self.transform = FrameTransform::default();
+    }
+
     // 从队列中取出最新的一项
     // pub fn pop_latest(&mut self) -> Option<FrameData> {
     //     // 获取队列的锁
     //     self.f.as_ref().pop() // 移除并返回队列中最后一个元素（最新的一项）
     // }
 
-    pub fn storage_canvas(&mut self, time: u64) {
-        let buffer = self.current_buffer.as_mut().unwrap();
-        let pool = self.pool.as_mut().unwrap();
-        let c = buffer.canvas(pool).unwrap().to_vec();
+    /// 读取当前帧，转为正向的 `Argb8888` 并裁剪到选区后存入队列
+    pub fn storage_canvas(&mut self, time: u64) -> Result<(), String> {
+        let c = if self.dmabuf_active {
+            self.current_dmabuf
+                .as_ref()
+                .ok_or("no dmabuf for the recorded frame")?
+                .canvas()
+        } else {
+            let buffer = self
+                .current_buffer
+                .as_mut()
+                .ok_or("no buffer for the recorded frame")?;
+            let pool = self.pool.as_mut().ok_or("SlotPool is not initialized")?;
+            buffer
+                .canvas(pool)
+                .ok_or("recorded buffer is not in the pool")?
+                .to_vec()
+        };
+        let (width, height, stride, format) = self
+            .current_info
+            .take()
+            .ok_or("recorded frame has no buffer info")?;
+        // 转为正向画面
+        let info = CanvasInfo {
+            width,
+            height,
+            stride,
+            format,
+        };
+        let output_transform = self.output_transform.unwrap_or(Transform::Normal);
+        let (c, info) = self.transform.apply(c, info, output_transform);
+        let (c, info) = pixel_format::to_argb8888(c, info)?;
+        // NOTE: 只保留选区部分，避免录制整个输出占用过多内存
+        let (c, info) = match &self.crop {
+            Some(rect) => crop_canvas(&c, info, rect)?,
+            None => (c, info),
+        };
+        self.bytes += c.len();
+        let CanvasInfo {
+            width,
+            height,
+            stride,
+            format,
+        } = info;
 
         // 创建 FrameData
         let frame_data = FrameData {
             time,
             canvas: Some(c),
             is_ok: true,
+            width,
+            height,
+            stride,
+            format,
         };
         // 插入到队列中
         // let mut queue = self.f;
@@ -65,39 +223,497 @@ impl FrameQueue {
         self.current_buffer = None;
         self.is_copy = true;
         // println!("len: {}", self.f.iter().len())
+        Ok(())
     }
 
+    /// 将录制的帧编码为 gif，帧在到达时已裁剪到选区；帧间延迟由 `Ready` 时间戳计算
     #[allow(clippy::wrong_self_convention)]
-    pub fn to_gif(&mut self, output_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        // 创建输出文件
+    pub fn to_gif(
+        &mut self,
+        output_path: &str,
+        options: &GifOptions,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         let file = std::fs::File::create(output_path)?;
-        let mut encoder = GifEncoder::new_with_speed(file, 30);
-        for (i, f) in self.f.iter_mut().enumerate() {
-            let data = f.canvas.as_mut().unwrap();
-
-            // 转换 XRGB8888 数据到 RGBA8888 格式
-            let converted_data: Vec<u8> = data
-                .chunks_exact(4)
-                .flat_map(|pixel| {
-                    let b = pixel[0];
-                    let g = pixel[1];
-                    let r = pixel[2];
-                    vec![r, g, b, 0] // 转换为 RGBA 格式
-                })
-                .collect();
+        let result = self.write_gif(std::io::BufWriter::new(file), options);
+        // NOTE: 编码失败时不留下不完整的 gif
+        if result.is_err() {
+            std::fs::remove_file(output_path).ok();
+        }
+        result
+    }
+
+    /// 逐帧转换并编码到 `writer`，内存中只保留当前帧的 RGBA
+    pub fn write_gif<W: std::io::Write>(
+        &self,
+        writer: W,
+        options: &GifOptions,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let first = self.f.first().ok_or("no frame recorded")?;
+        let (w, h) = (first.width, first.height);
+        if w <= 0 || h <= 0 || w > u16::MAX as i32 || h > u16::MAX as i32 {
+            return Err(format!("invalid gif size {}x{}", w, h).into());
+        }
+
+        let frames: Vec<&FrameData> = self.f.iter().filter(|f| f.is_ok).collect();
+        let resized = frames
+            .iter()
+            .filter(|f| f.width != w || f.height != h)
+            .count();
+        if resized > 0 {
+            warn!(
+                "{} of {} frames are not {}x{}, they are clipped or padded to fit",
+                resized,
+                frames.len(),
+                w,
+                h
+            );
+        }
+
+        let global_quant = if options.global_palette {
+            Some(train_global_palette(&frames, w, h, options.speed)?)
+        } else {
+            None
+        };
+        let global_palette = global_quant
+            .as_ref()
+            .map(|q| q.color_map_rgb())
+            .unwrap_or_default();
 
-            // 使用转换后的数据创建 ImageBuffer
-            let rgb_buffer: ImageBuffer<Rgba<u8>, Vec<u8>> =
-                ImageBuffer::from_raw(1366, 768, converted_data).unwrap();
+        let mut encoder = gif::Encoder::new(writer, w as u16, h as u16, &global_palette)?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
 
-            // 创建帧并设置延迟
-            let frame = image::Frame::new(rgb_buffer);
+        let mut last_delay = DEFAULT_DELAY;
+        for (i, f) in frames.iter().enumerate() {
+            // 使用下一帧的时间戳计算当前帧的显示时长
+            let delay = match frames.get(i + 1) {
+                Some(next) => {
+                    last_delay = frame_delay(f.time, next.time);
+                    last_delay
+                }
+                None => last_delay,
+            };
+            let mut rgba = frame_to_rgba(f, w, h)?;
+
+            let mut frame = match (&global_quant, options.dither) {
+                (Some(quant), dither) => {
+                    let indices = map_to_palette(quant, &rgba, w as usize, dither);
+                    gif::Frame::from_indexed_pixels(w as u16, h as u16, indices, None)
+                }
+                (None, true) => {
+                    let quant = NeuQuant::new(options.speed, 256, &rgba);
+                    let indices = map_to_palette(&quant, &rgba, w as usize, true);
+                    gif::Frame::from_palette_pixels(
+                        w as u16,
+                        h as u16,
+                        indices,
+                        quant.color_map_rgb(),
+                        None,
+                    )
+                }
+                (None, false) => {
+                    gif::Frame::from_rgba_speed(w as u16, h as u16, &mut rgba, options.speed)
+                }
+            };
+            frame.delay = delay;
 
             // 编码当前帧
-            debug!("encode_frame: {}", i);
-            encoder.encode_frame(frame)?;
+            debug!("encode_frame: {}, delay: {}0ms", i, delay);
+            encoder.write_frame(&frame)?;
         }
 
         Ok(())
     }
 }
+
+/// gif 编码选项
+#[derive(Debug, Clone)]
+pub struct GifOptions {
+    /// 所有帧共用一个调色板，体积更小，也避免帧间颜色跳变
+    pub global_palette: bool,
+    /// 使用 Floyd-Steinberg 抖动
+    pub dither: bool,
+    /// NeuQuant 采样速度，1-30，越小质量越好
+    pub speed: i32,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        Self {
+            global_palette: false,
+            dither: false,
+            speed: 10,
+        }
+    }
+}
+
+/// 只有一帧时的默认延迟，100ms
+const DEFAULT_DELAY: u16 = 10;
+/// 多数播放器会把小于 20ms 的延迟当成 100ms
+const MIN_DELAY: u16 = 2;
+/// 训练全局调色板时最多采样的像素数
+const GLOBAL_SAMPLE_PIXELS: usize = 1 << 20;
+/// 录制帧占用内存的上限，达到后停止录制并保存
+const MAX_RECORDED_BYTES: usize = 2 << 30;
+
+/// 将正向的 `Argb8888` 画布裁剪到选区，超出画布的部分被截去
+fn crop_canvas(
+    data: &[u8],
+    info: CanvasInfo,
+    rect: &SubRect,
+) -> Result<(Vec<u8>, CanvasInfo), String> {
+    let x = rect.relative_min_x.clamp(0, info.width);
+    let y = rect.relative_min_y.clamp(0, info.height);
+    let w = rect.width.min(info.width - x);
+    let h = rect.height.min(info.height - y);
+    if w <= 0 || h <= 0 {
+        return Err(format!(
+            "selection is outside the {}x{} frame",
+            info.width, info.height
+        ));
+    }
+
+    let mut out = Vec::with_capacity(w as usize * h as usize * 4);
+    for row in y..y + h {
+        let start = row as usize * info.stride as usize + x as usize * 4;
+        let line = data
+            .get(start..start + w as usize * 4)
+            .ok_or("frame data is shorter than expected")?;
+        out.extend_from_slice(line);
+    }
+    let info = CanvasInfo {
+        width: w,
+        height: h,
+        stride: w * 4,
+        format: info.format,
+    };
+    Ok((out, info))
+}
+
+/// CLOCK_MONOTONIC 的当前时间，单位纳秒，与 presentation time 使用同一时钟
+pub fn monotonic_nanos() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// 两帧时间戳（纳秒）之差换算为 gif 的延迟，单位为 10ms，超出 u16 时取最大值
+fn frame_delay(time: u64, next: u64) -> u16 {
+    let cs = next.saturating_sub(time) / 10_000_000;
+    (cs.min(u16::MAX as u64) as u16).max(MIN_DELAY)
+}
+
+/// 按帧的格式和 stride 转换为 `w`x`h` 的 RGBA8888，帧在到达时已裁剪到选区
+/// NOTE: 录制中途输出模式或 buffer 类型变化时帧的尺寸可能不同，按左上角对齐，超出的部分截去，不足的部分补黑
+fn frame_to_rgba(f: &FrameData, w: i32, h: i32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let data = f.canvas.as_ref().ok_or("frame without canvas")?;
+    // 内存中的字节顺序，小端下 Argb8888 为 [B, G, R, A]
+    let bgr = match f.format {
+        Format::Argb8888 | Format::Xrgb8888 => true,
+        Format::Abgr8888 | Format::Xbgr8888 => false,
+        other => return Err(format!("unsupported frame format {:?}", other).into()),
+    };
+
+    let copy_w = f.width.clamp(0, w) as usize;
+    // 补齐的部分为不透明黑色
+    let mut rgba = [0, 0, 0, 255].repeat(w as usize * h as usize);
+    for (row, out) in rgba
+        .chunks_exact_mut(w as usize * 4)
+        .take(f.height.clamp(0, h) as usize)
+        .enumerate()
+    {
+        let start = row * f.stride as usize;
+        let line = data
+            .get(start..start + copy_w * 4)
+            .ok_or("frame data is shorter than expected")?;
+        for (pixel, out) in line.chunks_exact(4).zip(out.chunks_exact_mut(4)) {
+            if bgr {
+                out.copy_from_slice(&[pixel[2], pixel[1], pixel[0], 255]);
+            } else {
+                out.copy_from_slice(&[pixel[0], pixel[1], pixel[2], 255]);
+            }
+        }
+    }
+    Ok(rgba)
+}
+
+/// 从所有帧中均匀采样像素训练全局调色板，逐帧转换，不同时保留多帧
+fn train_global_palette(
+    frames: &[&FrameData],
+    w: i32,
+    h: i32,
+    speed: i32,
+) -> Result<NeuQuant, Box<dyn std::error::Error>> {
+    let total = frames.len() * w as usize * h as usize;
+    let step = (total / GLOBAL_SAMPLE_PIXELS).max(1);
+    let mut samples = Vec::with_capacity((total / step + frames.len()) * 4);
+    for f in frames {
+        let rgba = frame_to_rgba(f, w, h)?;
+        samples.extend(rgba.chunks_exact(4).step_by(step).flatten());
+    }
+    Ok(NeuQuant::new(speed, 256, &samples))
+}
+
+/// 将 RGBA 像素映射到调色板索引，可选 Floyd-Steinberg 抖动
+fn map_to_palette(quant: &NeuQuant, rgba: &[u8], width: usize, dither: bool) -> Vec<u8> {
+    if !dither {
+        return rgba
+            .chunks_exact(4)
+            .map(|p| quant.index_of(p) as u8)
+            .collect();
+    }
+
+    let height = rgba.len() / 4 / width;
+    // 误差以 i32 累积
+    let mut buf: Vec<i32> = rgba.iter().map(|&v| v as i32).collect();
+    let mut indices = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) * 4;
+            let pixel = [
+                buf[i].clamp(0, 255) as u8,
+                buf[i + 1].clamp(0, 255) as u8,
+                buf[i + 2].clamp(0, 255) as u8,
+                255,
+            ];
+            let index = quant.index_of(&pixel);
+            let color = quant.lookup(index).unwrap_or(pixel);
+            indices.push(index as u8);
+
+            for c in 0..3 {
+                let err = pixel[c] as i32 - color[c] as i32;
+                let mut spread = |dx: isize, dy: usize, weight: i32| {
+                    let nx = x as isize + dx;
+                    let ny = y + dy;
+                    if nx >= 0 && (nx as usize) < width && ny < height {
+                        buf[(ny * width + nx as usize) * 4 + c] += err * weight / 16;
+                    }
+                };
+                spread(1, 0, 7);
+                spread(-1, 1, 3);
+                spread(0, 1, 5);
+                spread(1, 1, 1);
+            }
+        }
+    }
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    /// `width`x`height` 的 Argb8888 帧，每行带 4 字节填充，像素为 `pixel(x, y)` 给出的 [R, G, B]
+    fn frame(time: u64, width: i32, height: i32, pixel: impl Fn(i32, i32) -> [u8; 3]) -> FrameData {
+        let stride = width * 4 + 4;
+        let mut canvas = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let [r, g, b] = pixel(x, y);
+                canvas.extend_from_slice(&[b, g, r, 255]);
+            }
+            canvas.extend_from_slice(&[0; 4]);
+        }
+        FrameData {
+            time,
+            canvas: Some(canvas),
+            is_ok: true,
+            width,
+            height,
+            stride,
+            format: Format::Argb8888,
+        }
+    }
+
+    fn solid(time: u64, color: [u8; 3]) -> FrameData {
+        frame(time, 4, 4, |_, _| color)
+    }
+
+    struct DecodedFrame {
+        delay: u16,
+        local_palette: bool,
+        pixels: Vec<[u8; 3]>,
+    }
+
+    /// 解码 gif，返回 (宽, 高, 帧)，像素按所用调色板还原为 RGB
+    /// NOTE: gif 编码器总会写入全局调色板（为空时补齐），这里只区分帧是否带局部调色板
+    fn decode(bytes: &[u8]) -> (u16, u16, Vec<DecodedFrame>) {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(bytes).unwrap();
+        let global = decoder.global_palette().map(<[u8]>::to_vec);
+        let (width, height) = (decoder.width(), decoder.height());
+        let mut frames = Vec::new();
+        while let Some(f) = decoder.read_next_frame().unwrap() {
+            let palette = f.palette.as_ref().or(global.as_ref()).unwrap();
+            let pixels = f
+                .buffer
+                .iter()
+                .map(|&i| palette[i as usize * 3..][..3].try_into().unwrap())
+                .collect();
+            frames.push(DecodedFrame {
+                delay: f.delay,
+                local_palette: f.palette.is_some(),
+                pixels,
+            });
+        }
+        (width, height, frames)
+    }
+
+    /// NeuQuant 的颜色有少量偏差
+    fn assert_color(pixels: &[[u8; 3]], color: [u8; 3]) {
+        for p in pixels {
+            assert!(
+                p.iter().zip(color).all(|(&a, b)| a.abs_diff(b) <= 8),
+                "{:?} != {:?}",
+                p,
+                color
+            );
+        }
+    }
+
+    fn encode(f: Vec<FrameData>, options: &GifOptions) -> Vec<u8> {
+        let queue = FrameQueue {
+            f,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        queue.write_gif(&mut out, options).unwrap();
+        out
+    }
+
+    #[test]
+    fn frame_delay_saturates() {
+        assert_eq!(frame_delay(0, 50 * MS), 5);
+        assert_eq!(frame_delay(0, MS), MIN_DELAY);
+        assert_eq!(frame_delay(100 * MS, 0), MIN_DELAY);
+        assert_eq!(frame_delay(0, 1_000_000 * 1000 * MS), u16::MAX);
+        assert_eq!(frame_delay(0, u64::MAX), u16::MAX);
+    }
+
+    #[test]
+    fn frame_to_rgba_skips_stride_padding() {
+        let f = frame(0, 2, 2, |x, y| [10, y as u8, x as u8]);
+        let rgba = frame_to_rgba(&f, 2, 2).unwrap();
+        assert_eq!(
+            rgba,
+            [10, 0, 0, 255, 10, 0, 1, 255, 10, 1, 0, 255, 10, 1, 1, 255]
+        );
+        let short = FrameData {
+            canvas: f.canvas.as_ref().map(|c| c[..12].to_vec()),
+            ..frame(0, 2, 2, |_, _| [0; 3])
+        };
+        assert!(frame_to_rgba(&short, 2, 2).is_err());
+
+        let unsupported = FrameData {
+            format: Format::Rgb565,
+            ..frame(0, 1, 1, |_, _| [0; 3])
+        };
+        assert!(frame_to_rgba(&unsupported, 1, 1).is_err());
+    }
+
+    #[test]
+    fn gif_uses_timestamps_for_delay() {
+        let frames = vec![
+            solid(0, [255, 0, 0]),
+            solid(50 * MS, [255, 0, 0]),
+            solid(50 * MS + 1_000_000 * 1000 * MS, [255, 0, 0]),
+        ];
+        let (_, _, decoded) = decode(&encode(frames, &GifOptions::default()));
+        let delays: Vec<u16> = decoded.iter().map(|f| f.delay).collect();
+        assert_eq!(delays, [5, u16::MAX, u16::MAX]);
+
+        let (_, _, single) = decode(&encode(vec![solid(0, [255, 0, 0])], &GifOptions::default()));
+        assert_eq!(single[0].delay, DEFAULT_DELAY);
+    }
+
+    #[test]
+    fn frames_are_cropped_to_selection() {
+        // 左半为红，右半为蓝，选区超出右下角，只剩 2x1
+        let f = frame(
+            0,
+            4,
+            4,
+            |x, _| if x < 2 { [255, 0, 0] } else { [0, 0, 255] },
+        );
+        let info = CanvasInfo {
+            width: f.width,
+            height: f.height,
+            stride: f.stride,
+            format: f.format,
+        };
+        let canvas = f.canvas.unwrap();
+        let (cropped, cropped_info) =
+            crop_canvas(&canvas, info, &SubRect::new(0, 2, 3, 4, 4)).unwrap();
+        assert_eq!(
+            (cropped_info.width, cropped_info.height, cropped_info.stride),
+            (2, 1, 8)
+        );
+        assert_eq!(cropped, [255, 0, 0, 255, 255, 0, 0, 255]);
+
+        assert!(crop_canvas(&canvas, info, &SubRect::new(0, 4, 0, 2, 2)).is_err());
+        assert!(crop_canvas(&canvas[..20], info, &SubRect::new(0, 0, 1, 2, 2)).is_err());
+    }
+
+    #[test]
+    fn frames_of_other_sizes_are_clipped_or_padded() {
+        let frames = vec![
+            solid(0, [255, 0, 0]),
+            frame(20 * MS, 2, 2, |_, _| [0, 0, 255]),
+            frame(40 * MS, 6, 6, |_, _| [0, 255, 0]),
+        ];
+        let (width, height, decoded) = decode(&encode(frames, &GifOptions::default()));
+        assert_eq!((width, height, decoded.len()), (4, 4, 3));
+        assert_color(&decoded[0].pixels, [255, 0, 0]);
+        for (i, p) in decoded[1].pixels.iter().enumerate() {
+            let inside = i % 4 < 2 && i / 4 < 2;
+            assert_color(&[*p], if inside { [0, 0, 255] } else { [0, 0, 0] });
+        }
+        assert_color(&decoded[2].pixels, [0, 255, 0]);
+    }
+
+    #[test]
+    fn recording_stops_at_memory_limit() {
+        let mut queue = FrameQueue::default();
+        assert!(!queue.is_full());
+        queue.bytes = MAX_RECORDED_BYTES;
+        assert!(queue.is_full());
+        queue.start(0, Transform::Normal, None);
+        assert!(!queue.is_full());
+    }
+
+    #[test]
+    fn global_palette_is_shared_by_frames() {
+        let options = GifOptions {
+            global_palette: true,
+            ..Default::default()
+        };
+        for dither in [false, true] {
+            let options = GifOptions { dither, ..options };
+            let frames = vec![solid(0, [255, 0, 0]), solid(20 * MS, [0, 0, 255])];
+            let (_, _, decoded) = decode(&encode(frames, &options));
+            assert!(decoded.iter().all(|f| !f.local_palette));
+            assert_color(&decoded[0].pixels, [255, 0, 0]);
+            assert_color(&decoded[1].pixels, [0, 0, 255]);
+        }
+    }
+
+    #[test]
+    fn local_palette_per_frame() {
+        for dither in [false, true] {
+            let options = GifOptions {
+                dither,
+                ..Default::default()
+            };
+            let frames = vec![solid(0, [255, 0, 0]), solid(20 * MS, [0, 0, 255])];
+            let (_, _, decoded) = decode(&encode(frames, &options));
+            assert!(decoded.iter().all(|f| f.local_palette));
+            assert_color(&decoded[0].pixels, [255, 0, 0]);
+            assert_color(&decoded[1].pixels, [0, 0, 255]);
+        }
+    }
+}
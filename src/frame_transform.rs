@@ -0,0 +1,191 @@
+//! INFO: Normalise captured buffers to the upright orientation the user sees
+//! Compositors hand out frames in the output's buffer orientation, optionally y-inverted
+use wayland_client::protocol::wl_output::Transform;
+
+use crate::{pixel_format, zwlr_screencopy_mode::CanvasInfo};
+
+/// NOTE: 合成器对一帧 buffer 施加的变换
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameTransform {
+    /// ext frame 的 transform event 给出，None 时使用输出的 transform
+    pub transform: Option<Transform>,
+    /// wlr frame 的 flags event 给出，buffer 的行自下而上存储
+    pub y_invert: bool,
+}
+
+impl FrameTransform {
+    /// 将 buffer 转为正向画面，`output` 为输出设备当前的 transform
+    pub fn apply(
+        &self,
+        data: Vec<u8>,
+        info: CanvasInfo,
+        output: Transform,
+    ) -> (Vec<u8>, CanvasInfo) {
+        normalize(data, info, self.transform.unwrap_or(output), self.y_invert)
+    }
+}
+
+/// 是否旋转了 90/270 度，此时 buffer 与正向画面宽高互换
+pub fn is_rotated(transform: Transform) -> bool {
+    matches!(
+        transform,
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+    )
+}
+
+/// 按 wl_output.transform 的定义，合成器先水平翻转再逆时针旋转得到 buffer
+/// 这里对每个正向像素反查其在 buffer 中的位置
+pub fn normalize(
+    data: Vec<u8>,
+    info: CanvasInfo,
+    transform: Transform,
+    y_invert: bool,
+) -> (Vec<u8>, CanvasInfo) {
+    // (是否翻转, 逆时针旋转的 90 度次数)
+    let (flipped, quarter) = match transform {
+        Transform::Normal => (false, 0),
+        Transform::_90 => (false, 1),
+        Transform::_180 => (false, 2),
+        Transform::_270 => (false, 3),
+        Transform::Flipped => (true, 0),
+        Transform::Flipped90 => (true, 1),
+        Transform::Flipped180 => (true, 2),
+        Transform::Flipped270 => (true, 3),
+        _ => (false, 0),
+    };
+    let (bw, bh, stride) = (
+        info.width as usize,
+        info.height as usize,
+        info.stride as usize,
+    );
+    let Some(bpp) = pixel_format::bytes_per_pixel(info.format) else {
+        return (data, info);
+    };
+    if (!flipped && quarter == 0 && !y_invert) || stride < bw * bpp || data.len() < stride * bh {
+        return (data, info);
+    }
+
+    let (w, h) = if quarter % 2 == 1 { (bh, bw) } else { (bw, bh) };
+    let mut out = vec![0u8; w * h * bpp];
+    for uy in 0..h {
+        for ux in 0..w {
+            let fx = if flipped { w - 1 - ux } else { ux };
+            let (bx, by) = match quarter {
+                0 => (fx, uy),
+                1 => (uy, w - 1 - fx),
+                2 => (w - 1 - fx, h - 1 - uy),
+                _ => (h - 1 - uy, fx),
+            };
+            let by = if y_invert { bh - 1 - by } else { by };
+            let src = by * stride + bx * bpp;
+            let dst = (uy * w + ux) * bpp;
+            out[dst..dst + bpp].copy_from_slice(&data[src..src + bpp]);
+        }
+    }
+
+    let info = CanvasInfo {
+        width: w as i32,
+        height: h as i32,
+        stride: (w * bpp) as i32,
+        format: info.format,
+    };
+    (out, info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wayland_client::protocol::wl_shm::Format;
+
+    /// 每个字符为一个 Argb8888 像素，每行末尾带 4 字节填充
+    fn buffer(rows: &[&str]) -> (Vec<u8>, CanvasInfo) {
+        let width = rows[0].len();
+        let stride = width * 4 + 4;
+        let mut data = Vec::with_capacity(stride * rows.len());
+        for row in rows {
+            for c in row.bytes() {
+                data.extend_from_slice(&[c, 0, 0, 0]);
+            }
+            data.extend_from_slice(&[0xee; 4]);
+        }
+        let info = CanvasInfo {
+            width: width as i32,
+            height: rows.len() as i32,
+            stride: stride as i32,
+            format: Format::Argb8888,
+        };
+        (data, info)
+    }
+
+    /// 按 stride 读回每行的字符
+    fn rows(data: &[u8], info: &CanvasInfo) -> Vec<String> {
+        (0..info.height as usize)
+            .map(|y| {
+                let row = &data[y * info.stride as usize..][..info.width as usize * 4];
+                row.chunks_exact(4).map(|p| p[0] as char).collect()
+            })
+            .collect()
+    }
+
+    /// 正向画面为 3x2 的 "abc" / "def"，表中为合成器交给我们的 buffer
+    #[test]
+    fn normalizes_every_transform() {
+        let cases: [(Transform, bool, &[&str]); 10] = [
+            (Transform::Normal, false, &["abc", "def"]),
+            (Transform::_90, false, &["cf", "be", "ad"]),
+            (Transform::_180, false, &["fed", "cba"]),
+            (Transform::_270, false, &["da", "eb", "fc"]),
+            (Transform::Flipped, false, &["cba", "fed"]),
+            (Transform::Flipped90, false, &["ad", "be", "cf"]),
+            (Transform::Flipped180, false, &["def", "abc"]),
+            (Transform::Flipped270, false, &["fc", "eb", "da"]),
+            (Transform::Normal, true, &["def", "abc"]),
+            (Transform::_90, true, &["ad", "be", "cf"]),
+        ];
+        for (transform, y_invert, buffer_rows) in cases {
+            let (data, info) = buffer(buffer_rows);
+            let (out, out_info) = normalize(data, info, transform, y_invert);
+            assert_eq!(
+                (out_info.width, out_info.height),
+                (3, 2),
+                "{:?} y_invert={}",
+                transform,
+                y_invert
+            );
+            assert_eq!(
+                rows(&out, &out_info),
+                ["abc", "def"],
+                "{:?} y_invert={}",
+                transform,
+                y_invert
+            );
+        }
+    }
+
+    #[test]
+    fn rotation_swaps_width_and_height() {
+        let (data, info) = buffer(&["cf", "be", "ad"]);
+        assert!(is_rotated(Transform::_90));
+        let (_, out_info) = normalize(data, info, Transform::_90, false);
+        assert_eq!(
+            (out_info.width, out_info.height, out_info.stride),
+            (3, 2, 12)
+        );
+        assert!(!is_rotated(Transform::Flipped180));
+    }
+
+    #[test]
+    fn frame_transform_prefers_its_own_transform() {
+        let (data, info) = buffer(&["fed", "cba"]);
+        let frame = FrameTransform {
+            transform: Some(Transform::_180),
+            y_invert: false,
+        };
+        let (out, out_info) = frame.apply(data.clone(), info, Transform::_90);
+        assert_eq!(rows(&out, &out_info), ["abc", "def"]);
+
+        let frame = FrameTransform::default();
+        let (out, out_info) = frame.apply(data, info, Transform::_180);
+        assert_eq!(rows(&out, &out_info), ["abc", "def"]);
+    }
+}
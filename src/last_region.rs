@@ -0,0 +1,51 @@
+//! INFO: Persists the last selected region in `$XDG_STATE_HOME/foamshot/last-region`,
+//! used by `--last-region` and the `r` key in interactive mode
+use directories::ProjectDirs;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::select_rect::SelectRect;
+
+/// 上次的选区
+#[derive(Debug)]
+pub struct LastRegion {
+    /// 全局逻辑坐标
+    pub rect: SelectRect,
+    /// 与选区相交的输出名称及其缩放
+    pub outputs: Vec<(String, f64)>,
+}
+
+fn state_path() -> Option<PathBuf> {
+    let dirs = ProjectDirs::from("", "", "foamshot")?;
+    Some(dirs.state_dir()?.join("last-region"))
+}
+
+impl LastRegion {
+    /// NOTE: 第一行为 slurp 格式的区域，之后每行为 `缩放 输出名称`
+    pub fn save(&self) -> Result<(), String> {
+        let path = state_path().ok_or("can not find the state directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let mut content = format!("{}\n", self.rect.format_geometry("slurp", "", 1.0));
+        for (name, scale) in &self.outputs {
+            content.push_str(&format!("{} {}\n", scale, name));
+        }
+        fs::write(&path, content).map_err(|e| e.to_string())
+    }
+
+    pub fn load() -> Result<Self, String> {
+        let path = state_path().ok_or("can not find the state directory")?;
+        let content =
+            fs::read_to_string(&path).map_err(|_| "no region was saved yet".to_string())?;
+        let mut lines = content.lines();
+        let rect = SelectRect::from_geometry(lines.next().unwrap_or_default())?;
+        let outputs = lines
+            .filter_map(|line| {
+                let (scale, name) = line.split_once(' ')?;
+                Some((name.to_string(), scale.parse().ok()?))
+            })
+            .collect();
+        Ok(Self { rect, outputs })
+    }
+}
@@ -1,11 +1,19 @@
 pub mod action;
 pub mod cairo_render;
+pub mod capture_backend;
 pub mod config;
+pub mod daemon;
+pub mod dmabuf;
 pub mod foamcore;
+pub mod foreign_toplevel;
 pub mod frame_queue;
+pub mod frame_transform;
+pub mod last_region;
 pub mod monitors;
 pub mod notify;
+pub mod pixel_format;
 pub mod pointer_helper;
+pub mod portal;
 pub mod protocols;
 pub mod save_helper;
 pub mod select_rect;
@@ -4,7 +4,7 @@ use log::debug;
 use smithay_client_toolkit::shm::slot::{self, Buffer, SlotPool};
 use wayland_client::{
     QueueHandle,
-    protocol::{wl_output, wl_shm::Format, wl_surface},
+    protocol::{wl_compositor, wl_output, wl_shm::Format, wl_surface},
 };
 use wayland_protocols::wp::{
     fractional_scale::v1::client::{
@@ -18,18 +18,31 @@ use wayland_protocols_wlr::layer_shell::v1::client::{
     zwlr_layer_surface_v1::{self, Anchor, KeyboardInteractivity},
 };
 
-use crate::{cairo_render::draw_base, foamcore::FoamShot, select_rect::SubRect};
+use crate::{
+    cairo_render::{draw_base, draw_countdown, draw_window_list},
+    foamcore::FoamShot,
+    foreign_toplevel::WindowPicker,
+    frame_transform,
+    select_rect::SubRect,
+};
 
 /// NOTE: 为物理显示器做的抽象，包含其基础信息
 #[derive(Default, Debug)]
 pub struct FoamMonitors {
-    /// 索引，由output event进行赋值
+    /// 索引，为 wl_output 的 registry name，输出热插拔时保持不变
     pub id: usize,
     /// 显示器的命名，也许会有用
     pub name: String,
     pub output: Option<wl_output::WlOutput>,
+    /// 正向画面的物理宽高，已按 transform 交换
     pub width: i32,
     pub height: i32,
+    /// wl_output mode 给出的面板原始宽高
+    pub mode_size: (i32, i32),
+    /// wl_output geometry 给出的变换，None 视为 Normal
+    pub transform: Option<wl_output::Transform>,
+    /// wl_output.scale 给出的整数缩放，0 表示未收到
+    pub output_scale: i32,
     ///显示器 左上角 全局坐标 x
     pub global_x: i32,
     ///显示器 左上角 全局坐标 y
@@ -82,6 +95,57 @@ impl FoamMonitors {
             self.subrect = Some(SubRect::new(self.id, x, y, w, h))
         }
     }
+    pub fn output_transform(&self) -> wl_output::Transform {
+        self.transform.unwrap_or(wl_output::Transform::Normal)
+    }
+
+    /// 根据 mode 与 transform 更新正向画面的宽高，旋转 90/270 度时交换
+    pub fn update_size(&mut self) {
+        let (w, h) = self.mode_size;
+        (self.width, self.height) = if frame_transform::is_rotated(self.output_transform()) {
+            (h, w)
+        } else {
+            (w, h)
+        };
+    }
+
+    /// 逻辑尺寸，缺少 xdg_output 时按物理尺寸处理
+    pub fn logical_size(&self) -> (i32, i32) {
+        if self.logical_width > 0 {
+            (self.logical_width, self.logical_height)
+        } else {
+            (self.width, self.height)
+        }
+    }
+
+    /// 物理像素与逻辑坐标的比例，由 wl_output mode 与 xdg_output 逻辑尺寸得出
+    pub fn buffer_scale(&self) -> f64 {
+        if self.logical_width > 0 {
+            self.width as f64 / self.logical_width as f64
+        } else {
+            1.0
+        }
+    }
+
+    /// 整数 buffer scale，`preferred` 为 0 时由物理与逻辑尺寸估算
+    pub fn integer_scale(&self, preferred: i32) -> u32 {
+        integer_scale((self.width, self.height), self.buffer_scale(), preferred)
+    }
+
+    /// wl_surface.preferred_buffer_scale 变化时更新整数缩放，仅在没有 viewport 时生效
+    pub fn update_buffer_scale(&mut self, factor: i32) {
+        let normal = self.integer_scale(factor);
+        let Some(scale) = self.scale.as_mut() else {
+            return;
+        };
+        if scale.uses_buffer_scale() && scale.update_normal(normal) {
+            self.surface
+                .as_ref()
+                .unwrap()
+                .set_buffer_scale(normal as i32);
+        }
+    }
+
     pub fn max_rect(&mut self) {
         self.new_subrect(0, 0, self.width, self.height);
     }
@@ -93,24 +157,37 @@ impl FoamMonitors {
         &mut self,
         layer_shell: &zwlr_layer_shell_v1::ZwlrLayerShellV1,
         qh: &QueueHandle<FoamShot>,
-        viewporter: wp_viewporter::WpViewporter,
-        fractional_manager: Option<(WpFractionalScaleManagerV1, u32)>,
+        viewporter: Option<&wp_viewporter::WpViewporter>,
+        fractional_manager: Option<&WpFractionalScaleManagerV1>,
     ) {
         let id = self.id;
-        let output = self.output.as_ref().unwrap();
         let (w, h) = (self.width, self.height);
+        let ratio = self.buffer_scale();
+        let normal = self.integer_scale(self.output_scale);
+        let (logical_width, logical_height) = (self.logical_width, self.logical_height);
+        let output = self.output.as_ref().unwrap();
         let surface = self.surface.as_mut().expect("Missing surfaces");
 
-        let viewport = viewporter.get_viewport(surface, qh, id);
-        viewport.set_destination(self.logical_width, self.logical_height);
-
-        if let Some((fm, _)) = fractional_manager {
-            let fractional = fm.get_fractional_scale(surface, qh, id);
-            self.scale = Some(FoamScale::new_fractional(fractional, viewport));
-        } else {
-            // TODO:
-            self.scale = Some(FoamScale::new_normal())
-        }
+        // NOTE: viewport 需要逻辑尺寸，缺少 xdg_output 时同样回退到整数缩放
+        let scale = match viewporter {
+            Some(viewporter) if logical_width > 0 && logical_height > 0 => {
+                let viewport = viewporter.get_viewport(surface, qh, id);
+                viewport.set_destination(logical_width, logical_height);
+                match fractional_manager {
+                    Some(fm) => {
+                        let fractional = fm.get_fractional_scale(surface, qh, id);
+                        FoamScale::new_fractional(fractional, viewport, ratio)
+                    }
+                    None => FoamScale::new_viewport(viewport, ratio),
+                }
+            }
+            _ => {
+                debug!("output {} fallback to integer buffer scale {}", id, normal);
+                surface.set_buffer_scale(normal as i32);
+                FoamScale::new_normal(normal)
+            }
+        };
+        self.scale = Some(scale);
 
         let layer = zwlr_layer_shell_v1::ZwlrLayerShellV1::get_layer_surface(
             layer_shell,
@@ -160,6 +237,19 @@ impl FoamMonitors {
         self.base_buffer = Some(buffer)
     }
 
+    /// 附加空 buffer，并设置空的输入区域，使鼠标事件穿透到下层窗口
+    pub fn passthrough_attach(
+        &mut self,
+        compositor: &wl_compositor::WlCompositor,
+        qh: &QueueHandle<FoamShot>,
+    ) {
+        let surface = self.surface.as_ref().expect("Missing surfaces");
+        let region = compositor.create_region(qh, ());
+        surface.set_input_region(Some(&region));
+        region.destroy();
+        self.clean_attach();
+    }
+
     pub fn no_freeze_attach(&mut self) {
         let (w, h) = (self.width, self.height);
         let surface = self.surface.as_ref().expect("Missing surfaces");
@@ -174,6 +264,52 @@ impl FoamMonitors {
         self.base_buffer = Some(buffer)
     }
 
+    /// 延迟截图时在子矩形中心绘制剩余秒数，没有子矩形时只附加空 buffer
+    pub fn countdown_attach(&mut self, text: &str) {
+        let (w, h) = (self.width, self.height);
+        let surface = self.surface.as_ref().expect("Missing surfaces");
+        let pool = self.pool.as_mut().unwrap();
+        let (buffer, canvas) = pool.create_buffer(w, h, w * 4, Format::Argb8888).unwrap();
+        canvas.fill(0);
+        if let Some(rect) = self.subrect.as_ref() {
+            let center = (
+                rect.relative_min_x as f64 + rect.width as f64 / 2.0,
+                rect.relative_min_y as f64 + rect.height as f64 / 2.0,
+            );
+            draw_countdown(canvas, w, h, center, text);
+        }
+
+        buffer.attach_to(surface).unwrap();
+        surface.damage_buffer(0, 0, w, h);
+        surface.commit();
+        self.base_buffer = Some(buffer)
+    }
+
+    /// 交互式选择窗口时在输出中央绘制窗口列表
+    pub fn picker_attach(&mut self, picker: &WindowPicker) {
+        let (w, h) = (self.width, self.height);
+        let scale = self.buffer_scale();
+        let layout = picker.layout(self.logical_size());
+        let surface = self.surface.as_ref().expect("Missing surfaces");
+        let pool = self.pool.as_mut().unwrap();
+        let (buffer, canvas) = pool.create_buffer(w, h, w * 4, Format::Argb8888).unwrap();
+        canvas.fill(0);
+        draw_window_list(
+            canvas,
+            w,
+            h,
+            scale,
+            &layout,
+            &picker.labels(),
+            picker.highlighted(),
+        );
+
+        buffer.attach_to(surface).unwrap();
+        surface.damage_buffer(0, 0, w, h);
+        surface.commit();
+        self.base_buffer = Some(buffer)
+    }
+
     /// 该方法用于绘制所属输出上的子矩形
     pub fn update_select_subrect(&mut self, base_canvas: &[u8], freeze: bool) {
         let (w, h) = (self.width, self.height);
@@ -290,35 +426,54 @@ impl FoamMonitors {
 
 #[derive(Debug)]
 pub struct FoamScale {
+    /// 整数缩放，来自 wl_output.scale 或 wl_surface.preferred_buffer_scale
     normal: u32,
-    fractional: Option<(u32, WpFractionalScaleV1, WpViewport)>,
+    /// 物理像素与逻辑坐标的比例，fractional scale 尚未给出时使用
+    ratio: f64,
+    fractional: Option<(u32, WpFractionalScaleV1)>,
+    viewport: Option<WpViewport>,
 }
 
 impl FoamScale {
-    fn new_fractional(fractional_client: WpFractionalScaleV1, viewprot: WpViewport) -> Self {
+    fn new_fractional(
+        fractional_client: WpFractionalScaleV1,
+        viewprot: WpViewport,
+        ratio: f64,
+    ) -> Self {
         Self {
             normal: 1,
-            fractional: Some((0, fractional_client, viewprot)),
+            ratio,
+            fractional: Some((0, fractional_client)),
+            viewport: Some(viewprot),
         }
     }
 
-    #[allow(unused)]
-    pub fn get_scale(&self) -> f64 {
-        if let Some(fractional) = self.fractional.as_ref() {
-            let mut scale = fractional.0;
-            if scale == 0 {
-                scale = 120;
-            }
-            scale as f64 / 120.0
-        } else {
-            self.normal as f64
+    /// 只有 viewporter，buffer 为物理尺寸，由 viewport 缩放到逻辑尺寸
+    fn new_viewport(viewprot: WpViewport, ratio: f64) -> Self {
+        Self {
+            normal: 1,
+            ratio,
+            fractional: None,
+            viewport: Some(viewprot),
         }
     }
 
-    fn new_normal() -> Self {
+    /// 没有 viewporter，使用整数 buffer scale
+    fn new_normal(normal: u32) -> Self {
         Self {
-            normal: 1,
+            normal,
+            ratio: normal as f64,
             fractional: None,
+            viewport: None,
+        }
+    }
+
+    /// surface 坐标到物理像素的比例
+    pub fn get_scale(&self) -> f64 {
+        match (self.fractional.as_ref(), self.viewport.as_ref()) {
+            (Some((fraction, _)), _) if *fraction != 0 => *fraction as f64 / 120.0,
+            (_, Some(_)) => self.ratio,
+            _ => self.normal as f64,
         }
     }
 
@@ -327,7 +482,11 @@ impl FoamScale {
         self.fractional.is_some()
     }
 
-    #[allow(unused)]
+    /// 是否通过 wl_surface.set_buffer_scale 处理缩放
+    pub fn uses_buffer_scale(&self) -> bool {
+        self.viewport.is_none()
+    }
+
     pub fn update_normal(&mut self, normal: u32) -> bool {
         let changed = self.normal != normal;
         self.normal = normal;
@@ -343,27 +502,65 @@ impl FoamScale {
             false
         }
     }
+}
 
-    pub fn calculate_pos(&self, pos: (f64, f64)) -> (f64, f64) {
-        if let Some(fractional) = self.fractional.as_ref() {
-            let mut scale = fractional.0;
-            if scale == 0 {
-                scale = 120;
-            }
-            let scale_f64 = scale as f64 / 120.0;
-            (pos.0 * scale_f64, pos.1 * scale_f64)
-        } else {
-            (pos.0 * self.normal as f64, pos.1 * self.normal as f64)
-        }
+/// 整数 buffer scale，`preferred` 为 0 时使用 `ratio` 向下取整
+/// NOTE: 向上取整会让 1.5 倍的输出按 2 倍提交，surface 比输出更小；buffer 尺寸必须能被 scale 整除，否则使用 1
+fn integer_scale(size: (i32, i32), ratio: f64, preferred: i32) -> u32 {
+    let scale = if preferred > 0 {
+        preferred
+    } else {
+        ratio.floor().max(1.0) as i32
+    };
+    if size.0 % scale == 0 && size.1 % scale == 0 {
+        scale as u32
+    } else {
+        1
     }
 }
 
 impl Drop for FoamScale {
     fn drop(&mut self) {
-        #[allow(clippy::option_map_unit_fn)]
-        self.fractional.as_ref().map(|(_, f, v)| {
+        if let Some((_, f)) = self.fractional.as_ref() {
             f.destroy();
+        }
+        if let Some(v) = self.viewport.as_ref() {
             v.destroy();
-        });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_scale_floors_fractional_ratio() {
+        let cases = [
+            ((1920, 1080), 1.0, 0, 1),
+            ((2880, 1800), 1.5, 0, 1),
+            ((3840, 2160), 1.75, 0, 1),
+            ((3840, 2160), 2.0, 0, 2),
+            ((3840, 2160), 2.5, 0, 2),
+            ((1920, 1080), 0.5, 0, 1),
+        ];
+        for (size, ratio, preferred, expected) in cases {
+            assert_eq!(
+                integer_scale(size, ratio, preferred),
+                expected,
+                "{:?} {}",
+                size,
+                ratio
+            );
+        }
+    }
+
+    #[test]
+    fn integer_scale_prefers_compositor_scale() {
+        assert_eq!(integer_scale((3840, 2160), 1.5, 2), 2);
+        assert_eq!(integer_scale((2880, 1800), 2.0, 3), 3);
+        // buffer 尺寸不能整除时退回 1
+        assert_eq!(integer_scale((2881, 1800), 2.0, 0), 1);
+        assert_eq!(integer_scale((2880, 1801), 1.0, 2), 1);
     }
 }
@@ -1,4 +1,5 @@
 //! INFO: Provides a wrapper for Dbus notify to send possible information, relying on `notify-send`
+use log::warn;
 use std::process::Command;
 
 pub enum NotificationLevel {
@@ -43,3 +44,53 @@ pub fn send<T: ToString>(
         .output()
         .ok();
 }
+
+/// 延迟截图的倒计时通知，每次更新替换上一条，截图前需要关闭避免出现在画面中
+#[derive(Default)]
+pub struct Countdown {
+    id: Option<u32>,
+}
+
+impl Countdown {
+    pub fn update(&mut self, body: &str) {
+        let mut cmd = Command::new("notify-send");
+        cmd.arg("--print-id")
+            .arg("--urgency")
+            .arg(NotificationLevel::Info.to_urgency())
+            .arg("--expire-time")
+            .arg("1500")
+            .arg("-a")
+            .arg(env!("CARGO_PKG_NAME"));
+        if let Some(id) = self.id {
+            cmd.arg("--replace-id").arg(id.to_string());
+        }
+        let Ok(output) = cmd.arg("countdown").arg(body).output() else {
+            return;
+        };
+        if let Ok(id) = String::from_utf8_lossy(&output.stdout).trim().parse() {
+            self.id = Some(id);
+        }
+    }
+
+    /// notify-send 不能关闭通知，通过 D-Bus 调用 CloseNotification
+    pub fn close(&mut self) {
+        let Some(id) = self.id.take() else {
+            return;
+        };
+        if let Err(e) = close_notification(id) {
+            warn!("failed to close the countdown notification: {}", e);
+        }
+    }
+}
+
+fn close_notification(id: u32) -> zbus::Result<()> {
+    let conn = zbus::blocking::Connection::session()?;
+    let proxy = zbus::blocking::Proxy::new(
+        &conn,
+        "org.freedesktop.Notifications",
+        "/org/freedesktop/Notifications",
+        "org.freedesktop.Notifications",
+    )?;
+    proxy.call_method("CloseNotification", &(id,))?;
+    Ok(())
+}
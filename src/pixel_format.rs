@@ -0,0 +1,329 @@
+//! INFO: Pixel format conversion for captured buffers
+//! Canvases are converted to tightly packed `Argb8888` for cairo and the overlay,
+//! and can be decoded to 16 bit RGBA to keep the precision of 10 bit formats
+use wayland_client::protocol::wl_shm::Format;
+
+use crate::zwlr_screencopy_mode::CanvasInfo;
+
+/// 单个像素解码为 16 位的 [R, G, B, A]
+type Decoder = fn(&[u8]) -> [u16; 4];
+
+/// 每像素字节数，未知格式返回 None
+pub fn bytes_per_pixel(format: Format) -> Option<usize> {
+    match format {
+        Format::Argb8888
+        | Format::Xrgb8888
+        | Format::Abgr8888
+        | Format::Xbgr8888
+        | Format::Rgba8888
+        | Format::Rgbx8888
+        | Format::Bgra8888
+        | Format::Bgrx8888
+        | Format::Argb2101010
+        | Format::Xrgb2101010
+        | Format::Abgr2101010
+        | Format::Xbgr2101010 => Some(4),
+        Format::Rgb888 | Format::Bgr888 => Some(3),
+        Format::Rgb565 => Some(2),
+        _ => None,
+    }
+}
+
+/// 将 `bits` 位的通道值扩展到 16 位
+fn expand(value: u32, bits: u32) -> u16 {
+    let max = (1u32 << bits) - 1;
+    ((value & max) * 0xffff / max) as u16
+}
+
+fn pixel32(px: &[u8]) -> u32 {
+    u32::from_le_bytes([px[0], px[1], px[2], px[3]])
+}
+
+/// 按 wl_shm 格式的位布局（小端 u32）取出 (R, G, B, A) 的偏移，alpha 为 None 时视为不透明
+fn decode_8888(p: u32, r: u32, g: u32, b: u32, a: Option<u32>) -> [u16; 4] {
+    [
+        expand(p >> r, 8),
+        expand(p >> g, 8),
+        expand(p >> b, 8),
+        a.map_or(0xffff, |a| expand(p >> a, 8)),
+    ]
+}
+
+fn decode_2101010(p: u32, r: u32, b: u32, alpha: bool) -> [u16; 4] {
+    [
+        expand(p >> r, 10),
+        expand(p >> 10, 10),
+        expand(p >> b, 10),
+        if alpha { expand(p >> 30, 2) } else { 0xffff },
+    ]
+}
+
+fn decoder(format: Format) -> Option<Decoder> {
+    let d: Decoder = match format {
+        Format::Argb8888 => |px| decode_8888(pixel32(px), 16, 8, 0, Some(24)),
+        Format::Xrgb8888 => |px| decode_8888(pixel32(px), 16, 8, 0, None),
+        Format::Abgr8888 => |px| decode_8888(pixel32(px), 0, 8, 16, Some(24)),
+        Format::Xbgr8888 => |px| decode_8888(pixel32(px), 0, 8, 16, None),
+        Format::Rgba8888 => |px| decode_8888(pixel32(px), 24, 16, 8, Some(0)),
+        Format::Rgbx8888 => |px| decode_8888(pixel32(px), 24, 16, 8, None),
+        Format::Bgra8888 => |px| decode_8888(pixel32(px), 8, 16, 24, Some(0)),
+        Format::Bgrx8888 => |px| decode_8888(pixel32(px), 8, 16, 24, None),
+        Format::Argb2101010 => |px| decode_2101010(pixel32(px), 20, 0, true),
+        Format::Xrgb2101010 => |px| decode_2101010(pixel32(px), 20, 0, false),
+        Format::Abgr2101010 => |px| decode_2101010(pixel32(px), 0, 20, true),
+        Format::Xbgr2101010 => |px| decode_2101010(pixel32(px), 0, 20, false),
+        // 24 位格式在内存中按小端存储
+        Format::Rgb888 => |px| {
+            [
+                expand(px[2] as u32, 8),
+                expand(px[1] as u32, 8),
+                expand(px[0] as u32, 8),
+                0xffff,
+            ]
+        },
+        Format::Bgr888 => |px| {
+            [
+                expand(px[0] as u32, 8),
+                expand(px[1] as u32, 8),
+                expand(px[2] as u32, 8),
+                0xffff,
+            ]
+        },
+        Format::Rgb565 => |px| {
+            let p = u16::from_le_bytes([px[0], px[1]]) as u32;
+            [expand(p >> 11, 5), expand(p >> 5, 6), expand(p, 5), 0xffff]
+        },
+        _ => return None,
+    };
+    Some(d)
+}
+
+/// 转为紧密排列的 `Argb8888`（小端内存为 [B, G, R, A]），X 格式的 alpha 补为不透明
+/// 未知格式或数据短于 `info` 描述的大小时返回错误
+pub fn to_argb8888(data: Vec<u8>, info: CanvasInfo) -> Result<(Vec<u8>, CanvasInfo), String> {
+    let (w, h, stride) = (
+        info.width as usize,
+        info.height as usize,
+        info.stride as usize,
+    );
+    let packed = CanvasInfo {
+        width: info.width,
+        height: info.height,
+        stride: info.width * 4,
+        format: Format::Argb8888,
+    };
+    let bpp = bytes_per_pixel(info.format)
+        .ok_or_else(|| format!("unsupported shm format {:?}", info.format))?;
+    // NOTE: 最后一行可能不带 stride 的填充，只要求像素部分完整
+    let row = |y: usize| {
+        data.get(y * stride..y * stride + w * bpp)
+            .ok_or("canvas data is shorter than expected")
+    };
+
+    match info.format {
+        Format::Argb8888 if stride == w * 4 && data.len() >= w * h * 4 => {
+            let mut data = data;
+            data.truncate(w * h * 4);
+            return Ok((data, info));
+        }
+        // 最常见的格式，逐字节处理即可
+        Format::Argb8888 | Format::Xrgb8888 => {
+            let opaque = matches!(info.format, Format::Xrgb8888);
+            let mut out = Vec::with_capacity(w * h * 4);
+            for y in 0..h {
+                let start = out.len();
+                out.extend_from_slice(row(y)?);
+                if opaque {
+                    out[start..].chunks_exact_mut(4).for_each(|p| p[3] = 255);
+                }
+            }
+            return Ok((out, packed));
+        }
+        _ => {}
+    }
+
+    let decode =
+        decoder(info.format).ok_or_else(|| format!("unsupported shm format {:?}", info.format))?;
+    let mut out = Vec::with_capacity(w * h * 4);
+    for y in 0..h {
+        for px in row(y)?.chunks_exact(bpp) {
+            let [r, g, b, a] = decode(px);
+            out.extend_from_slice(&[
+                (b >> 8) as u8,
+                (g >> 8) as u8,
+                (r >> 8) as u8,
+                (a >> 8) as u8,
+            ]);
+        }
+    }
+    Ok((out, packed))
+}
+
+/// 将画布中 (x, y, w, h) 的区域解码为 16 位 RGBA，按行写入 `dst`，`dst_stride` 以 u16 为单位
+#[allow(clippy::too_many_arguments)]
+pub fn decode_rgba16(
+    data: &[u8],
+    info: &CanvasInfo,
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+    dst: &mut [u16],
+    dst_stride: usize,
+) -> Result<(), String> {
+    let (decode, bpp) = decoder(info.format)
+        .zip(bytes_per_pixel(info.format))
+        .ok_or_else(|| format!("unsupported shm format {:?}", info.format))?;
+    for row in 0..h {
+        let start = (y + row) * info.stride as usize + x * bpp;
+        let src = data
+            .get(start..start + w * bpp)
+            .ok_or("canvas data is shorter than expected")?;
+        let line = &mut dst[row * dst_stride..][..w * 4];
+        for (px, out) in src.chunks_exact(bpp).zip(line.chunks_exact_mut(4)) {
+            out.copy_from_slice(&decode(px));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(width: i32, height: i32, stride: i32, format: Format) -> CanvasInfo {
+        CanvasInfo {
+            width,
+            height,
+            stride,
+            format,
+        }
+    }
+
+    fn shape(info: CanvasInfo) -> (i32, i32, i32, Format) {
+        (info.width, info.height, info.stride, info.format)
+    }
+
+    fn decode(format: Format, px: &[u8]) -> [u16; 4] {
+        decoder(format).unwrap()(px)
+    }
+
+    #[test]
+    fn decodes_8888_layouts() {
+        // R=0x11 G=0x22 B=0x33 A=0x80，按各格式的位偏移组成小端 u32
+        let pack = |r: u32, g: u32, b: u32, a: u32| -> [u8; 4] {
+            ((0x11u32 << r) | (0x22 << g) | (0x33 << b) | (0x80 << a)).to_le_bytes()
+        };
+        let alpha = [0x1111, 0x2222, 0x3333, 0x8080];
+        let opaque = [0x1111, 0x2222, 0x3333, 0xffff];
+        let cases = [
+            (Format::Argb8888, pack(16, 8, 0, 24), alpha),
+            (Format::Xrgb8888, pack(16, 8, 0, 24), opaque),
+            (Format::Abgr8888, pack(0, 8, 16, 24), alpha),
+            (Format::Xbgr8888, pack(0, 8, 16, 24), opaque),
+            (Format::Rgba8888, pack(24, 16, 8, 0), alpha),
+            (Format::Rgbx8888, pack(24, 16, 8, 0), opaque),
+            (Format::Bgra8888, pack(8, 16, 24, 0), alpha),
+            (Format::Bgrx8888, pack(8, 16, 24, 0), opaque),
+        ];
+        for (format, px, expected) in cases {
+            assert_eq!(decode(format, &px), expected, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn decodes_2101010_layouts() {
+        // R=0x3ff G=0x200 B=0x001 A=0b10
+        let pack = |r: u32, b: u32| -> [u8; 4] {
+            ((2u32 << 30) | (0x3ff << r) | (0x200 << 10) | (1 << b)).to_le_bytes()
+        };
+        let (r, g, b, a) = (0xffff, 32799, 64, 43690);
+        let cases = [
+            (Format::Argb2101010, pack(20, 0), [r, g, b, a]),
+            (Format::Xrgb2101010, pack(20, 0), [r, g, b, 0xffff]),
+            (Format::Abgr2101010, pack(0, 20), [r, g, b, a]),
+            (Format::Xbgr2101010, pack(0, 20), [r, g, b, 0xffff]),
+        ];
+        for (format, px, expected) in cases {
+            assert_eq!(decode(format, &px), expected, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn decodes_888_layouts() {
+        let px = [0x33, 0x22, 0x11];
+        assert_eq!(
+            decode(Format::Rgb888, &px),
+            [0x1111, 0x2222, 0x3333, 0xffff]
+        );
+        assert_eq!(
+            decode(Format::Bgr888, &px),
+            [0x3333, 0x2222, 0x1111, 0xffff]
+        );
+    }
+
+    #[test]
+    fn decodes_565() {
+        // R=16 G=32 B=31
+        let px = ((16u16 << 11) | (32 << 5) | 31).to_le_bytes();
+        assert_eq!(decode(Format::Rgb565, &px), [33824, 33287, 0xffff, 0xffff]);
+    }
+
+    #[test]
+    fn decode_rgba16_reads_sub_rect() {
+        // 3x2 的 Bgr888，每行带 3 字节填充
+        #[rustfmt::skip]
+        let data = [
+            1, 0, 0,  2, 0, 0,  3, 0, 0,  9, 9, 9,
+            4, 0, 0,  5, 0, 0,  6, 0, 0,
+        ];
+        let info = info(3, 2, 12, Format::Bgr888);
+        let mut dst = [0u16; 2 * 2 * 4];
+        decode_rgba16(&data, &info, 1, 0, 2, 2, &mut dst, 8).unwrap();
+        let red: Vec<u16> = dst.chunks(4).map(|p| p[0]).collect();
+        assert_eq!(red, [2 * 257, 3 * 257, 5 * 257, 6 * 257]);
+        assert!(
+            dst.chunks(4)
+                .all(|p| p[1] == 0 && p[2] == 0 && p[3] == 0xffff)
+        );
+
+        let short = &data[..data.len() - 1];
+        assert!(decode_rgba16(short, &info, 1, 0, 2, 2, &mut dst, 8).is_err());
+        let unsupported = CanvasInfo {
+            format: Format::Yuyv,
+            ..info
+        };
+        assert!(decode_rgba16(&data, &unsupported, 0, 0, 1, 1, &mut dst, 8).is_err());
+    }
+
+    #[test]
+    fn to_argb8888_packs_rows_and_fills_alpha() {
+        // 2x2 的 Xrgb8888，stride 为 12，最后一行没有填充
+        #[rustfmt::skip]
+        let data = vec![
+            1, 2, 3, 0,  4, 5, 6, 0,  7, 7, 7, 7,
+            8, 9, 10, 0,  11, 12, 13, 0,
+        ];
+        let (out, packed) = to_argb8888(data, info(2, 2, 12, Format::Xrgb8888)).unwrap();
+        assert_eq!(shape(packed), (2, 2, 8, Format::Argb8888));
+        assert_eq!(
+            out,
+            [1, 2, 3, 255, 4, 5, 6, 255, 8, 9, 10, 255, 11, 12, 13, 255]
+        );
+    }
+
+    #[test]
+    fn to_argb8888_converts_other_formats() {
+        let data = vec![0x33, 0x22, 0x11, 0x66, 0x55, 0x44];
+        let (out, packed) = to_argb8888(data, info(2, 1, 6, Format::Rgb888)).unwrap();
+        assert_eq!(shape(packed), (2, 1, 8, Format::Argb8888));
+        assert_eq!(out, [0x33, 0x22, 0x11, 255, 0x66, 0x55, 0x44, 255]);
+    }
+
+    #[test]
+    fn to_argb8888_rejects_short_and_unknown_data() {
+        assert!(to_argb8888(vec![0; 12], info(2, 2, 8, Format::Argb8888)).is_err());
+        assert!(to_argb8888(vec![0; 11], info(2, 2, 8, Format::Rgb565)).is_err());
+        assert!(to_argb8888(vec![0; 4], info(1, 1, 4, Format::Yuyv)).is_err());
+    }
+}
@@ -30,6 +30,15 @@ pub struct PointerHelper {
 }
 
 impl PointerHelper {
+    /// 清空上次会话的坐标与索引
+    pub fn reset(&mut self) {
+        self.g_current_pos = None;
+        self.g_start_pos = None;
+        self.g_end_pos = None;
+        self.start_index = None;
+        self.end_index = None;
+    }
+
     /// 确保cursor_shape_device存在
     #[inline(always)]
     fn ensure_cursor_device(
@@ -0,0 +1,321 @@
+//! INFO: Fallback screenshot through `org.freedesktop.portal.Screenshot` on the session bus
+//! Used on compositors without wlr-screencopy / ext-image-copy-capture or layer-shell, such as GNOME and KDE
+use futures_lite::{StreamExt, future};
+use log::debug;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use wayland_client::protocol::wl_shm::Format;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value};
+use zbus::{Connection, MatchRule, Message, MessageStream, message};
+
+use crate::zwlr_screencopy_mode::{CanvasInfo, SingleCanvas};
+
+const PORTAL_DEST: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const SCREENSHOT_IFACE: &str = "org.freedesktop.portal.Screenshot";
+const REQUEST_IFACE: &str = "org.freedesktop.portal.Request";
+const REQUEST_PATH_PREFIX: &str = "/org/freedesktop/portal/desktop/request";
+
+/// 等待 portal 响应的最长时间，交互式截图需要给用户留出选择区域的时间
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// 通过 portal 截图，返回截图文件的路径
+/// `interactive` 为 true 时由 portal 让用户选择区域
+pub fn screenshot(interactive: bool) -> Result<PathBuf, String> {
+    zbus::block_on(async {
+        let conn = Connection::session()
+            .await
+            .map_err(|e| format!("failed to connect to the session bus: {}", e))?;
+        request_screenshot(&conn, interactive, RESPONSE_TIMEOUT).await
+    })
+}
+
+async fn request_screenshot(
+    conn: &Connection,
+    interactive: bool,
+    timeout: Duration,
+) -> Result<PathBuf, String> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    let token = format!("foamshot_{}_{}", std::process::id(), nanos);
+    let sender = conn
+        .unique_name()
+        .ok_or("session bus connection has no unique name")?;
+    let request_path = request_path(sender.as_str(), &token);
+
+    // NOTE: 必须在调用之前订阅，否则可能错过 Response
+    let mut responses = subscribe_response(conn, &request_path)
+        .await
+        .map_err(|e| format!("failed to subscribe to the portal response: {}", e))?;
+
+    let mut options: HashMap<&str, Value> = HashMap::new();
+    options.insert("handle_token", token.as_str().into());
+    options.insert("interactive", interactive.into());
+    let reply = conn
+        .call_method(
+            Some(PORTAL_DEST),
+            PORTAL_PATH,
+            Some(SCREENSHOT_IFACE),
+            "Screenshot",
+            &("", options),
+        )
+        .await
+        .map_err(|e| format!("portal screenshot call failed: {}", e))?;
+    let handle: OwnedObjectPath = reply
+        .body()
+        .deserialize()
+        .map_err(|e| format!("malformed portal reply: {}", e))?;
+    debug!("portal request: {}", handle.as_str());
+    if handle.as_str() != request_path {
+        return Err(format!(
+            "unexpected portal request handle {}",
+            handle.as_str()
+        ));
+    }
+
+    let response = async {
+        let message = responses
+            .next()
+            .await
+            .ok_or("session bus closed before the portal responded")?
+            .map_err(|e| e.to_string())?;
+        parse_response(&message)
+    };
+    let expired = async {
+        async_io::Timer::after(timeout).await;
+        Err(format!("portal did not respond within {:?}", timeout))
+    };
+    future::or(response, expired).await
+}
+
+/// portal 按 `sender` 与 `handle_token` 生成的 Request 对象路径
+fn request_path(sender: &str, token: &str) -> String {
+    let sender = sender.trim_start_matches(':').replace('.', "_");
+    format!("{}/{}/{}", REQUEST_PATH_PREFIX, sender, token)
+}
+
+async fn subscribe_response(conn: &Connection, path: &str) -> zbus::Result<MessageStream> {
+    let rule = MatchRule::builder()
+        .msg_type(message::Type::Signal)
+        .interface(REQUEST_IFACE)?
+        .member("Response")?
+        .path(path)?
+        .build();
+    MessageStream::for_match_rule(rule, conn, None).await
+}
+
+/// 解析 Response 信号中的返回码与 uri
+fn parse_response(message: &Message) -> Result<PathBuf, String> {
+    let (code, results): (u32, HashMap<String, OwnedValue>) = message
+        .body()
+        .deserialize()
+        .map_err(|e| format!("malformed portal response: {}", e))?;
+    match code {
+        0 => {}
+        1 => return Err("portal screenshot was cancelled".to_string()),
+        _ => return Err("portal screenshot failed".to_string()),
+    }
+
+    let uri: &str = results
+        .get("uri")
+        .and_then(|v| <&str>::try_from(&**v).ok())
+        .ok_or("portal response has no uri")?;
+    debug!("portal response: {}", uri);
+    let path = uri
+        .strip_prefix("file://")
+        .ok_or_else(|| format!("unsupported uri {}", uri))?;
+    Ok(PathBuf::from(percent_decode(path)))
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(hex) = s.get(i + 1..i + 3)
+            && let Ok(b) = u8::from_str_radix(hex, 16)
+        {
+            out.push(b);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// 读取 portal 返回的图片，转换为 `Argb8888` 画布
+pub fn load_canvas(path: &Path) -> Result<SingleCanvas, String> {
+    let image = image::open(path)
+        .map_err(|e| format!("failed to load {}: {}", path.display(), e))?
+        .into_rgba8();
+    let (width, height) = (image.width() as i32, image.height() as i32);
+    // 内存中 Argb8888 为 [B, G, R, A]
+    let mut data = image.into_raw();
+    data.chunks_exact_mut(4).for_each(|p| p.swap(0, 2));
+    Ok(SingleCanvas {
+        data,
+        info: CanvasInfo {
+            width,
+            height,
+            stride: width * 4,
+            format: Format::Argb8888,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use zbus::message::Header;
+
+    /// 私有的 session bus，drop 时结束 dbus-daemon
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl PrivateBus {
+        /// 没有 dbus-daemon 时返回 None，测试跳过
+        fn start() -> Option<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.as_mut()?)
+                .read_line(&mut address)
+                .ok()?;
+            Some(Self {
+                daemon,
+                address: address.trim().to_string(),
+            })
+        }
+
+        async fn connect(&self) -> Connection {
+            zbus::connection::Builder::address(self.address.as_str())
+                .unwrap()
+                .build()
+                .await
+                .unwrap()
+        }
+
+        async fn serve(&self, portal: StubPortal) -> Connection {
+            zbus::connection::Builder::address(self.address.as_str())
+                .unwrap()
+                .name(PORTAL_DEST)
+                .unwrap()
+                .serve_at(PORTAL_PATH, portal)
+                .unwrap()
+                .build()
+                .await
+                .unwrap()
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            self.daemon.kill().ok();
+            self.daemon.wait().ok();
+        }
+    }
+
+    /// 收到调用后在 Request 路径上发出预设的 Response，为 None 时不响应
+    struct StubPortal {
+        response: Option<(u32, &'static str)>,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.portal.Screenshot")]
+    impl StubPortal {
+        async fn screenshot(
+            &self,
+            #[zbus(header)] header: Header<'_>,
+            #[zbus(connection)] conn: &Connection,
+            _parent_window: &str,
+            options: HashMap<String, OwnedValue>,
+        ) -> zbus::fdo::Result<OwnedObjectPath> {
+            let sender = header.sender().unwrap().to_owned();
+            let token = <&str>::try_from(&*options["handle_token"]).unwrap();
+            let path = request_path(sender.as_str(), token);
+            if let Some((code, uri)) = self.response {
+                let results = HashMap::from([("uri", Value::from(uri))]);
+                conn.emit_signal(
+                    Some(sender),
+                    path.as_str(),
+                    REQUEST_IFACE,
+                    "Response",
+                    &(code, results),
+                )
+                .await?;
+            }
+            Ok(OwnedObjectPath::try_from(path).unwrap())
+        }
+    }
+
+    fn run(
+        response: Option<(u32, &'static str)>,
+        timeout: Duration,
+    ) -> Option<Result<PathBuf, String>> {
+        let Some(bus) = PrivateBus::start() else {
+            eprintln!("dbus-daemon not found, skipping");
+            return None;
+        };
+        Some(zbus::block_on(async {
+            let _portal = bus.serve(StubPortal { response }).await;
+            let conn = bus.connect().await;
+            request_screenshot(&conn, true, timeout).await
+        }))
+    }
+
+    #[test]
+    fn returns_decoded_file_path() {
+        let Some(result) = run(
+            Some((0, "file:///tmp/foam%20shot's%22.png")),
+            Duration::from_secs(5),
+        ) else {
+            return;
+        };
+        assert_eq!(result, Ok(PathBuf::from("/tmp/foam shot's\".png")));
+    }
+
+    #[test]
+    fn reports_cancelled_request() {
+        let Some(result) = run(Some((1, "")), Duration::from_secs(5)) else {
+            return;
+        };
+        assert_eq!(result, Err("portal screenshot was cancelled".to_string()));
+    }
+
+    #[test]
+    fn rejects_non_file_uri() {
+        let Some(result) = run(
+            Some((0, "https://example.com/a.png")),
+            Duration::from_secs(5),
+        ) else {
+            return;
+        };
+        assert!(result.unwrap_err().starts_with("unsupported uri"));
+    }
+
+    #[test]
+    fn times_out_without_response() {
+        let Some(result) = run(None, Duration::from_millis(200)) else {
+            return;
+        };
+        assert!(result.unwrap_err().contains("did not respond"));
+    }
+
+    #[test]
+    fn percent_decode_keeps_invalid_escapes() {
+        assert_eq!(percent_decode("a%2Fb%zz%4"), "a/b%zz%4");
+    }
+}
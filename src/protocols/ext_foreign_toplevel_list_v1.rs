@@ -0,0 +1,71 @@
+//! INFO: ext_foreign_toplevel_list_v1 && ext_foreign_toplevel_handle_v1 interface implementation
+//! Lists the toplevel windows for window capture
+use log::debug;
+use wayland_client::{Dispatch, Proxy, event_created_child};
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::{
+    ext_foreign_toplevel_handle_v1::{self, ExtForeignToplevelHandleV1},
+    ext_foreign_toplevel_list_v1::{self, ExtForeignToplevelListV1},
+};
+
+use crate::{foamcore::FoamShot, foreign_toplevel::ForeignToplevel};
+
+impl Dispatch<ExtForeignToplevelListV1, ()> for FoamShot {
+    fn event(
+        app: &mut Self,
+        _proxy: &ExtForeignToplevelListV1,
+        event: <ExtForeignToplevelListV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qh: &wayland_client::QueueHandle<Self>,
+    ) {
+        match event {
+            ext_foreign_toplevel_list_v1::Event::Toplevel { toplevel } => {
+                app.wlctx.toplevel_helper.toplevels.push(ForeignToplevel {
+                    handle: toplevel,
+                    title: String::new(),
+                    app_id: String::new(),
+                    identifier: String::new(),
+                    closed: false,
+                });
+            }
+            ext_foreign_toplevel_list_v1::Event::Finished => {
+                app.wlctx.toplevel_helper.list = None;
+            }
+            _ => (),
+        }
+    }
+
+    event_created_child!(FoamShot, ExtForeignToplevelListV1, [
+        ext_foreign_toplevel_list_v1::EVT_TOPLEVEL_OPCODE => (ExtForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ExtForeignToplevelHandleV1, ()> for FoamShot {
+    fn event(
+        app: &mut Self,
+        proxy: &ExtForeignToplevelHandleV1,
+        event: <ExtForeignToplevelHandleV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qh: &wayland_client::QueueHandle<Self>,
+    ) {
+        let Some(toplevel) = app.wlctx.toplevel_helper.get_mut(proxy) else {
+            return;
+        };
+        match event {
+            ext_foreign_toplevel_handle_v1::Event::Title { title } => toplevel.title = title,
+            ext_foreign_toplevel_handle_v1::Event::AppId { app_id } => toplevel.app_id = app_id,
+            ext_foreign_toplevel_handle_v1::Event::Identifier { identifier } => {
+                toplevel.identifier = identifier
+            }
+            ext_foreign_toplevel_handle_v1::Event::Done => {
+                debug!("toplevel: {} {}", toplevel.app_id, toplevel.title);
+            }
+            ext_foreign_toplevel_handle_v1::Event::Closed => {
+                toplevel.closed = true;
+                proxy.destroy();
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! INFO: ext_image_copy_capture_manager_v1 && ext_image_copy_capture_session_v1 && ext_image_copy_capture_frame_v1
+//! && ext_output_image_capture_source_manager_v1 interface implementation
+//! Used for screen frame capture on compositors that implement the ext image capture protocols
+use std::sync::Mutex;
+
+use log::*;
+use wayland_client::{Dispatch, Proxy, protocol::wl_shm::Format};
+use wayland_protocols::ext::{
+    image_capture_source::v1::client::{
+        ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1,
+        ext_image_capture_source_v1::ExtImageCaptureSourceV1,
+        ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+    },
+    image_copy_capture::v1::client::{
+        ext_image_copy_capture_frame_v1::{self, ExtImageCopyCaptureFrameV1},
+        ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1,
+        ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1},
+    },
+};
+
+use crate::{
+    action::Action,
+    capture_backend::CaptureTarget,
+    foamcore::{FoamShot, UserTarget},
+    frame_queue, pixel_format,
+    zwlr_screencopy_mode::CanvasInfo,
+};
+
+/// 优先使用的 shm 格式，与 wlr-screencopy 常见格式保持一致
+const PREFERRED_FORMATS: [Format; 2] = [Format::Xrgb8888, Format::Argb8888];
+
+/// NOTE: 一次捕获 session 的状态，session 的 event 会在 Done 之前多次给出约束
+pub struct ExtSessionData {
+    pub target: CaptureTarget,
+    source: ExtImageCaptureSourceV1,
+    /// (width, height, formats, frame 是否已创建)
+    state: Mutex<(u32, u32, Vec<Format>, bool)>,
+}
+
+impl ExtSessionData {
+    pub fn new(target: CaptureTarget, source: ExtImageCaptureSourceV1) -> Self {
+        Self {
+            target,
+            source,
+            state: Mutex::new((0, 0, Vec::new(), false)),
+        }
+    }
+}
+
+/// NOTE: 一帧的状态，`Ready` 后销毁 frame，非录制时一并销毁 session 和 source
+pub struct ExtFrameData {
+    pub target: CaptureTarget,
+    session: ExtImageCopyCaptureSessionV1,
+    /// 是否属于录制复用的 session
+    recorder: bool,
+    /// presentation_time 给出的时间戳，单位纳秒，合成器可能不发送
+    time: Mutex<Option<u64>>,
+}
+
+impl Dispatch<ExtImageCopyCaptureSessionV1, ExtSessionData> for FoamShot {
+    fn event(
+        app: &mut Self,
+        proxy: &ExtImageCopyCaptureSessionV1,
+        event: <ExtImageCopyCaptureSessionV1 as Proxy>::Event,
+        data: &ExtSessionData,
+        _conn: &wayland_client::Connection,
+        qh: &wayland_client::QueueHandle<Self>,
+    ) {
+        match event {
+            ext_image_copy_capture_session_v1::Event::BufferSize { width, height } => {
+                let mut state = data.state.lock().unwrap();
+                state.0 = width;
+                state.1 = height;
+            }
+            ext_image_copy_capture_session_v1::Event::ShmFormat { format } => {
+                let mut state = data.state.lock().unwrap();
+                if let Ok(format) = format.into_result()
+                    && !state.2.contains(&format)
+                {
+                    state.2.push(format);
+                }
+            }
+            // NOTE: 约束变化时会再次给出 Done，之后的帧按新的约束创建 buffer
+            ext_image_copy_capture_session_v1::Event::Done => {
+                {
+                    let mut state = data.state.lock().unwrap();
+                    if state.3 {
+                        return;
+                    }
+                    state.3 = true;
+                }
+                // 录制时复用 session，之后的帧由 `capture_frame` 直接创建
+                if matches!(app.target, UserTarget::Recorder)
+                    && matches!(data.target, CaptureTarget::Output(_))
+                {
+                    app.wlctx.scm.recorder_session = Some(proxy.clone());
+                }
+                if let Err(e) = capture_frame(app, proxy, qh) {
+                    warn!("{}", e);
+                    app.action = Action::Exit;
+                }
+            }
+            ext_image_copy_capture_session_v1::Event::Stopped => {
+                if app.wlctx.scm.recorder_session.as_ref() == Some(proxy) {
+                    app.wlctx.scm.recorder_session = None;
+                    if app.action == Action::OnRecorder {
+                        app.send_warn("capture session was stopped, stop recording");
+                        app.action = Action::Output;
+                    }
+                }
+                proxy.destroy();
+                data.source.destroy();
+            }
+            _ => (),
+        }
+    }
+}
+
+/// 按 session 当前的约束创建 buffer 并捕获一帧
+pub fn capture_frame(
+    app: &mut FoamShot,
+    session: &ExtImageCopyCaptureSessionV1,
+    qh: &wayland_client::QueueHandle<FoamShot>,
+) -> Result<(), String> {
+    let data = session
+        .data::<ExtSessionData>()
+        .ok_or("ext image copy session has no data")?;
+    // NOTE: 输出已被移除，等待 Stopped 销毁 session
+    if let CaptureTarget::Output(index) = data.target
+        && !app.wlctx.has_output(index)
+    {
+        return Ok(());
+    }
+    let (width, height, format) = {
+        let state = data.state.lock().unwrap();
+        let format = PREFERRED_FORMATS
+            .into_iter()
+            .find(|f| state.2.contains(f))
+            .or_else(|| {
+                state
+                    .2
+                    .iter()
+                    .copied()
+                    .find(|f| pixel_format::bytes_per_pixel(*f).is_some())
+            })
+            .ok_or("ext image copy session has no shm format")?;
+        (state.0 as i32, state.1 as i32, format)
+    };
+    let stride = width * pixel_format::bytes_per_pixel(format).unwrap() as i32;
+    trace!(
+        "capture frame => {:?}, {}x{} {:?}",
+        data.target, width, height, format
+    );
+
+    let buffer = match data.target {
+        CaptureTarget::Output(index) => {
+            app.wlctx
+                .create_copy_buffer(index, width, height, stride, format, &app.target)
+        }
+        CaptureTarget::Toplevel => match app.wlctx.shm.as_ref() {
+            Some(shm) => app.wlctx.scm.create_window_buffer(
+                shm,
+                CanvasInfo {
+                    width,
+                    height,
+                    stride,
+                    format,
+                },
+            ),
+            None => Err("wl_shm is not bound".to_string()),
+        },
+    }
+    .map_err(|e| format!("failed to create copy buffer for {:?}: {}", data.target, e))?;
+
+    let frame = session.create_frame(
+        qh,
+        ExtFrameData {
+            target: data.target,
+            session: session.clone(),
+            recorder: app.wlctx.scm.recorder_session.as_ref() == Some(session),
+            time: Mutex::new(None),
+        },
+    );
+    frame.attach_buffer(&buffer);
+    frame.damage_buffer(0, 0, width, height);
+    frame.capture();
+    Ok(())
+}
+
+impl Dispatch<ExtImageCopyCaptureFrameV1, ExtFrameData> for FoamShot {
+    fn event(
+        app: &mut Self,
+        proxy: &ExtImageCopyCaptureFrameV1,
+        event: <ExtImageCopyCaptureFrameV1 as Proxy>::Event,
+        data: &ExtFrameData,
+        _conn: &wayland_client::Connection,
+        _qh: &wayland_client::QueueHandle<Self>,
+    ) {
+        match event {
+            ext_image_copy_capture_frame_v1::Event::PresentationTime {
+                tv_sec_hi,
+                tv_sec_lo,
+                tv_nsec,
+            } => {
+                let seconds: u64 = ((tv_sec_hi as u64) << 32) | (tv_sec_lo as u64);
+                *data.time.lock().unwrap() = Some((seconds * 1_000_000_000) + tv_nsec as u64);
+            }
+            ext_image_copy_capture_frame_v1::Event::Transform { transform } => {
+                let transform = transform.into_result().ok();
+                match (&app.target, data.target) {
+                    (UserTarget::Shot, CaptureTarget::Output(i)) => {
+                        app.wlctx
+                            .scm
+                            .frame_transform
+                            .entry(i)
+                            .or_default()
+                            .transform = transform;
+                    }
+                    (UserTarget::Shot, CaptureTarget::Toplevel) => {
+                        app.wlctx.scm.window_transform.transform = transform;
+                    }
+                    (UserTarget::Recorder, _) => app.wlctx.fq.transform.transform = transform,
+                }
+            }
+            ext_image_copy_capture_frame_v1::Event::Ready => {
+                trace!("frame ready => {:?}", data.target);
+                destroy_frame(proxy, data);
+                if is_stale(app, data) {
+                    return;
+                }
+                match (&app.target, data.target) {
+                    (UserTarget::Shot, CaptureTarget::Output(i)) => {
+                        app.wlctx.scm.mark_ready(i);
+                    }
+                    (UserTarget::Shot, CaptureTarget::Toplevel) => app.wlctx.scm.copy_ready += 1,
+                    (UserTarget::Recorder, _) => {
+                        // NOTE: 合成器没有给出 presentation_time 时使用同一时钟的当前时间
+                        let time = data
+                            .time
+                            .lock()
+                            .unwrap()
+                            .unwrap_or_else(frame_queue::monotonic_nanos);
+                        if let Err(e) = app.wlctx.fq.storage_canvas(time) {
+                            warn!("failed to store recorded frame: {}", e);
+                            app.action = Action::Exit;
+                        }
+                    }
+                }
+            }
+            ext_image_copy_capture_frame_v1::Event::Failed { reason } => {
+                destroy_frame(proxy, data);
+                if is_stale(app, data) {
+                    return;
+                }
+                // NOTE: 输出在 copy 过程中被移除，忽略这一帧
+                if let CaptureTarget::Output(i) = data.target
+                    && !app.wlctx.has_output(i)
+                {
+                    debug!("output {} was removed, drop its frame", i);
+                    return;
+                }
+                warn!("buffer copy error: {:?}", reason);
+                app.action = Action::Exit;
+            }
+            _ => (),
+        }
+    }
+}
+
+/// 销毁 frame，非录制的 session 只捕获一帧，一并销毁 session 和 source
+fn destroy_frame(frame: &ExtImageCopyCaptureFrameV1, data: &ExtFrameData) {
+    frame.destroy();
+    // NOTE: 录制复用的 session 由 `stop_recorder_session` 销毁
+    if !data.recorder {
+        destroy_session(&data.session);
+    }
+}
+
+/// 录制结束后仍在途中的帧，其 session 已经销毁，忽略它的结果
+fn is_stale(app: &FoamShot, data: &ExtFrameData) -> bool {
+    data.recorder && app.wlctx.scm.recorder_session.as_ref() != Some(&data.session)
+}
+
+/// 销毁 session 及其 source
+pub fn destroy_session(session: &ExtImageCopyCaptureSessionV1) {
+    if let Some(session_data) = session.data::<ExtSessionData>() {
+        session_data.source.destroy();
+    }
+    session.destroy();
+}
+
+// NOTE: ne event
+#[allow(unused_variables)]
+impl Dispatch<ExtImageCopyCaptureManagerV1, ()> for FoamShot {
+    fn event(
+        app: &mut Self,
+        proxy: &ExtImageCopyCaptureManagerV1,
+        event: <ExtImageCopyCaptureManagerV1 as Proxy>::Event,
+        data: &(),
+        conn: &wayland_client::Connection,
+        qh: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
+// NOTE: ne event
+#[allow(unused_variables)]
+impl Dispatch<ExtOutputImageCaptureSourceManagerV1, ()> for FoamShot {
+    fn event(
+        app: &mut Self,
+        proxy: &ExtOutputImageCaptureSourceManagerV1,
+        event: <ExtOutputImageCaptureSourceManagerV1 as Proxy>::Event,
+        data: &(),
+        conn: &wayland_client::Connection,
+        qh: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
+// NOTE: ne event
+#[allow(unused_variables)]
+impl Dispatch<ExtImageCaptureSourceV1, ()> for FoamShot {
+    fn event(
+        app: &mut Self,
+        proxy: &ExtImageCaptureSourceV1,
+        event: <ExtImageCaptureSourceV1 as Proxy>::Event,
+        data: &(),
+        conn: &wayland_client::Connection,
+        qh: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
+// NOTE: ne event
+#[allow(unused_variables)]
+impl Dispatch<ExtForeignToplevelImageCaptureSourceManagerV1, ()> for FoamShot {
+    fn event(
+        app: &mut Self,
+        proxy: &ExtForeignToplevelImageCaptureSourceManagerV1,
+        event: <ExtForeignToplevelImageCaptureSourceManagerV1 as Proxy>::Event,
+        data: &(),
+        conn: &wayland_client::Connection,
+        qh: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
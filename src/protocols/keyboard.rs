@@ -13,6 +13,14 @@ const KEY_A: u32 = 30;
 const KEY_S: u32 = 31;
 const KEY_M: u32 = 50;
 const KEY_Q: u32 = 16;
+const KEY_R: u32 = 19;
+const KEY_1: u32 = 2;
+const KEY_9: u32 = 10;
+const KEY_J: u32 = 36;
+const KEY_K: u32 = 37;
+const KEY_ENTER: u32 = 28;
+const KEY_UP: u32 = 103;
+const KEY_DOWN: u32 = 108;
 
 // TODO:
 #[allow(unused_variables)]
@@ -34,19 +42,33 @@ impl Dispatch<wl_keyboard::WlKeyboard, ()> for FoamShot {
         {
             debug!("Key pressed: {}", key);
 
+            if app.wlctx.window_picker.is_some() {
+                picker_key(app, key);
+                return;
+            }
+
             match key {
                 KEY_A => {
-                    let current_output = app.wlctx.current_index;
-                    app.wlctx.set_one_max(current_output.unwrap());
-                    app.action = Action::Output
+                    if app.action == Action::OnRecorder {
+                        return;
+                    }
+                    let Some(current_output) = app.wlctx.current_index else {
+                        return;
+                    };
+                    app.wlctx.set_one_max(current_output);
+                    app.action = app.finish_select()
                 }
                 KEY_S => match app.action {
                     Action::WaitPointerPress => {}
                     Action::Init => {}
                     Action::Exit => {}
-                    _ => app.action = Action::Output,
+                    Action::OnRecorder => app.action = Action::Output,
+                    _ => app.action = app.finish_select(),
                 },
                 KEY_F => {
+                    if app.action == Action::OnRecorder {
+                        return;
+                    }
                     app.wlctx.current_freeze = !app.wlctx.current_freeze;
                     app.action = if app.wlctx.current_freeze {
                         Action::ToggleFreeze(IsFreeze::NewFrameFreeze)
@@ -54,7 +76,7 @@ impl Dispatch<wl_keyboard::WlKeyboard, ()> for FoamShot {
                         Action::ToggleFreeze(IsFreeze::UnFreeze)
                     };
                 }
-                KEY_Q |KEY_ESC=> match app.action {
+                KEY_Q | KEY_ESC => match app.action {
                     Action::OnEdit(a) => {
                         app.action = if app.wlctx.current_freeze {
                             Action::ToggleFreeze(IsFreeze::OldFrameFreeze)
@@ -63,14 +85,20 @@ impl Dispatch<wl_keyboard::WlKeyboard, ()> for FoamShot {
                         };
                     }
                     Action::OnRecorder => {
-                        // debug!("recorder size: {}", app.wlctx.fq.f.len());
-                        // app.wlctx.fq.to_gif("a.gif").ok();
-                        std::process::exit(0);
+                        // 停止录制，交由主循环编码输出
+                        app.action = Action::Output;
                     }
                     _ => {
-                        std::process::exit(0);
+                        // 取消截图，交由主循环结束会话
+                        app.action = Action::Exit;
                     }
                 },
+                KEY_R => {
+                    // 载入上次的选区进行调整
+                    if matches!(app.action, Action::WaitPointerPress | Action::OnEdit(_)) {
+                        app.load_last_region();
+                    }
+                }
                 KEY_M => {
                     debug!(
                         "current pos: {:?} g_rect: {:?}",
@@ -82,3 +110,30 @@ impl Dispatch<wl_keyboard::WlKeyboard, ()> for FoamShot {
         }
     }
 }
+
+/// 交互式选择窗口时的按键，Esc/q 取消
+fn picker_key(app: &mut FoamShot, key: u32) {
+    let Some(picker) = app.wlctx.window_picker.as_mut() else {
+        return;
+    };
+    let redraw = match key {
+        KEY_UP | KEY_K => picker.move_selection(-1),
+        KEY_DOWN | KEY_J => picker.move_selection(1),
+        KEY_ENTER => {
+            picker.pick(picker.highlighted());
+            false
+        }
+        KEY_1..=KEY_9 => {
+            picker.pick((key - KEY_1) as usize);
+            false
+        }
+        KEY_Q | KEY_ESC => {
+            app.action = Action::Exit;
+            false
+        }
+        _ => false,
+    };
+    if redraw {
+        app.wlctx.picker_attach();
+    }
+}
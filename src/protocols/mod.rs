@@ -1,6 +1,8 @@
 //! INFO: Implementation of the core (general) interface of the Wayland protocol
 //! `wl_pointer` and `wl_keyboard` are implemented separately
 
+mod ext_foreign_toplevel_list_v1;
+mod ext_image_copy_capture_v1;
 mod keyboard;
 mod pointer;
 mod wp_cursor_shape_manager_v1;
@@ -9,7 +11,9 @@ mod wp_viewporter;
 mod xdg;
 mod zwlr_layer_shell_v1;
 mod zwlr_screencopy_manager_v1;
+mod zwp_linux_dmabuf_v1;
 
+pub use ext_image_copy_capture_v1::{ExtSessionData, capture_frame, destroy_session};
 use log::*;
 use smithay_client_toolkit::{
     delegate_shm,
@@ -20,13 +24,22 @@ use wayland_client::{
     globals::GlobalListContents,
     protocol::{
         wl_callback::{self},
-        wl_compositor, wl_output, wl_registry, wl_seat, wl_surface,
+        wl_compositor, wl_output, wl_region, wl_registry, wl_seat, wl_surface,
     },
 };
 use wayland_protocols::{
+    ext::{
+        foreign_toplevel_list::v1::client::ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1,
+        image_capture_source::v1::client::{
+            ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1,
+            ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+        },
+        image_copy_capture::v1::client::ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1,
+    },
     wp::{
         cursor_shape::v1::client::wp_cursor_shape_manager_v1::WpCursorShapeManagerV1,
         fractional_scale::v1::client::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+        linux_dmabuf::zv1::client::zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
         viewporter::client::wp_viewporter::WpViewporter,
     },
     xdg::xdg_output::zv1::client::zxdg_output_manager_v1,
@@ -36,9 +49,7 @@ use wayland_protocols_wlr::{
     screencopy::v1::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
 };
 
-use crate::{
-    action::Action, foamcore::FoamShot, monitors, zwlr_screencopy_mode::ZwlrScreencopyMode,
-};
+use crate::{action::Action, foamcore::FoamShot, monitors};
 
 impl Dispatch<wl_registry::WlRegistry, ()> for FoamShot {
     fn event(
@@ -60,27 +71,27 @@ impl Dispatch<wl_registry::WlRegistry, ()> for FoamShot {
                 let interface_name = interface.as_str();
                 match interface_name {
                     // Compositor 绑定
-                    _ if interface_name == wl_compositor::WlCompositor::interface().name => {
-                        if app.wlctx.compositor.is_none() {
-                            let compositor = proxy.bind(name, version, qh, ());
-                            app.wlctx.compositor = Some((compositor, name));
-                        }
+                    _ if interface_name == wl_compositor::WlCompositor::interface().name
+                        && app.wlctx.compositor.is_none() =>
+                    {
+                        let compositor = proxy.bind(name, version, qh, ());
+                        app.wlctx.compositor = Some((compositor, name));
                     }
                     // Seat 绑定及相关资源获取
-                    _ if interface_name == wl_seat::WlSeat::interface().name => {
-                        if app.wlctx.seat.is_none() {
-                            let seat: wl_seat::WlSeat = proxy.bind(name, version, qh, ());
-                            let pointer = seat.get_pointer(qh, ());
-                            let keyboard = seat.get_keyboard(qh, ());
-                            app.wlctx.pointer_helper.pointer = Some(pointer);
-                            app.wlctx.keyboard = Some(keyboard);
-                            app.wlctx.seat = Some((seat, name));
-                        }
+                    _ if interface_name == wl_seat::WlSeat::interface().name
+                        && app.wlctx.seat.is_none() =>
+                    {
+                        let seat: wl_seat::WlSeat = proxy.bind(name, version, qh, ());
+                        let pointer = seat.get_pointer(qh, ());
+                        let keyboard = seat.get_keyboard(qh, ());
+                        app.wlctx.pointer_helper.pointer = Some(pointer);
+                        app.wlctx.keyboard = Some(keyboard);
+                        app.wlctx.seat = Some((seat, name));
                     }
-                    // 动态管理 outputs
+                    // 动态管理 outputs，以 registry name 作为索引，热插拔时不会变化
                     _ if interface_name == wl_output::WlOutput::interface().name => {
                         let outputs = app.wlctx.foam_outputs.as_mut().unwrap();
-                        let index = outputs.len();
+                        let index = name as usize;
                         let shm = app.wlctx.shm.as_mut().unwrap();
                         let pool =
                             SlotPool::new(256 * 256 * 4, shm).expect("Failed to create pool");
@@ -92,92 +103,137 @@ impl Dispatch<wl_registry::WlRegistry, ()> for FoamShot {
                         outputs.insert(index, foam_output);
                     }
                     // Layer shell 绑定
-                    _ if interface_name == ZwlrLayerShellV1::interface().name => {
-                        if app.wlctx.layer_shell.is_none() {
-                            let layer_shell = proxy.bind(name, version, qh, ());
-                            app.wlctx.layer_shell = Some((layer_shell, name));
-                        }
+                    _ if interface_name == ZwlrLayerShellV1::interface().name
+                        && app.wlctx.layer_shell.is_none() =>
+                    {
+                        let layer_shell = proxy.bind(name, version, qh, ());
+                        app.wlctx.layer_shell = Some((layer_shell, name));
                     }
                     // Screencopy manager 绑定
-                    _ if interface_name == ZwlrScreencopyManagerV1::interface().name => {
-                        if app.wlctx.scm.manager.is_none() {
-                            let manager: ZwlrScreencopyManagerV1 =
-                                proxy.bind(name, version, qh, ());
-                            // app.wayland_ctx.screencopy_manager = Some((manager.clone(), name));
-                            app.wlctx.scm = ZwlrScreencopyMode::new((manager, name));
-                        }
+                    _ if interface_name == ZwlrScreencopyManagerV1::interface().name
+                        && app.wlctx.scm.manager.is_none() =>
+                    {
+                        let manager: ZwlrScreencopyManagerV1 = proxy.bind(name, version, qh, ());
+                        app.wlctx.scm.manager = Some((manager, name));
+                        app.wlctx.scm.select_backend();
+                    }
+                    // ext image capture source 绑定
+                    _ if interface_name
+                        == ExtOutputImageCaptureSourceManagerV1::interface().name
+                        && app.wlctx.scm.ext_source_manager.is_none() =>
+                    {
+                        let manager = proxy.bind(name, version, qh, ());
+                        app.wlctx.scm.ext_source_manager = Some((manager, name));
+                        app.wlctx.scm.select_backend();
+                    }
+                    // ext image copy capture 绑定
+                    _ if interface_name == ExtImageCopyCaptureManagerV1::interface().name
+                        && app.wlctx.scm.ext_copy_manager.is_none() =>
+                    {
+                        let manager = proxy.bind(name, version, qh, ());
+                        app.wlctx.scm.ext_copy_manager = Some((manager, name));
+                        app.wlctx.scm.select_backend();
+                    }
+                    // ext toplevel image capture source 绑定，用于窗口捕获
+                    _ if interface_name
+                        == ExtForeignToplevelImageCaptureSourceManagerV1::interface().name
+                        && app.wlctx.scm.ext_toplevel_source_manager.is_none() =>
+                    {
+                        let manager = proxy.bind(name, version, qh, ());
+                        app.wlctx.scm.ext_toplevel_source_manager = Some((manager, name));
+                        app.wlctx.scm.select_backend();
+                    }
+                    // NOTE: 仅在窗口捕获时绑定，避免无用的 toplevel 事件，守护进程需要为之后的请求绑定
+                    _ if interface_name == ExtForeignToplevelListV1::interface().name
+                        && (app.wlctx.config.window.is_some() || app.wlctx.config.daemon)
+                        && app.wlctx.toplevel_helper.list.is_none() =>
+                    {
+                        let list = proxy.bind(name, version, qh, ());
+                        app.wlctx.toplevel_helper.list = Some((list, name));
+                    }
+                    // NOTE: 仅在 --dmabuf 或守护进程时绑定，create_immed 需要 v2
+                    _ if interface_name == ZwpLinuxDmabufV1::interface().name
+                        && (app.wlctx.config.dmabuf || app.wlctx.config.daemon)
+                        && version >= 2
+                        && app.wlctx.dmabuf.is_none() =>
+                    {
+                        let dmabuf = proxy.bind(name, version.min(3), qh, ());
+                        app.wlctx.dmabuf = Some((dmabuf, name));
                     }
                     // Cursor shape 相关绑定
-                    _ if interface_name == WpCursorShapeManagerV1::interface().name => {
-                        if app.wlctx.pointer_helper.cursor_shape_manager.is_none() {
-                            let manager: WpCursorShapeManagerV1 = proxy.bind(name, version, qh, ());
-                            app.wlctx.pointer_helper.cursor_shape_manager = Some((manager, name));
-                        }
+                    _ if interface_name == WpCursorShapeManagerV1::interface().name
+                        && app.wlctx.pointer_helper.cursor_shape_manager.is_none() =>
+                    {
+                        let manager: WpCursorShapeManagerV1 = proxy.bind(name, version, qh, ());
+                        app.wlctx.pointer_helper.cursor_shape_manager = Some((manager, name));
                     }
                     // NOTE: xdg_output_manager 处理多输出
                     _ if interface_name
-                        == zxdg_output_manager_v1::ZxdgOutputManagerV1::interface().name =>
+                        == zxdg_output_manager_v1::ZxdgOutputManagerV1::interface().name
+                        && app.wlctx.xdg_output_manager.is_none() =>
                     {
-                        if app.wlctx.xdg_output_manager.is_none() {
-                            let manager = proxy.bind(name, version, qh, ());
-                            app.wlctx.xdg_output_manager = Some((manager, name));
-                        }
+                        let manager = proxy.bind(name, version, qh, ());
+                        app.wlctx.xdg_output_manager = Some((manager, name));
                     }
                     // Viewporter
-                    _ if interface_name == WpViewporter::interface().name => {
-                        if app.wlctx.viewporter.is_none() {
-                            let viewporter = proxy.bind(name, version, qh, ());
-                            app.wlctx.viewporter = Some((viewporter, name));
-                        }
+                    _ if interface_name == WpViewporter::interface().name
+                        && app.wlctx.viewporter.is_none() =>
+                    {
+                        let viewporter = proxy.bind(name, version, qh, ());
+                        app.wlctx.viewporter = Some((viewporter, name));
                     }
                     // Fractional scale
-                    _ if interface_name == WpFractionalScaleManagerV1::interface().name => {
-                        if app.wlctx.fractional_manager.is_none() {
-                            let fractional = proxy.bind(name, version, qh, ());
-                            app.wlctx.fractional_manager = Some((fractional, name));
-                        }
+                    _ if interface_name == WpFractionalScaleManagerV1::interface().name
+                        && app.wlctx.fractional_manager.is_none() =>
+                    {
+                        let fractional = proxy.bind(name, version, qh, ());
+                        app.wlctx.fractional_manager = Some((fractional, name));
                     }
                     _ => (),
                 }
             }
             wl_registry::Event::GlobalRemove { name } => {
-                if let Some((_, compositor_name)) = &app.wlctx.compositor {
-                    if name == *compositor_name {
-                        warn!("WlCompositor was removed");
-                        app.wlctx.compositor = None;
-                    } else if let Some((_, sate_name)) = &app.wlctx.seat {
-                        if name == *sate_name {
-                            warn!("WlSeat was removed");
-                            app.wlctx.seat = None;
-                        }
-                    } else if let Some((_, screencopymanager_name)) = &app.wlctx.scm.manager {
-                        if name == *screencopymanager_name {
-                            warn!("ZwlrScreencopyManagerV1 was removed");
-                            app.wlctx.scm.manager = None;
-                        }
-                    } else if let Some((_, layer_shell_name)) = &app.wlctx.layer_shell {
-                        if name == *layer_shell_name {
-                            warn!("ZwlrLayerShellV1 was removed");
-                            app.wlctx.layer_shell = None;
-                        }
-                    } else if let Some((_, cursor_shape_manager_name)) =
-                        &app.wlctx.pointer_helper.cursor_shape_manager
-                    {
-                        if name == *cursor_shape_manager_name {
-                            warn!("WpCursorShapeManagerV1 was removed");
-                            app.wlctx.pointer_helper.cursor_shape_manager = None;
-                        }
-                    } else if let Some((_, viewporter_name)) = &app.wlctx.viewporter {
-                        if name == *viewporter_name {
-                            warn!("WpViewporter was removed");
-                            app.wlctx.viewporter = None;
-                        }
-                    } else if let Some((_, fractional_manager_name)) = &app.wlctx.fractional_manager
+                if let Some((_, compositor_name)) = &app.wlctx.compositor
+                    && name == *compositor_name
+                {
+                    warn!("WlCompositor was removed");
+                    app.wlctx.compositor = None;
+                } else if let Some((_, sate_name)) = &app.wlctx.seat
+                    && name == *sate_name
+                {
+                    warn!("WlSeat was removed");
+                    app.wlctx.seat = None;
+                } else if app.wlctx.scm.remove_global(name) {
+                    warn!("capture manager {} was removed", name);
+                } else if let Some((_, layer_shell_name)) = &app.wlctx.layer_shell
+                    && name == *layer_shell_name
+                {
+                    warn!("ZwlrLayerShellV1 was removed");
+                    app.wlctx.layer_shell = None;
+                } else if let Some((_, cursor_shape_manager_name)) =
+                    &app.wlctx.pointer_helper.cursor_shape_manager
+                    && name == *cursor_shape_manager_name
+                {
+                    warn!("WpCursorShapeManagerV1 was removed");
+                    app.wlctx.pointer_helper.cursor_shape_manager = None;
+                } else if let Some((_, viewporter_name)) = &app.wlctx.viewporter
+                    && name == *viewporter_name
+                {
+                    warn!("WpViewporter was removed");
+                    app.wlctx.viewporter = None;
+                } else if let Some((_, fractional_manager_name)) = &app.wlctx.fractional_manager
+                    && name == *fractional_manager_name
+                {
+                    warn!("WpFractionalScaleManagerV1 was removed");
+                    app.wlctx.fractional_manager = None;
+                } else if app.wlctx.remove_output(name as usize) {
+                    warn!("output {} was removed", name);
+                    // NOTE: 录制中的输出被移除，保存已录制的帧
+                    if app.wlctx.fq.monitor_id == Some(name as usize)
+                        && app.action == Action::OnRecorder
                     {
-                        if name == *fractional_manager_name {
-                            warn!("WpFractionalScaleManagerV1 was removed");
-                            app.wlctx.fractional_manager = None;
-                        }
+                        app.send_warn("recording output was removed, stop recording");
+                        app.action = Action::Output;
                     }
                 }
             }
@@ -201,6 +257,10 @@ impl Dispatch<wl_output::WlOutput, usize> for FoamShot {
             wl_output::Event::Scale { factor } => {
                 // NOTE: WE DO NOT CHANGE SCALE HERE SINCE IT WILL HAPPEN BEFORE WE INIT LAYER
                 // GO CHECK ON WlSurface INSTEAD
+                // 仅记录，用于缺少 viewporter 时的整数缩放
+                if let Some(foam_output) = app.wlctx.foam_outputs.as_mut().unwrap().get_mut(data) {
+                    foam_output.output_scale = factor;
+                }
             }
             wl_output::Event::Mode {
                 flags: _,
@@ -208,9 +268,15 @@ impl Dispatch<wl_output::WlOutput, usize> for FoamShot {
                 height,
                 refresh: _,
             } => {
-                let mut foam_output = app.wlctx.foam_outputs.as_mut().unwrap().get_mut(*data);
-                foam_output.as_mut().unwrap().width = width;
-                foam_output.as_mut().unwrap().height = height;
+                let foam_output = app
+                    .wlctx
+                    .foam_outputs
+                    .as_mut()
+                    .unwrap()
+                    .get_mut(data)
+                    .unwrap();
+                foam_output.mode_size = (width, height);
+                foam_output.update_size();
                 // hs_insert(&mut app.wayland_ctx.widths, *data, width);
                 // hs_insert(&mut app.wayland_ctx.heights, *data, height);
             }
@@ -233,6 +299,15 @@ impl Dispatch<wl_output::WlOutput, usize> for FoamShot {
                     error!("No ZxdgOutputManagerV1 loaded");
                     return;
                 };
+                if let Some(foam_output) = app.wlctx.foam_outputs.as_mut().unwrap().get_mut(data) {
+                    foam_output.transform = transform.into_result().ok();
+                    foam_output.update_size();
+                    // NOTE: geometry 会在输出属性变化时重发，surface 与 xdg_output 只创建一次
+                    if foam_output.surface.is_some() {
+                        return;
+                    }
+                }
+
                 // create an xdg_output object for this wl_output
                 let _ = xdg_output_manager.get_xdg_output(proxy, qh, *data);
 
@@ -247,10 +322,14 @@ impl Dispatch<wl_output::WlOutput, usize> for FoamShot {
                     .foam_outputs
                     .as_mut()
                     .unwrap()
-                    .get_mut(*data)
+                    .get_mut(data)
                     .unwrap();
                 foam_output.surface = Some(compositor.create_surface(qh, *data));
             }
+            wl_output::Event::Done => {
+                // 会话中途加入的输出
+                app.wlctx.init_hotplug_layer(*data);
+            }
             _ => {}
         };
     }
@@ -268,28 +347,15 @@ impl Dispatch<wl_surface::WlSurface, usize> for FoamShot {
     ) {
         match event {
             wl_surface::Event::PreferredBufferTransform { transform } => {
-                if let Ok(t) = transform.into_result() {
-                    proxy.set_buffer_transform(t);
-                }
+                // NOTE: 画布已经转为正向，buffer 不再需要设置 transform
+                trace!("preferred buffer transform: {:?}", transform);
             }
             wl_surface::Event::PreferredBufferScale { factor } => {
-                // let mut foam_output = app.wlctx.foam_outputs.as_mut().unwrap().get_mut(*data);
-                // foam_output
-                //     .as_mut()
-                //     .unwrap()
-                //     .surface
-                //     .as_mut()
-                //     .unwrap()
-                //     .set_buffer_scale(factor);
-                // foam_output
-                //     .as_mut()
-                //     .unwrap()
-                //     .scale
-                //     .as_mut()
-                //     .unwrap()
-                //     .update_normal(factor as u32);
                 // NOTE: WE DO NOT CHANGE BUFFER SCALE, USE ViewPorter INSTEAD.
-                // proxy.set_buffer_scale(factor);
+                // 没有 viewporter 时回退到整数 buffer scale
+                if let Some(foam_output) = app.wlctx.foam_outputs.as_mut().unwrap().get_mut(data) {
+                    foam_output.update_buffer_scale(factor);
+                }
             }
             _ => {}
         }
@@ -322,6 +388,19 @@ impl Dispatch<wl_compositor::WlCompositor, ()> for FoamShot {
     ) {
     }
 }
+// NOTE: ne events
+#[allow(unused_variables)]
+impl Dispatch<wl_region::WlRegion, ()> for FoamShot {
+    fn event(
+        app: &mut Self,
+        proxy: &wl_region::WlRegion,
+        event: <wl_region::WlRegion as Proxy>::Event,
+        data: &(),
+        conn: &wayland_client::Connection,
+        qh: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
 // NOTE: unused
 #[allow(unused_variables)]
 impl Dispatch<wl_seat::WlSeat, ()> for FoamShot {
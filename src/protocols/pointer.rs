@@ -6,7 +6,7 @@ use wayland_client::{Dispatch, Proxy};
 use wayland_protocols::wp::cursor_shape::v1::client::wp_cursor_shape_device_v1::Shape;
 
 use crate::action::{Action, EditAction};
-use crate::foamcore::{FoamShot, UserTarget};
+use crate::foamcore::FoamShot;
 use crate::{monitors, select_rect};
 
 // TODO:
@@ -20,6 +20,10 @@ impl Dispatch<wl_pointer::WlPointer, ()> for FoamShot {
         conn: &wayland_client::Connection,
         qh: &wayland_client::QueueHandle<Self>,
     ) {
+        if app.wlctx.window_picker.is_some() {
+            picker_pointer(app, proxy, event);
+            return;
+        }
         match event {
             wl_pointer::Event::Leave { serial, surface } => {
                 let _ = app.wlctx.set_cursor_shape(Shape::Default, proxy);
@@ -35,8 +39,8 @@ impl Dispatch<wl_pointer::WlPointer, ()> for FoamShot {
                 let surface_index = match surface.data::<usize>() {
                     Some(idx) => *idx,
                     None => {
-                        error!("can not get surface index in Enter, exit!");
-                        std::process::exit(0)
+                        error!("can not get surface index in Enter");
+                        return;
                     }
                 };
                 app.wlctx.unknown_index = Some(surface_index);
@@ -50,12 +54,12 @@ impl Dispatch<wl_pointer::WlPointer, ()> for FoamShot {
                     .wlctx
                     .foam_outputs
                     .as_ref()
-                    .and_then(|outputs| outputs.get(surface_index))
+                    .and_then(|outputs| outputs.get(&surface_index))
                 {
                     Some(output) => output,
                     None => {
-                        error!("can not get foam_output, exit!");
-                        std::process::exit(0)
+                        error!("can not get foam_output");
+                        return;
                     }
                 };
 
@@ -89,7 +93,7 @@ impl Dispatch<wl_pointer::WlPointer, ()> for FoamShot {
                     // NOTE:  full screen mode handle
                     if app.wlctx.config.full_screen {
                         app.wlctx.set_one_max(surface_index);
-                        app.action = Action::Output;
+                        app.action = app.finish_select();
                         return;
                     }
 
@@ -99,12 +103,10 @@ impl Dispatch<wl_pointer::WlPointer, ()> for FoamShot {
                         .start_index
                         .get_or_insert(surface_index);
 
-                    let sp = foam_output.scale.as_ref().unwrap().calculate_pos((x, y));
-                    // 鼠标未移动时进行初始化
+                    // 鼠标未移动时进行初始化，全局坐标为逻辑坐标
                     app.wlctx.pointer_helper.g_current_pos.get_or_insert((
-                        // foam_output.scale.as_ref().unwrap().calculate_pos((x, y))
-                        sp.0 + foam_output.global_x as f64,
-                        sp.1 + foam_output.global_y as f64,
+                        x + foam_output.global_x as f64,
+                        y + foam_output.global_y as f64,
                     ));
                 }
             }
@@ -130,46 +132,35 @@ impl Dispatch<wl_pointer::WlPointer, ()> for FoamShot {
                                 app.wlctx.pointer_helper.g_start_pos =
                                     app.wlctx.pointer_helper.g_current_pos;
 
-                                if let Some(current_pos) = app.wlctx.pointer_helper.g_current_pos {
-                                    if let Some(global_rect) = app.wlctx.global_rect.as_ref() {
-                                        let hit_region = global_rect.hit_region(
-                                            current_pos.0 as i32,
-                                            current_pos.1 as i32,
-                                            select_rect::THRESHOLD,
-                                        );
-                                        app.action = Action::OnEdit(hit_region);
-                                    }
+                                if let Some(current_pos) = app.wlctx.pointer_helper.g_current_pos
+                                    && let Some(global_rect) = app.wlctx.global_rect.as_ref()
+                                {
+                                    let hit_region = global_rect.hit_region(
+                                        current_pos.0 as i32,
+                                        current_pos.1 as i32,
+                                        select_rect::THRESHOLD,
+                                    );
+                                    app.action = Action::OnEdit(hit_region);
                                 }
                             }
                             _ => {}
                         },
                         wl_pointer::ButtonState::Released => {
+                            // 录制过程中忽略鼠标
+                            if app.action == Action::OnRecorder {
+                                return;
+                            }
                             if app.action == Action::OnDraw {
                                 app.wlctx.pointer_helper.end_index = app.wlctx.current_index;
                                 app.wlctx.pointer_helper.g_end_pos =
                                     app.wlctx.pointer_helper.g_current_pos;
                             }
 
-                            app.action = if app.wlctx.config.edit {
+                            // 连续截图时同样进入编辑，按 s 保存
+                            app.action = if app.wlctx.config.edit || app.wlctx.config.multi {
                                 Action::OnEdit(EditAction::None)
                             } else {
-                                match app.target {
-                                    UserTarget::Shot => Action::Output,
-                                    UserTarget::Recorder => {
-                                        // TODO:
-                                        todo!()
-                                        // app.wlctx.unset_freeze();
-                                        // app.wlctx
-                                        //     .foam_outputs
-                                        //     .as_mut()
-                                        //     .unwrap()
-                                        //     .iter_mut()
-                                        //     .for_each(|m| {
-                                        //         m.layer_surface.as_mut().unwrap().destroy();
-                                        //     });
-                                        // Action::OnRecorder
-                                    }
-                                }
+                                app.finish_select()
                             };
                         }
                         _ => (),
@@ -189,14 +180,13 @@ impl Dispatch<wl_pointer::WlPointer, ()> for FoamShot {
                 ) {
                     (Some(u), Some(s), Some(o)) => (u, s, o),
                     _ => {
-                        error!("can not get surface index in Motion, exit!");
-                        // return;
-                        std::process::exit(0)
+                        error!("can not get surface index in Motion");
+                        return;
                     }
                 };
 
                 let (start_output, unknown_output) =
-                    match (outputs.get(start_index), outputs.get(unknown_index)) {
+                    match (outputs.get(&start_index), outputs.get(&unknown_index)) {
                         (Some(s), Some(u)) => (s, u),
                         _ => return,
                     };
@@ -208,12 +198,10 @@ impl Dispatch<wl_pointer::WlPointer, ()> for FoamShot {
                     surface_y,
                 );
 
-                // Only calculate once
-                let sp = start_output.scale.as_ref().unwrap().calculate_pos((x, y));
-
+                // NOTE: 选区使用全局逻辑坐标，各输出的缩放在计算子矩形时处理
                 let global_pos = (
-                    sp.0 + start_output.global_x as f64,
-                    sp.1 + start_output.global_y as f64,
+                    x + start_output.global_x as f64,
+                    y + start_output.global_y as f64,
                 );
 
                 app.wlctx.pointer_helper.g_current_pos = Some((global_pos.0, global_pos.1));
@@ -255,3 +243,65 @@ impl Dispatch<wl_pointer::WlPointer, ()> for FoamShot {
         }
     }
 }
+
+/// 交互式选择窗口时的鼠标事件，悬停高亮所在行，点击确定
+fn picker_pointer(app: &mut FoamShot, proxy: &wl_pointer::WlPointer, event: wl_pointer::Event) {
+    let (surface_x, surface_y) = match event {
+        wl_pointer::Event::Enter {
+            serial,
+            surface,
+            surface_x,
+            surface_y,
+        } => {
+            app.wlctx.pointer_helper.serial = serial;
+            app.wlctx.unknown_index = surface.data::<usize>().copied();
+            let _ = app.wlctx.set_cursor_shape(Shape::Default, proxy);
+            (surface_x, surface_y)
+        }
+        wl_pointer::Event::Motion {
+            surface_x,
+            surface_y,
+            ..
+        } => (surface_x, surface_y),
+        wl_pointer::Event::Button {
+            state: wayland_client::WEnum::Value(wl_pointer::ButtonState::Pressed),
+            ..
+        } => {
+            if let Some(picker) = app.wlctx.window_picker.as_mut()
+                && let Some(row) = picker.hovered
+            {
+                picker.pick(row);
+            }
+            return;
+        }
+        wl_pointer::Event::Leave { .. } => {
+            app.wlctx.unknown_index = None;
+            if app
+                .wlctx
+                .window_picker
+                .as_mut()
+                .is_some_and(|p| p.hover(None))
+            {
+                app.wlctx.picker_attach();
+            }
+            return;
+        }
+        _ => return,
+    };
+
+    let Some(foam_output) = app
+        .wlctx
+        .unknown_index
+        .and_then(|i| app.wlctx.foam_outputs.as_ref()?.get(&i))
+    else {
+        return;
+    };
+    let logical_size = foam_output.logical_size();
+    let Some(picker) = app.wlctx.window_picker.as_mut() else {
+        return;
+    };
+    let row = picker.layout(logical_size).row_at(surface_x, surface_y);
+    if picker.hover(row) {
+        app.wlctx.picker_attach();
+    }
+}
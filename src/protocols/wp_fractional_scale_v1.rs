@@ -21,7 +21,7 @@ impl wayland_client::Dispatch<WpFractionalScaleV1, usize> for FoamShot {
     ) {
         if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
             debug!("wp_fractional_scale_v1::Event::PreferredScale: {}", scale);
-            let mut foam_output = app.wlctx.foam_outputs.as_mut().unwrap().get_mut(*data);
+            let mut foam_output = app.wlctx.foam_outputs.as_mut().unwrap().get_mut(data);
             foam_output
                 .as_mut()
                 .unwrap()
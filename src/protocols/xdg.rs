@@ -31,13 +31,11 @@ impl Dispatch<zxdg_output_v1::ZxdgOutputV1, usize> for FoamShot {
         conn: &wayland_client::Connection,
         qh: &wayland_client::QueueHandle<Self>,
     ) {
-        let foam_output = app
-            .wlctx
-            .foam_outputs
-            .as_mut()
-            .unwrap()
-            .get_mut(*data)
-            .unwrap();
+        // NOTE: 输出可能已被移除
+        let Some(foam_output) = app.wlctx.foam_outputs.as_mut().unwrap().get_mut(data) else {
+            proxy.destroy();
+            return;
+        };
 
         match event {
             zxdg_output_v1::Event::LogicalPosition { x, y } => {
@@ -58,6 +56,11 @@ impl Dispatch<zxdg_output_v1::ZxdgOutputV1, usize> for FoamShot {
                 info!("Name: {}", name);
                 foam_output.name = name;
             }
+            // NOTE: 旧版本的 xdg_output 使用自己的 done 事件
+            #[allow(deprecated)]
+            zxdg_output_v1::Event::Done => {
+                app.wlctx.init_hotplug_layer(*data);
+            }
             _ => (),
         }
 
@@ -30,19 +30,31 @@ impl Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, usize> for FoamShot {
                 proxy.ack_configure(serial);
                 proxy.set_size(width, height);
                 if app.action == Action::Init {
-                    if app.wlctx.config.full_screen {
+                    if app.wlctx.window_picker.is_some() {
+                        app.wlctx.picker_attach_with_udata(*data);
+                    } else if app.wlctx.config.full_screen {
                         app.wlctx.no_freeze_attach_with_udata(*data);
                     } else {
                         app.wlctx.attach_with_udata(*data);
                     }
                     app.wlctx.layer_ready += 1;
-                    if app.wlctx.layer_ready == app.wlctx.foam_outputs.as_ref().unwrap().len() {
+                    // NOTE: 输出可能在配置完成前被移除
+                    if app.wlctx.layer_ready >= app.wlctx.foam_outputs.as_ref().unwrap().len() {
                         app.wlctx.current_freeze = app.wlctx.config.freeze;
+                        // --full-screen 模式不显示冻结画面，输出时再进行区域 copy
+                        if app.wlctx.config.full_screen {
+                            app.wlctx.current_freeze = false;
+                        }
                         app.action = Action::WaitPointerPress;
 
                         app.wlctx.layer_ready = 0;
                         debug!("all layer configured, enter WaitPointerPress")
                     }
+                } else if app.wlctx.window_picker.is_some() {
+                    app.wlctx.picker_attach_with_udata(*data);
+                } else if app.action != Action::OnRecorder {
+                    // NOTE: 会话中途加入的输出
+                    app.wlctx.attach_with_udata(*data);
                 }
             }
             zwlr_layer_surface_v1::Event::Closed => {
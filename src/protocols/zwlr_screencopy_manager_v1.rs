@@ -1,7 +1,6 @@
 //! INFO: zwlr_screencopy_manager_v1 && zwlr_screencopy_frame_v1 interface implementation
 //! Used for screen frame capture
 use log::*;
-use smithay_client_toolkit::shm::slot::SlotPool;
 use wayland_client::{Dispatch, Proxy};
 use wayland_protocols_wlr::screencopy::v1::client::{
     zwlr_screencopy_frame_v1, zwlr_screencopy_manager_v1,
@@ -28,53 +27,46 @@ impl Dispatch<zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1, usize> for FoamSh
                         height,
                         stride,
                     } => {
-                        let current = app
-                            .wlctx
-                            .foam_outputs
-                            .as_mut()
-                            .unwrap()
-                            .get_mut(*data)
-                            .unwrap();
-                        let shm = app.wlctx.shm.as_mut().unwrap();
-                        let pool = SlotPool::new(stride as usize * height as usize, shm)
-                            .expect("Failed to create pool");
-                        //
-                        // // TEST:
-                        // pool.resize(height as usize * stride as usize).ok().unwrap();
-                        //
-                        // debug!(
-                        //     "zwlr_screencopy_frame_v1::Event::Buffer: pool size: {}",
-                        //     pool.len()
-                        // );
-
-                        current.pool = Some(pool);
-
-                        let (buffer, canvas) = current
-                            .pool
-                            .as_mut()
-                            .unwrap()
-                            .create_buffer(
-                                width as i32,
-                                height as i32,
-                                stride as i32,
-                                format.into_result().unwrap(),
-                            )
-                            .unwrap();
-                        canvas.fill(0);
-                        app.wlctx.scm.insert_buffer(*data, buffer).ok();
+                        app.wlctx.scm.frame_formats.entry(*data).or_default().shm = Some((
+                            width as i32,
+                            height as i32,
+                            stride as i32,
+                            format.into_result().unwrap(),
+                        ));
+                    }
+                    zwlr_screencopy_frame_v1::Event::LinuxDmabuf {
+                        format,
+                        width,
+                        height,
+                    } => {
+                        app.wlctx.scm.frame_formats.entry(*data).or_default().dmabuf =
+                            Some((format, width as i32, height as i32));
+                    }
+                    zwlr_screencopy_frame_v1::Event::Flags { flags } => {
+                        if let Ok(flags) = flags.into_result() {
+                            app.wlctx
+                                .scm
+                                .frame_transform
+                                .entry(*data)
+                                .or_default()
+                                .y_invert =
+                                flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert);
+                        }
                     }
                     zwlr_screencopy_frame_v1::Event::BufferDone => {
                         trace!("bufferdone => data:{}, copy frame to buffer", data);
-                        let buffer = app
-                            .wlctx
-                            .scm
-                            .base_buffers
-                            .as_mut()
-                            .unwrap()
-                            .get_mut(data)
-                            .unwrap()
-                            .wl_buffer();
-                        proxy.copy(buffer);
+                        if !app.wlctx.has_output(*data) {
+                            debug!("output {} was removed, drop its frame", data);
+                            proxy.destroy();
+                            return;
+                        }
+                        match app.wlctx.attach_copy_buffer(*data, &UserTarget::Shot) {
+                            Some(buffer) => proxy.copy(&buffer),
+                            None => {
+                                warn!("no usable buffer type for output {}", data);
+                                app.action = Action::Exit;
+                            }
+                        }
                     }
                     #[allow(unused)]
                     zwlr_screencopy_frame_v1::Event::Ready {
@@ -87,68 +79,103 @@ impl Dispatch<zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1, usize> for FoamSh
                         // let timestamp = (seconds * 1_000_000_000) + tv_nsec as u64;
                         // trace!("data:{}, timestamp:{} frame ready", timestamp, data);
                         proxy.destroy();
-                        app.wlctx.scm.copy_ready += 1;
+                        app.wlctx.scm.mark_ready(*data);
                     }
                     zwlr_screencopy_frame_v1::Event::Failed => {
+                        proxy.destroy();
+                        // NOTE: 输出在 copy 过程中被移除，忽略这一帧
+                        if !app.wlctx.has_output(*data) {
+                            debug!("output {} was removed, drop its frame", data);
+                            return;
+                        }
                         warn!("buffer copy error");
                         app.action = Action::Exit;
                     }
                     _ => (),
                 }
             }
-            UserTarget::Recorder => {
-                // match event {
-                //     #[allow(unused)]
-                //     zwlr_screencopy_frame_v1::Event::LinuxDmabuf {
-                //         format,
-                //         width,
-                //         height,
-                //     } => {
-                //         let bytes = format.to_le_bytes();
-                //         let f = match &bytes {
-                //             b"XR24" => Format::Xbgr8888,
-                //             b"AR24" => Format::Argb8888,
-                //             _ => {
-                //                 println!("Unsupported LinuxDmabuf format: {:?}", bytes);
-                //                 std::process::exit(-1);
-                //             }
-                //         };
-                //     }
-                //     zwlr_screencopy_frame_v1::Event::Buffer {
-                //         format,
-                //         width,
-                //         height,
-                //         stride,
-                //     } => {
-                //         app.wlctx.fq.new_buffer(
-                //             width as i32,
-                //             height as i32,
-                //             stride as i32,
-                //             format.into_result().unwrap(),
-                //         );
-                //     }
-                //     zwlr_screencopy_frame_v1::Event::BufferDone => {
-                //         let buffer = app.wlctx.fq.current_buffer.as_mut().unwrap();
-                //         proxy.copy(buffer.wl_buffer());
-                //     }
-                //     zwlr_screencopy_frame_v1::Event::Ready {
-                //         tv_sec_hi, // 时间戳的秒数（高32位）
-                //         tv_sec_lo, // 时间戳的秒数（低32位）
-                //         tv_nsec,   // 时间戳中的纳秒部分
-                //     } => {
-                //         let seconds: u64 = ((tv_sec_hi as u64) << 32) | (tv_sec_lo as u64);
-                //         // 转换为精确时间戳
-                //         let timestamp = (seconds * 1_000_000_000) + tv_nsec as u64;
-                //         app.wlctx.fq.storage_canvas(timestamp);
-                //         proxy.destroy();
-                //     }
-                //     zwlr_screencopy_frame_v1::Event::Failed => {
-                //         warn!("buffer copy error");
-                //         app.action = Action::Exit;
-                //     }
-                //     _ => (),
-                // }
-            }
+            UserTarget::Recorder => match event {
+                zwlr_screencopy_frame_v1::Event::Buffer {
+                    format,
+                    width,
+                    height,
+                    stride,
+                } => {
+                    app.wlctx.scm.frame_formats.entry(*data).or_default().shm = Some((
+                        width as i32,
+                        height as i32,
+                        stride as i32,
+                        format.into_result().unwrap(),
+                    ));
+                }
+                zwlr_screencopy_frame_v1::Event::LinuxDmabuf {
+                    format,
+                    width,
+                    height,
+                } => {
+                    app.wlctx.scm.frame_formats.entry(*data).or_default().dmabuf =
+                        Some((format, width as i32, height as i32));
+                }
+                zwlr_screencopy_frame_v1::Event::Flags { flags } => {
+                    if let Ok(flags) = flags.into_result() {
+                        app.wlctx.fq.transform.y_invert =
+                            flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert);
+                    }
+                }
+                zwlr_screencopy_frame_v1::Event::BufferDone => {
+                    if !app.wlctx.has_output(*data) {
+                        debug!("output {} was removed, stop recording", data);
+                        proxy.destroy();
+                        app.wlctx.fq.in_flight = false;
+                        if app.action == Action::OnRecorder {
+                            app.action = Action::Output;
+                        }
+                        return;
+                    }
+                    match app.wlctx.attach_copy_buffer(*data, &UserTarget::Recorder) {
+                        Some(buffer) => proxy.copy(&buffer),
+                        None => {
+                            warn!("no usable buffer type for output {}", data);
+                            proxy.destroy();
+                            app.wlctx.fq.in_flight = false;
+                            app.action = Action::Exit;
+                        }
+                    }
+                }
+                zwlr_screencopy_frame_v1::Event::Ready {
+                    tv_sec_hi, // 时间戳的秒数（高32位）
+                    tv_sec_lo, // 时间戳的秒数（低32位）
+                    tv_nsec,   // 时间戳中的纳秒部分
+                } => {
+                    app.wlctx.fq.in_flight = false;
+                    let seconds: u64 = ((tv_sec_hi as u64) << 32) | (tv_sec_lo as u64);
+                    // 转换为精确时间戳
+                    let timestamp = (seconds * 1_000_000_000) + tv_nsec as u64;
+                    if let Err(e) = app.wlctx.fq.storage_canvas(timestamp) {
+                        warn!("failed to store recorded frame: {}", e);
+                        app.action = Action::Exit;
+                    }
+                    proxy.destroy();
+                }
+                zwlr_screencopy_frame_v1::Event::Failed => {
+                    proxy.destroy();
+                    app.wlctx.fq.in_flight = false;
+                    // 已停止录制时只丢弃这一帧
+                    if app.action != Action::OnRecorder {
+                        debug!("drop the in-flight frame after recording stopped");
+                        return;
+                    }
+                    // NOTE: 录制中的输出被移除，保存已录制的帧
+                    if !app.wlctx.has_output(*data) {
+                        debug!("output {} was removed, stop recording", data);
+                        app.action = Action::Output;
+                        return;
+                    }
+                    warn!("buffer copy error");
+                    app.action = Action::Exit;
+                }
+                _ => (),
+            },
         }
     }
 }
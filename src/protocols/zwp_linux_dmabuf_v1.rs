@@ -0,0 +1,56 @@
+//! INFO: zwp_linux_dmabuf_v1 && zwp_linux_buffer_params_v1 interface implementation
+//! Used to create dmabuf wl_buffers for screencopy
+
+use log::*;
+use wayland_client::{Dispatch, Proxy, protocol::wl_buffer};
+use wayland_protocols::wp::linux_dmabuf::zv1::client::{
+    zwp_linux_buffer_params_v1, zwp_linux_dmabuf_v1,
+};
+
+use crate::{action::Action, foamcore::FoamShot};
+
+// NOTE: unused, udmabuf 只使用线性布局，不需要格式与 modifier 列表
+#[allow(unused_variables)]
+impl Dispatch<zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1, ()> for FoamShot {
+    fn event(
+        app: &mut Self,
+        proxy: &zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
+        event: <zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1 as Proxy>::Event,
+        data: &(),
+        conn: &wayland_client::Connection,
+        qh: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
+#[allow(unused_variables)]
+impl Dispatch<zwp_linux_buffer_params_v1::ZwpLinuxBufferParamsV1, ()> for FoamShot {
+    fn event(
+        app: &mut Self,
+        proxy: &zwp_linux_buffer_params_v1::ZwpLinuxBufferParamsV1,
+        event: <zwp_linux_buffer_params_v1::ZwpLinuxBufferParamsV1 as Proxy>::Event,
+        data: &(),
+        conn: &wayland_client::Connection,
+        qh: &wayland_client::QueueHandle<Self>,
+    ) {
+        // NOTE: create_immed 失败时 copy 无法继续
+        if let zwp_linux_buffer_params_v1::Event::Failed = event {
+            warn!("compositor failed to import udmabuf, try again without --dmabuf");
+            app.action = Action::Exit;
+        }
+    }
+}
+
+// NOTE: unused, dmabuf 只用于一次 copy，不需要 release
+#[allow(unused_variables)]
+impl Dispatch<wl_buffer::WlBuffer, ()> for FoamShot {
+    fn event(
+        app: &mut Self,
+        proxy: &wl_buffer::WlBuffer,
+        event: <wl_buffer::WlBuffer as Proxy>::Event,
+        data: &(),
+        conn: &wayland_client::Connection,
+        qh: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
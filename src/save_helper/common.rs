@@ -1,157 +1,352 @@
-use crate::monitors::FoamMonitors;
+use crate::config::ScalePolicy;
+use crate::pixel_format;
 use crate::wayland_ctx::WaylandCtx;
+use crate::zwlr_screencopy_mode::{CanvasInfo, SingleCanvas};
+use log::warn;
 use std::error::Error;
 
 // 捕获区域信息结构体
 pub struct CaptureInfo {
-    pub min_x: i32,
-    pub min_y: i32,
+    /// 画布左上角的全局逻辑坐标
+    pub min_x: f64,
+    pub min_y: f64,
+    /// 画布像素与逻辑坐标的比例
+    pub scale: f64,
     pub total_width: u32,
     pub total_height: u32,
     pub monitor_ids: Vec<usize>,
+    /// 每个输出的子区域在最终画布上的位置与尺寸，与 `monitor_ids` 一一对应
+    pub placements: Vec<Placement>,
+}
+
+/// 子区域在最终画布上的位置与尺寸，尺寸与子区域不同时进行缩放
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
 }
 
 /// 计算捕获区域信息
+/// 各输出的子矩形为自身 buffer 上的物理坐标，按全局逻辑坐标排布后统一缩放到 `--scale-policy` 给出的比例
+/// 缩放与合成比例相同的输出按原尺寸复制，其余输出进行缩放
 pub fn calculate_capture_info(wl_ctx: &WaylandCtx) -> Result<Option<CaptureInfo>, Box<dyn Error>> {
     let outputs = wl_ctx.foam_outputs.as_ref().ok_or("未初始化输出设备")?;
 
-    let (bounds, active_info): (Option<(i32, i32, i32, i32)>, Vec<_>) = outputs
+    // (id, 子矩形, 输出缩放, 子矩形左上角的全局逻辑坐标)
+    let active: Vec<_> = outputs
         .iter()
-        .filter_map(|output| {
-            output.subrect.as_ref().map(|rect| {
-                let global_x = output.global_x + rect.relative_min_x;
-                let global_y = output.global_y + rect.relative_min_y;
-                let right = global_x + rect.width;
-                let bottom = global_y + rect.height;
-
-                (
-                    (global_x, global_y, right, bottom),
-                    (rect.monitor_id, global_x, global_y),
-                )
-            })
+        .filter_map(|(&id, output)| {
+            let rect = output.subrect.as_ref()?;
+            let scale = output.buffer_scale();
+            let logical_x = output.global_x as f64 + rect.relative_min_x as f64 / scale;
+            let logical_y = output.global_y as f64 + rect.relative_min_y as f64 / scale;
+            Some((id, rect, scale, logical_x, logical_y))
         })
-        .fold(
-            (None, Vec::new()),
-            |(bounds, mut active_info), (rect_bounds, info)| {
-                let bounds = Some(match bounds {
-                    Some((px, py, pr, pb)) => (
-                        px.min(rect_bounds.0),
-                        py.min(rect_bounds.1),
-                        pr.max(rect_bounds.2),
-                        pb.max(rect_bounds.3),
-                    ),
-                    None => rect_bounds,
-                });
-                active_info.push(info);
-                (bounds, active_info)
-            },
-        );
+        .collect();
+    if active.is_empty() {
+        return Ok(None);
+    }
 
-    bounds
-        .map(|(x, y, r, b)| {
-            Ok(Some(CaptureInfo {
-                min_x: x,
-                min_y: y,
-                total_width: (r - x) as u32,
-                total_height: (b - y) as u32,
-                monitor_ids: active_info.iter().map(|(id, _, _)| *id).collect(),
-            }))
-        })
-        .unwrap_or(Ok(None))
-}
+    let canvas_scale = match (wl_ctx.config.all_outputs_scale, wl_ctx.config.scale_policy) {
+        (Some(scale), _) => scale,
+        (None, ScalePolicy::Max) => active
+            .iter()
+            .map(|&(_, _, scale, ..)| scale)
+            .fold(1.0, f64::max),
+        (None, ScalePolicy::Logical) => 1.0,
+    };
+    let min_x = active.iter().map(|a| a.3).fold(f64::INFINITY, f64::min);
+    let min_y = active.iter().map(|a| a.4).fold(f64::INFINITY, f64::min);
 
-/// 创建透明背景的最终画布
-pub fn create_final_surface(
-    width: u32,
-    height: u32,
-) -> Result<cairo::ImageSurface, Box<dyn Error>> {
-    let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width as i32, height as i32)?;
+    let mut placements = Vec::with_capacity(active.len());
+    let (mut total_width, mut total_height) = (0, 0);
+    for &(_, rect, scale, logical_x, logical_y) in &active {
+        let ratio = canvas_scale / scale;
+        let placement = Placement {
+            x: ((logical_x - min_x) * canvas_scale).round() as i32,
+            y: ((logical_y - min_y) * canvas_scale).round() as i32,
+            width: (rect.width as f64 * ratio).round().max(1.0) as i32,
+            height: (rect.height as f64 * ratio).round().max(1.0) as i32,
+        };
+        total_width = total_width.max(placement.x + placement.width);
+        total_height = total_height.max(placement.y + placement.height);
+        placements.push(placement);
+    }
 
-    let ctx = cairo::Context::new(&surface)?;
-    ctx.set_source_rgba(0.0, 0.0, 0.0, 0.0);
-    ctx.paint()?;
+    Ok(Some(CaptureInfo {
+        min_x,
+        min_y,
+        scale: canvas_scale,
+        total_width: total_width as u32,
+        total_height: total_height as u32,
+        monitor_ids: active.iter().map(|a| a.0).collect(),
+        placements,
+    }))
+}
 
-    Ok(surface)
+/// 一个输出的合成步骤：画布上 `source` 处的子区域绘制到最终画布的 `placement` 上
+#[derive(Debug, Clone, Copy)]
+pub struct CompositeStep {
+    pub id: usize,
+    /// 选区在该输出画布内的像素坐标 (x, y, width, height)
+    pub source: (i32, i32, i32, i32),
+    pub placement: Placement,
 }
 
-/// 处理所有显示器输出
-/// 修改最终传入的final_surface
-pub fn process_all_outputs(
-    wl_ctx: &mut WaylandCtx,
-    capture_info: &CaptureInfo,
-    final_surface: &cairo::ImageSurface,
-) -> Result<(), Box<dyn Error>> {
-    let outputs = wl_ctx.foam_outputs.as_mut().ok_or("输出设备未初始化")?;
+/// 多输出的合成计划，8 位与 16 位的输出共用，保证两者的排布、缩放与裁剪一致
+#[derive(Debug)]
+pub struct CompositePlan {
+    pub width: u32,
+    pub height: u32,
+    pub steps: Vec<CompositeStep>,
+}
+
+/// 由各输出的选区与画布计算合成计划，并检查选区没有超出输出与画布
+/// `origin` 为画布左上角在输出上的物理坐标，区域捕获时画布只包含输出的一部分
+pub fn plan_composite(wl_ctx: &WaylandCtx) -> Result<CompositePlan, Box<dyn Error>> {
+    let capture_info = match calculate_capture_info(wl_ctx)? {
+        Some(info) => info,
+        None => {
+            warn!("未找到有效截图区域");
+            return Err("未找到有效截图区域".into());
+        }
+    };
+    let outputs = wl_ctx.foam_outputs.as_ref().ok_or("输出设备未初始化")?;
 
-    for &id in &capture_info.monitor_ids {
+    let mut steps = Vec::with_capacity(capture_info.placements.len());
+    for (&id, placement) in capture_info
+        .monitor_ids
+        .iter()
+        .zip(&capture_info.placements)
+    {
         let output = outputs
-            .get_mut(id)
+            .get(&id)
             .ok_or_else(|| format!("显示器{}不存在", id))?;
+        let rect = output
+            .subrect
+            .as_ref()
+            .ok_or_else(|| format!("显示器{}没有设置子区域", output.id))?;
+        if rect.relative_min_x + rect.width > output.width
+            || rect.relative_min_y + rect.height > output.height
+        {
+            return Err(format!(
+                "子区域超出边界 (显示器{}: {}x{}, 子区域: {}x{} @ ({},{}))",
+                output.id,
+                output.width,
+                output.height,
+                rect.width,
+                rect.height,
+                rect.relative_min_x,
+                rect.relative_min_y
+            )
+            .into());
+        }
+
+        let info = wl_ctx
+            .scm
+            .canvas_info
+            .get(&id)
+            .ok_or_else(|| format!("显示器{}的画布未初始化", id))?;
+        let origin = wl_ctx.scm.canvas_origin.get(&id).copied().unwrap_or((0, 0));
+        // 选区在画布内的坐标
+        let x = rect.relative_min_x - origin.0;
+        let y = rect.relative_min_y - origin.1;
+        if x < 0 || y < 0 || x + rect.width > info.width || y + rect.height > info.height {
+            return Err(format!(
+                "子区域超出画布 (显示器{}: 画布 {}x{} @ ({},{}), 子区域: {}x{} @ ({},{}))",
+                output.id,
+                info.width,
+                info.height,
+                origin.0,
+                origin.1,
+                rect.width,
+                rect.height,
+                rect.relative_min_x,
+                rect.relative_min_y
+            )
+            .into());
+        }
 
-        process_single_output(
-            output,
-            wl_ctx
-                .scm
-                .base_canvas
-                .as_mut()
-                .unwrap()
-                .get_mut(&id)
-                .unwrap(),
-            capture_info,
-            final_surface,
-        )?;
+        steps.push(CompositeStep {
+            id,
+            source: (x, y, rect.width, rect.height),
+            placement: *placement,
+        });
     }
 
-    Ok(())
+    Ok(CompositePlan {
+        width: capture_info.total_width,
+        height: capture_info.total_height,
+        steps,
+    })
 }
 
-/// 处理单个显示器输出
-pub fn process_single_output(
-    output: &mut FoamMonitors,
-    base_canvas: &mut [u8],
-    capture_info: &CaptureInfo,
-    final_surface: &cairo::ImageSurface,
-) -> Result<(), Box<dyn Error>> {
-    let rect = output
-        .subrect
-        .as_ref()
-        .ok_or_else(|| format!("显示器{}没有设置子区域", output.id))?;
+/// 生成最终输出的画布
+/// 存在单张画布（窗口捕获）时直接使用，否则按合成计划合成各个输出上的子区域
+/// 画布在 `storage_copy_canvas` 中已按 transform 与 y_invert 转为正向，与选区坐标一致
+pub fn build_final_surface(wl_ctx: &mut WaylandCtx) -> Result<cairo::ImageSurface, Box<dyn Error>> {
+    if let Some(single) = wl_ctx.single_canvas.as_ref() {
+        return create_surface_from_single(single);
+    }
+
+    let plan = plan_composite(wl_ctx)?;
+    let final_surface = create_final_surface(plan.width, plan.height, wl_ctx.config.background)?;
+    let canvases = wl_ctx.scm.base_canvas.as_mut().ok_or("画布未初始化")?;
+    for step in &plan.steps {
+        let canvas = canvases
+            .get_mut(&step.id)
+            .ok_or_else(|| format!("显示器{}的画布未初始化", step.id))?;
+        let info = wl_ctx
+            .scm
+            .canvas_info
+            .get(&step.id)
+            .ok_or_else(|| format!("显示器{}的画布未初始化", step.id))?;
+        paint_step(&final_surface, canvas, info, step)?;
+    }
+
+    Ok(final_surface)
+}
+
+/// 将单张画布转换为 `Argb8888` 后复制到新的 cairo surface
+pub fn create_surface_from_single(
+    single: &SingleCanvas,
+) -> Result<cairo::ImageSurface, Box<dyn Error>> {
+    let (data, info) = pixel_format::to_argb8888(single.data.clone(), single.info)?;
 
-    if rect.relative_min_x + rect.width > output.width
-        || rect.relative_min_y + rect.height > output.height
+    let mut surface = cairo::ImageSurface::create(cairo::Format::ARgb32, info.width, info.height)?;
+    let dst_stride = surface.stride() as usize;
     {
-        return Err(format!(
-            "子区域超出边界 (显示器{}: {}x{}, 子区域: {}x{} @ ({},{}))",
-            output.id,
-            output.width,
-            output.height,
-            rect.width,
-            rect.height,
-            rect.relative_min_x,
-            rect.relative_min_y
-        )
-        .into());
+        let mut dst_data = surface.data()?;
+        let row_len = info.width as usize * 4;
+        for y in 0..info.height as usize {
+            let src = &data[y * info.stride as usize..][..row_len];
+            dst_data[y * dst_stride..][..row_len].copy_from_slice(src);
+        }
     }
+    Ok(surface)
+}
 
-    // let canvas = output
-    //     .base_canvas
-    //     .as_mut()
-    //     .ok_or_else(|| format!("显示器{}的画布未初始化", output.id))?;
+/// 生成 16 位 RGBA 的最终图像，保留 10 位格式的精度
+/// 与 `build_final_surface` 相同，单张画布优先，否则按同一个合成计划合成各个输出上的子区域
+pub fn build_final_rgba16(wl_ctx: &WaylandCtx) -> Result<Rgba16Image, Box<dyn Error>> {
+    if let Some(single) = wl_ctx.single_canvas.as_ref() {
+        let (w, h) = (single.info.width as usize, single.info.height as usize);
+        let mut buf = vec![0u16; w * h * 4];
+        pixel_format::decode_rgba16(&single.data, &single.info, 0, 0, w, h, &mut buf, w * 4)?;
+        return image::ImageBuffer::from_raw(w as u32, h as u32, buf)
+            .ok_or_else(|| "创建16位图像失败".into());
+    }
 
-    let dest_x = (output.global_x + rect.relative_min_x) - capture_info.min_x;
-    let dest_y = (output.global_y + rect.relative_min_y) - capture_info.min_y;
+    let plan = plan_composite(wl_ctx)?;
+    let background = wl_ctx
+        .config
+        .background
+        .unwrap_or_default()
+        .map(|c| c as u16 * 257);
+    let mut image =
+        image::ImageBuffer::from_pixel(plan.width, plan.height, image::Rgba(background));
+    for step in &plan.steps {
+        let deep = wl_ctx
+            .scm
+            .deep_canvas
+            .get(&step.id)
+            .ok_or_else(|| format!("显示器{}的画布未初始化", step.id))?;
+        paint_step_rgba16(&mut image, deep, step)?;
+    }
+    Ok(image)
+}
 
-    let sub_surface = create_sub_surface(
-        base_canvas,
-        output.width,
-        rect.relative_min_x,
-        rect.relative_min_y,
-        rect.width,
-        rect.height,
+pub type Rgba16Image = image::ImageBuffer<image::Rgba<u16>, Vec<u16>>;
+
+/// 16 位路径的 `paint_step`，尺寸不同时缩放，只写入 placement 与画布相交的部分
+fn paint_step_rgba16(
+    image: &mut Rgba16Image,
+    canvas: &SingleCanvas,
+    step: &CompositeStep,
+) -> Result<(), Box<dyn Error>> {
+    let (x, y, w, h) = step.source;
+    let (w, h) = (w as usize, h as usize);
+    let mut sub = vec![0u16; w * h * 4];
+    pixel_format::decode_rgba16(
+        &canvas.data,
+        &canvas.info,
+        x as usize,
+        y as usize,
+        w,
+        h,
+        &mut sub,
+        w * 4,
     )?;
+    let sub: Rgba16Image =
+        image::ImageBuffer::from_raw(w as u32, h as u32, sub).ok_or("创建16位图像失败")?;
+    let placement = &step.placement;
+    let sub = if placement.width as usize == w && placement.height as usize == h {
+        sub
+    } else {
+        image::imageops::resize(
+            &sub,
+            placement.width as u32,
+            placement.height as u32,
+            image::imageops::FilterType::Triangle,
+        )
+    };
+    image::imageops::replace(image, &sub, placement.x as i64, placement.y as i64);
+    Ok(())
+}
+
+/// 创建最终画布，`background` 为输出之间空隙的填充色，None 时透明
+pub fn create_final_surface(
+    width: u32,
+    height: u32,
+    background: Option<[u8; 4]>,
+) -> Result<cairo::ImageSurface, Box<dyn Error>> {
+    let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width as i32, height as i32)?;
+
+    let ctx = cairo::Context::new(&surface)?;
+    let [r, g, b, a] = background.unwrap_or_default().map(|c| c as f64 / 255.0);
+    ctx.set_source_rgba(r, g, b, a);
+    ctx.paint()?;
+
+    Ok(surface)
+}
 
+/// 按合成步骤将 `Argb8888` 画布上的子区域绘制到最终画布
+fn paint_step(
+    final_surface: &cairo::ImageSurface,
+    canvas: &mut [u8],
+    info: &CanvasInfo,
+    step: &CompositeStep,
+) -> Result<(), Box<dyn Error>> {
+    let (x, y, width, height) = step.source;
+    let sub_surface = create_sub_surface(canvas, info.stride, x, y, width, height)?;
+    paint_placement(final_surface, &sub_surface, width, height, &step.placement)
+}
+
+/// 将 `width`x`height` 的子区域绘制到最终画布的 `placement` 上，尺寸不同时缩放
+/// NOTE: 总是裁剪到 placement，否则 Pad 会把边缘像素铺满整个画布，覆盖背景与其他输出
+fn paint_placement(
+    final_surface: &cairo::ImageSurface,
+    sub_surface: &cairo::ImageSurface,
+    width: i32,
+    height: i32,
+    placement: &Placement,
+) -> Result<(), Box<dyn Error>> {
     let ctx = cairo::Context::new(final_surface)?;
-    ctx.set_source_surface(&sub_surface, dest_x as f64, dest_y as f64)?;
+    ctx.translate(placement.x as f64, placement.y as f64);
+    if placement.width != width || placement.height != height {
+        ctx.scale(
+            placement.width as f64 / width as f64,
+            placement.height as f64 / height as f64,
+        );
+    }
+    ctx.rectangle(0.0, 0.0, width as f64, height as f64);
+    ctx.clip();
+    ctx.set_source_surface(sub_surface, 0.0, 0.0)?;
+    // Pad 避免缩放时边缘与背景混合
+    ctx.source().set_extend(cairo::Extend::Pad);
+    ctx.source().set_filter(cairo::Filter::Good);
     ctx.paint()?;
 
     Ok(())
@@ -160,13 +355,13 @@ pub fn process_single_output(
 /// 创建子区域surface
 pub fn create_sub_surface(
     canvas: &mut [u8],
-    output_width: i32,
+    stride: i32,
     x: i32,
     y: i32,
     width: i32,
     height: i32,
 ) -> Result<cairo::ImageSurface, Box<dyn Error>> {
-    let stride = output_width as usize * 4;
+    let stride = stride as usize;
     let start_offset = (y as usize * stride) + (x as usize * 4);
 
     let surface = unsafe {
@@ -181,3 +376,238 @@ pub fn create_sub_surface(
 
     Ok(surface)
 }
+
+/// 将 cairo surface 转换为紧密排列的 RGB 数据，忽略 alpha
+pub fn surface_to_rgb8(
+    surface: &mut cairo::ImageSurface,
+) -> Result<(u32, u32, Vec<u8>), Box<dyn Error>> {
+    let width = surface.width() as usize;
+    let height = surface.height() as usize;
+    let stride = surface.stride() as usize;
+    let data = surface.data()?;
+
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    for row in data.chunks(stride).take(height) {
+        // Cairo ARGB32格式：[B, G, R, A]
+        for px in row[..width * 4].chunks_exact(4) {
+            rgb.extend_from_slice(&[px[2], px[1], px[0]]);
+        }
+    }
+    Ok((width as u32, height as u32, rgb))
+}
+
+/// 将 cairo surface 转换为紧密排列的 RGBA 数据
+/// NOTE: cairo 使用预乘 alpha，这里还原为直通 alpha
+pub fn surface_to_rgba8(
+    surface: &mut cairo::ImageSurface,
+) -> Result<(u32, u32, Vec<u8>), Box<dyn Error>> {
+    let width = surface.width() as usize;
+    let height = surface.height() as usize;
+    let stride = surface.stride() as usize;
+    let data = surface.data()?;
+
+    let unpremultiply = |c: u8, a: u8| ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8;
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for row in data.chunks(stride).take(height) {
+        for px in row[..width * 4].chunks_exact(4) {
+            let a = px[3];
+            if a == 0 {
+                rgba.extend_from_slice(&[0, 0, 0, 0]);
+            } else {
+                rgba.extend_from_slice(&[
+                    unpremultiply(px[2], a),
+                    unpremultiply(px[1], a),
+                    unpremultiply(px[0], a),
+                    a,
+                ]);
+            }
+        }
+    }
+    Ok((width as u32, height as u32, rgba))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 纯色的 ARgb32 surface
+    fn solid(width: i32, height: i32, [r, g, b]: [f64; 3]) -> cairo::ImageSurface {
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height).unwrap();
+        let ctx = cairo::Context::new(&surface).unwrap();
+        ctx.set_source_rgb(r, g, b);
+        ctx.paint().unwrap();
+        drop(ctx);
+        surface
+    }
+
+    /// 返回 (x, y) 处的 [B, G, R, A]
+    fn pixel(surface: &mut cairo::ImageSurface, x: usize, y: usize) -> [u8; 4] {
+        surface.flush();
+        let stride = surface.stride() as usize;
+        let data = surface.data().unwrap();
+        let offset = y * stride + x * 4;
+        data[offset..offset + 4].try_into().unwrap()
+    }
+
+    const RED: [u8; 4] = [0, 0, 255, 255];
+    const BLUE: [u8; 4] = [255, 0, 0, 255];
+    const GRAY: [u8; 4] = [0x20, 0x20, 0x20, 0xff];
+
+    #[test]
+    fn two_outputs_keep_background_and_each_other() {
+        // 两个 2x2 的输出之间留 2 像素空隙
+        let mut final_surface = create_final_surface(6, 3, Some([0x20, 0x20, 0x20, 0xff])).unwrap();
+        let left = solid(2, 2, [1.0, 0.0, 0.0]);
+        let right = solid(2, 2, [0.0, 0.0, 1.0]);
+        let place = |x| Placement {
+            x,
+            y: 0,
+            width: 2,
+            height: 2,
+        };
+        paint_placement(&final_surface, &left, 2, 2, &place(0)).unwrap();
+        paint_placement(&final_surface, &right, 2, 2, &place(4)).unwrap();
+
+        for y in 0..2 {
+            assert_eq!(pixel(&mut final_surface, 0, y), RED);
+            assert_eq!(pixel(&mut final_surface, 1, y), RED);
+            assert_eq!(pixel(&mut final_surface, 2, y), GRAY);
+            assert_eq!(pixel(&mut final_surface, 3, y), GRAY);
+            assert_eq!(pixel(&mut final_surface, 4, y), BLUE);
+            assert_eq!(pixel(&mut final_surface, 5, y), BLUE);
+        }
+        for x in 0..6 {
+            assert_eq!(pixel(&mut final_surface, x, 2), GRAY);
+        }
+    }
+
+    #[test]
+    fn scaled_output_stays_inside_placement() {
+        let mut final_surface = create_final_surface(6, 4, Some([0x20, 0x20, 0x20, 0xff])).unwrap();
+        let low = solid(2, 2, [1.0, 0.0, 0.0]);
+        let high = solid(2, 2, [0.0, 0.0, 1.0]);
+        // 1x 的输出放大到 4x4，2x 的输出按原尺寸放在右侧
+        let scaled = Placement {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 4,
+        };
+        let native = Placement {
+            x: 4,
+            y: 0,
+            width: 2,
+            height: 2,
+        };
+        paint_placement(&final_surface, &low, 2, 2, &scaled).unwrap();
+        paint_placement(&final_surface, &high, 2, 2, &native).unwrap();
+
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(pixel(&mut final_surface, x, y), RED);
+            }
+        }
+        assert_eq!(pixel(&mut final_surface, 4, 0), BLUE);
+        assert_eq!(pixel(&mut final_surface, 5, 1), BLUE);
+        assert_eq!(pixel(&mut final_surface, 4, 2), GRAY);
+        assert_eq!(pixel(&mut final_surface, 5, 3), GRAY);
+    }
+
+    /// `width`x`height` 的 Argb8888 画布，`rect` (x, y, w, h) 内为 `inner`，其余为 `outer`
+    fn argb_canvas(
+        width: i32,
+        height: i32,
+        outer: [u8; 4],
+        inner: [u8; 4],
+        rect: (i32, i32, i32, i32),
+    ) -> (Vec<u8>, CanvasInfo) {
+        let info = CanvasInfo {
+            width,
+            height,
+            stride: width * 4,
+            format: wayland_client::protocol::wl_shm::Format::Argb8888,
+        };
+        let (rx, ry, rw, rh) = rect;
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let inside = x >= rx && x < rx + rw && y >= ry && y < ry + rh;
+                data.extend_from_slice(if inside { &inner } else { &outer });
+            }
+        }
+        (data, info)
+    }
+
+    #[test]
+    fn eight_and_sixteen_bit_paths_agree_on_mixed_scale_layout() {
+        const GREEN: [u8; 4] = [0, 255, 0, 255];
+        // 1x 的输出放大到 4x4，2x 的输出只取画布内 (1,1) 处的 2x2 蓝色选区，
+        // 原尺寸放在 (6,1)，最终画布宽 7，右侧一列被裁掉
+        let (mut low, low_info) = argb_canvas(2, 2, RED, RED, (0, 0, 0, 0));
+        let (mut high, high_info) = argb_canvas(4, 3, GREEN, BLUE, (1, 1, 2, 2));
+        let steps = [
+            CompositeStep {
+                id: 0,
+                source: (0, 0, 2, 2),
+                placement: Placement {
+                    x: 0,
+                    y: 0,
+                    width: 4,
+                    height: 4,
+                },
+            },
+            CompositeStep {
+                id: 1,
+                source: (1, 1, 2, 2),
+                placement: Placement {
+                    x: 6,
+                    y: 1,
+                    width: 2,
+                    height: 2,
+                },
+            },
+        ];
+        let (width, height) = (7, 5);
+
+        let mut surface =
+            create_final_surface(width, height, Some([0x20, 0x20, 0x20, 0xff])).unwrap();
+        let deep: Vec<SingleCanvas> = [(&low, low_info), (&high, high_info)]
+            .into_iter()
+            .map(|(data, info)| SingleCanvas {
+                data: data.clone(),
+                info,
+            })
+            .collect();
+        paint_step(&surface, &mut low, &low_info, &steps[0]).unwrap();
+        paint_step(&surface, &mut high, &high_info, &steps[1]).unwrap();
+        let (_, _, rgba8) = surface_to_rgba8(&mut surface).unwrap();
+
+        let mut image = image::ImageBuffer::from_pixel(
+            width,
+            height,
+            image::Rgba([0x2020, 0x2020, 0x2020, 0xffff]),
+        );
+        for step in &steps {
+            paint_step_rgba16(&mut image, &deep[step.id], step).unwrap();
+        }
+        let rgba16: Vec<u8> = image
+            .into_raw()
+            .into_iter()
+            .map(|c| (c >> 8) as u8)
+            .collect();
+
+        assert_eq!(rgba8, rgba16);
+        let at = |x: u32, y: u32| -> [u8; 4] {
+            let i = ((y * width + x) * 4) as usize;
+            rgba8[i..i + 4].try_into().unwrap()
+        };
+        assert_eq!(at(0, 0), [255, 0, 0, 255]);
+        assert_eq!(at(3, 3), [255, 0, 0, 255]);
+        assert_eq!(at(4, 0), [0x20, 0x20, 0x20, 0xff]);
+        assert_eq!(at(6, 0), [0x20, 0x20, 0x20, 0xff]);
+        assert_eq!(at(6, 1), [0, 0, 255, 255]);
+        assert_eq!(at(6, 2), [0, 0, 255, 255]);
+        assert_eq!(at(6, 3), [0x20, 0x20, 0x20, 0xff]);
+        assert_eq!(at(0, 4), [0x20, 0x20, 0x20, 0xff]);
+    }
+}
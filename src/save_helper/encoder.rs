@@ -0,0 +1,195 @@
+//! INFO: Registry of the image encoders, selected by the output extension or `--format`
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::{config::ImageType, wayland_ctx::WaylandCtx};
+
+use super::{bmp, jpg, png, pnm, qoi, webp};
+
+/// 将最终画布编码后写入 writer
+pub type EncodeFn = fn(&mut WaylandCtx, &mut dyn Write) -> Result<(), Box<dyn Error>>;
+
+/// NOTE: 新的格式在 `ENCODERS` 中注册，并在 `ImageType` 中添加对应的变体
+pub struct Encoder {
+    pub image_type: ImageType,
+    /// `--format` 使用的名称
+    pub name: &'static str,
+    /// 复制到剪贴板时使用的 mime 类型
+    pub mime: &'static str,
+    /// 识别的后缀，第一个为 `--format` 替换后缀时使用的后缀
+    pub extensions: &'static [&'static str],
+    /// 是否支持有损编码，只有这些格式接受 `--quality`
+    pub lossy: bool,
+    /// None 表示只用于录制
+    pub encode: Option<EncodeFn>,
+}
+
+pub const ENCODERS: &[Encoder] = &[
+    Encoder {
+        image_type: ImageType::Png,
+        name: "png",
+        mime: "image/png",
+        lossy: false,
+        extensions: &["png"],
+        encode: Some(png::encode_png),
+    },
+    Encoder {
+        image_type: ImageType::Jpg,
+        name: "jpg",
+        mime: "image/jpeg",
+        lossy: true,
+        extensions: &["jpg", "jpeg"],
+        encode: Some(jpg::encode_jpg),
+    },
+    Encoder {
+        image_type: ImageType::Webp,
+        name: "webp",
+        mime: "image/webp",
+        lossy: true,
+        extensions: &["webp"],
+        encode: Some(webp::encode_webp),
+    },
+    Encoder {
+        image_type: ImageType::Qoi,
+        name: "qoi",
+        mime: "image/qoi",
+        lossy: false,
+        extensions: &["qoi"],
+        encode: Some(qoi::encode_qoi),
+    },
+    Encoder {
+        image_type: ImageType::Bmp,
+        name: "bmp",
+        mime: "image/bmp",
+        lossy: false,
+        extensions: &["bmp"],
+        encode: Some(bmp::encode_bmp),
+    },
+    Encoder {
+        image_type: ImageType::Ppm,
+        name: "ppm",
+        mime: "image/x-portable-pixmap",
+        lossy: false,
+        extensions: &["ppm"],
+        encode: Some(pnm::encode_ppm),
+    },
+    Encoder {
+        image_type: ImageType::Pam,
+        name: "pam",
+        mime: "image/x-portable-arbitrarymap",
+        lossy: false,
+        extensions: &["pam"],
+        encode: Some(pnm::encode_pam),
+    },
+    // NOTE: gif 仅在录制模式下输出，见 `FrameQueue::to_gif`
+    Encoder {
+        image_type: ImageType::Gif,
+        name: "gif",
+        mime: "image/gif",
+        lossy: false,
+        extensions: &["gif"],
+        encode: None,
+    },
+];
+
+pub fn by_type(image_type: ImageType) -> &'static Encoder {
+    ENCODERS
+        .iter()
+        .find(|e| e.image_type == image_type)
+        .expect("every image type is registered")
+}
+
+pub fn by_name(name: &str) -> Option<&'static Encoder> {
+    ENCODERS.iter().find(|e| e.name.eq_ignore_ascii_case(name))
+}
+
+pub fn by_extension(ext: &str) -> Option<&'static Encoder> {
+    ENCODERS
+        .iter()
+        .find(|e| e.extensions.iter().any(|x| x.eq_ignore_ascii_case(ext)))
+}
+
+/// 所有格式的名称，用于错误提示
+pub fn names() -> String {
+    ENCODERS
+        .iter()
+        .map(|e| e.name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// 按配置的格式编码并写入 `output_path`，`-o -` 时写入标准输出
+pub fn save_image(wl_ctx: &mut WaylandCtx) -> Result<(), Box<dyn Error>> {
+    let encoder = by_type(wl_ctx.config.image_type);
+    let encode = encoder
+        .encode
+        .ok_or_else(|| format!("{} is only supported with --recorder", encoder.name))?;
+
+    if wl_ctx.config.stdout {
+        let mut writer = BufWriter::new(io::stdout().lock());
+        encode(wl_ctx, &mut writer)?;
+        writer.flush().map_err(|e| format!("刷新输出失败: {}", e))?;
+        return Ok(());
+    }
+    let path = wl_ctx.config.output_path.clone();
+    write_new_file(&path, |writer| encode(wl_ctx, writer))
+}
+
+/// 创建新文件并写入，失败时删除已创建的文件，不留下不完整的图片
+fn write_new_file<F>(path: &Path, write: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), Box<dyn Error>>,
+{
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| format!("创建文件失败: {}", e))?;
+    let mut writer = BufWriter::new(file);
+    let result = write(&mut writer).and_then(|_| {
+        writer
+            .flush()
+            .map_err(|e| format!("刷新文件失败: {}", e).into())
+    });
+    if result.is_err() {
+        drop(writer);
+        std::fs::remove_file(path).ok();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("foamshot-{}-{}", std::process::id(), name));
+        std::fs::remove_file(&path).ok();
+        path
+    }
+
+    #[test]
+    fn failed_encode_removes_file() {
+        let path = temp_path("failed.png");
+        let result = write_new_file(&path, |writer| {
+            writer.write_all(&[0; 64 * 1024])?;
+            Err("encode failed".into())
+        });
+        assert_eq!(result.unwrap_err().to_string(), "encode failed");
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn successful_encode_keeps_file() {
+        let path = temp_path("ok.png");
+        write_new_file(&path, |writer| Ok(writer.write_all(b"image")?)).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"image");
+
+        // 不覆盖已存在的文件，也不删除它
+        assert!(write_new_file(&path, |_| Ok(())).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"image");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,472 @@
+//! INFO: Capture metadata embedded into the saved image: PNG text chunks, JPEG/WebP EXIF and XMP
+use chrono::{DateTime, Local, SecondsFormat};
+
+use crate::wayland_ctx::WaylandCtx;
+
+/// XMP 中 foamshot 自定义字段的命名空间
+const XMP_NAMESPACE: &str = "https://github.com/Thirdwinter/foamshot/ns/1.0/";
+
+/// 截图的来源信息，`--no-metadata` 时不写入
+#[derive(Debug)]
+pub struct CaptureMetadata {
+    pub timestamp: DateTime<Local>,
+    /// 与选区相交的输出名称及其缩放，窗口与 portal 截图时为空
+    pub outputs: Vec<(String, f64)>,
+    /// slurp 格式的全局逻辑坐标选区
+    pub geometry: Option<String>,
+    pub compositor: Option<String>,
+}
+
+enum TiffValue {
+    Ascii(String),
+    Long(u32),
+}
+
+impl CaptureMetadata {
+    pub fn collect(wl_ctx: &WaylandCtx) -> Self {
+        // 窗口与 portal 截图没有选区
+        let selection = wl_ctx
+            .single_canvas
+            .is_none()
+            .then(|| wl_ctx.logical_selection())
+            .flatten();
+        let outputs = match selection {
+            Some(_) => wl_ctx
+                .foam_outputs
+                .as_ref()
+                .map(|outputs| {
+                    outputs
+                        .values()
+                        .filter(|m| m.subrect.is_some())
+                        .map(|m| (m.name.clone(), m.buffer_scale()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+        Self {
+            timestamp: Local::now(),
+            outputs,
+            geometry: selection.map(|(rect, _)| rect.format_geometry("slurp", "", 1.0)),
+            compositor: compositor_name(),
+        }
+    }
+
+    pub fn software() -> String {
+        format!("foamshot {}", env!("CARGO_PKG_VERSION"))
+    }
+
+    fn output_names(&self) -> String {
+        let names: Vec<_> = self.outputs.iter().map(|(name, _)| name.as_str()).collect();
+        names.join(",")
+    }
+
+    fn output_scales(&self) -> String {
+        let scales: Vec<_> = self.outputs.iter().map(|(_, s)| s.to_string()).collect();
+        scales.join(",")
+    }
+
+    /// png 文本块的关键字与内容，`Software` 与 `Creation Time` 为 png 规范中的关键字
+    pub fn text_entries(&self) -> Vec<(&'static str, String)> {
+        let mut entries = vec![
+            ("Software", Self::software()),
+            ("Creation Time", self.timestamp.to_rfc2822()),
+        ];
+        if !self.outputs.is_empty() {
+            entries.push(("Outputs", self.output_names()));
+            entries.push(("Scales", self.output_scales()));
+        }
+        entries.extend(self.geometry.clone().map(|g| ("Geometry", g)));
+        entries.extend(self.compositor.clone().map(|c| ("Compositor", c)));
+        entries
+    }
+
+    /// exif 的 ImageDescription，只能包含 ASCII
+    fn description(&self) -> String {
+        let mut description = String::from("screenshot");
+        if let Some(geometry) = &self.geometry {
+            description.push_str(&format!(" of {}", geometry));
+        }
+        if !self.outputs.is_empty() {
+            description.push_str(&format!(
+                " on {} (scale {})",
+                self.output_names(),
+                self.output_scales()
+            ));
+        }
+        if let Some(compositor) = &self.compositor {
+            description.push_str(&format!(" under {}", compositor));
+        }
+        description
+    }
+
+    /// 小端序的 TIFF 数据，不含 `Exif\0\0` 前缀
+    /// NOTE: IFD0 记录描述、软件与时间，并指向只含拍摄时间的 Exif IFD
+    pub fn exif(&self) -> Vec<u8> {
+        let datetime = self.timestamp.format("%Y:%m:%d %H:%M:%S").to_string();
+        let mut ifd0 = vec![
+            (0x010e, TiffValue::Ascii(self.description())),
+            (0x0131, TiffValue::Ascii(Self::software())),
+            (0x0132, TiffValue::Ascii(datetime.clone())),
+        ];
+        // Exif IFD 紧跟在 IFD0 之后
+        let exif_offset = 8 + ifd_size(ifd0.len() + 1);
+        ifd0.push((0x8769, TiffValue::Long(exif_offset as u32)));
+        let exif_ifd = vec![
+            (0x9003, TiffValue::Ascii(datetime)),
+            (
+                0x9011,
+                TiffValue::Ascii(self.timestamp.format("%:z").to_string()),
+            ),
+        ];
+        write_tiff(&[ifd0, exif_ifd])
+    }
+
+    /// 包含全部字段的 XMP 数据包
+    pub fn xmp(&self) -> String {
+        let mut attributes = vec![
+            (
+                "xmp:CreateDate",
+                self.timestamp.to_rfc3339_opts(SecondsFormat::Secs, false),
+            ),
+            ("xmp:CreatorTool", Self::software()),
+        ];
+        if !self.outputs.is_empty() {
+            attributes.push(("foamshot:Outputs", self.output_names()));
+            attributes.push(("foamshot:Scales", self.output_scales()));
+        }
+        attributes.extend(self.geometry.clone().map(|g| ("foamshot:Geometry", g)));
+        attributes.extend(self.compositor.clone().map(|c| ("foamshot:Compositor", c)));
+
+        let attributes: String = attributes
+            .into_iter()
+            .map(|(key, value)| format!("\n    {}=\"{}\"", key, escape_xml(&value)))
+            .collect();
+        format!(
+            concat!(
+                "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+                "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+                " <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+                "  <rdf:Description rdf:about=\"\"\n",
+                "    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n",
+                "    xmlns:foamshot=\"{}\"{}/>\n",
+                " </rdf:RDF>\n",
+                "</x:xmpmeta>\n",
+                "<?xpacket end=\"r\"?>"
+            ),
+            XMP_NAMESPACE, attributes
+        )
+    }
+
+    /// 在 jpeg 的 SOI 与 JFIF 段之后插入 EXIF 与 XMP 的 APP1 段
+    pub fn insert_into_jpeg(&self, jpeg: &[u8]) -> Result<Vec<u8>, String> {
+        if !jpeg.starts_with(&[0xff, 0xd8]) {
+            return Err("invalid jpeg data".to_string());
+        }
+        // JFIF 规定 APP0 必须紧跟 SOI
+        let mut split = 2;
+        if jpeg.get(2..4) == Some(&[0xff, 0xe0]) && jpeg.len() >= 6 {
+            split += 2 + u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+        }
+
+        let mut exif = b"Exif\0\0".to_vec();
+        exif.extend(self.exif());
+        let mut xmp = b"http://ns.adobe.com/xap/1.0/\0".to_vec();
+        xmp.extend(self.xmp().as_bytes());
+
+        let mut out = Vec::with_capacity(jpeg.len() + exif.len() + xmp.len() + 8);
+        out.extend_from_slice(&jpeg[..split]);
+        for payload in [exif, xmp] {
+            let len = u16::try_from(payload.len() + 2).map_err(|_| "metadata too large")?;
+            out.extend_from_slice(&[0xff, 0xe1]);
+            out.extend_from_slice(&len.to_be_bytes());
+            out.extend(payload);
+        }
+        out.extend_from_slice(&jpeg[split..]);
+        Ok(out)
+    }
+
+    /// 在 libwebp 输出的 webp 末尾追加 EXIF 与 XMP 块
+    /// NOTE: 元数据块需要扩展格式，简单格式的文件在开头补上 VP8X，`width`/`height` 为画布尺寸
+    pub fn insert_into_webp(
+        &self,
+        webp: &[u8],
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<u8>, String> {
+        if webp.len() < 12 || &webp[..4] != b"RIFF" || &webp[8..12] != b"WEBP" {
+            return Err("invalid webp data".to_string());
+        }
+        let mut chunks = webp[12..].to_vec();
+        match chunks.get(..4) {
+            Some(b"VP8X") => chunks[8] |= WEBP_EXIF_FLAG | WEBP_XMP_FLAG,
+            Some(b"VP8 " | b"VP8L") => {
+                let mut vp8x = vec![WEBP_EXIF_FLAG | WEBP_XMP_FLAG, 0, 0, 0];
+                vp8x.extend(&(width - 1).to_le_bytes()[..3]);
+                vp8x.extend(&(height - 1).to_le_bytes()[..3]);
+                let mut extended = Vec::with_capacity(chunks.len() + 18);
+                push_riff_chunk(&mut extended, b"VP8X", &vp8x);
+                extended.extend(chunks);
+                chunks = extended;
+            }
+            _ => return Err("unsupported webp layout".to_string()),
+        }
+        push_riff_chunk(&mut chunks, b"EXIF", &self.exif());
+        push_riff_chunk(&mut chunks, b"XMP ", self.xmp().as_bytes());
+
+        let size = u32::try_from(chunks.len() + 4).map_err(|_| "webp too large")?;
+        let mut out = Vec::with_capacity(chunks.len() + 12);
+        out.extend_from_slice(b"RIFF");
+        out.extend(size.to_le_bytes());
+        out.extend_from_slice(b"WEBP");
+        out.extend(chunks);
+        Ok(out)
+    }
+}
+
+/// VP8X 中表示包含 EXIF 与 XMP 块的标志位
+const WEBP_EXIF_FLAG: u8 = 0x08;
+const WEBP_XMP_FLAG: u8 = 0x04;
+
+/// RIFF 块的长度不含填充，奇数长度补一个字节
+fn push_riff_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(fourcc);
+    out.extend((payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// 按常见的环境变量推断合成器
+fn compositor_name() -> Option<String> {
+    if let Ok(desktop) = std::env::var("XDG_CURRENT_DESKTOP")
+        && !desktop.is_empty()
+    {
+        return Some(desktop);
+    }
+    [
+        ("HYPRLAND_INSTANCE_SIGNATURE", "Hyprland"),
+        ("NIRI_SOCKET", "niri"),
+        ("SWAYSOCK", "sway"),
+        ("WAYFIRE_SOCKET", "wayfire"),
+    ]
+    .into_iter()
+    .find(|(var, _)| std::env::var_os(var).is_some())
+    .map(|(_, name)| name.to_string())
+}
+
+fn ifd_size(entries: usize) -> usize {
+    2 + 12 * entries + 4
+}
+
+/// 依次写入各个 IFD，超过 4 字节的值放在所有 IFD 之后
+fn write_tiff(ifds: &[Vec<(u16, TiffValue)>]) -> Vec<u8> {
+    let data_offset = 8 + ifds.iter().map(|ifd| ifd_size(ifd.len())).sum::<usize>();
+    let mut out = b"II*\0\x08\0\0\0".to_vec();
+    let mut data = Vec::new();
+    for ifd in ifds {
+        out.extend((ifd.len() as u16).to_le_bytes());
+        for (tag, value) in ifd {
+            out.extend(tag.to_le_bytes());
+            match value {
+                TiffValue::Long(v) => {
+                    out.extend(4u16.to_le_bytes());
+                    out.extend(1u32.to_le_bytes());
+                    out.extend(v.to_le_bytes());
+                }
+                TiffValue::Ascii(s) => {
+                    let mut bytes: Vec<u8> = s.bytes().filter(u8::is_ascii).collect();
+                    bytes.push(0);
+                    out.extend(2u16.to_le_bytes());
+                    out.extend((bytes.len() as u32).to_le_bytes());
+                    if bytes.len() <= 4 {
+                        bytes.resize(4, 0);
+                        out.extend(bytes);
+                    } else {
+                        out.extend(((data_offset + data.len()) as u32).to_le_bytes());
+                        data.extend(bytes);
+                        // 值的偏移需要按字对齐
+                        if data.len() % 2 == 1 {
+                            data.push(0);
+                        }
+                    }
+                }
+            }
+        }
+        out.extend(0u32.to_le_bytes());
+    }
+    out.extend(data);
+    out
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use image::ImageDecoder;
+    use image::codecs::jpeg::{JpegDecoder, JpegEncoder};
+
+    fn metadata() -> CaptureMetadata {
+        CaptureMetadata {
+            timestamp: Local.with_ymd_and_hms(2025, 4, 1, 12, 30, 45).unwrap(),
+            outputs: vec![("DP-1".to_string(), 1.0), ("eDP-1".to_string(), 1.5)],
+            geometry: Some("100,50 600x200".to_string()),
+            compositor: Some("<sway & \"co\">".to_string()),
+        }
+    }
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// 读取一个 IFD，ASCII 值去掉结尾的 NUL，LONG 值转为字符串
+    fn read_ifd(tiff: &[u8], offset: usize) -> Vec<(u16, String)> {
+        let count = u16_at(tiff, offset) as usize;
+        let entries = (0..count)
+            .map(|i| {
+                let entry = offset + 2 + 12 * i;
+                let (tag, kind, len) = (
+                    u16_at(tiff, entry),
+                    u16_at(tiff, entry + 2),
+                    u32_at(tiff, entry + 4) as usize,
+                );
+                let value = match kind {
+                    2 => {
+                        let start = if len <= 4 {
+                            entry + 8
+                        } else {
+                            u32_at(tiff, entry + 8) as usize
+                        };
+                        let bytes = &tiff[start..start + len];
+                        assert_eq!(bytes.last(), Some(&0), "tag {:#x}", tag);
+                        String::from_utf8(bytes[..len - 1].to_vec()).unwrap()
+                    }
+                    4 => u32_at(tiff, entry + 8).to_string(),
+                    kind => panic!("unexpected type {} for tag {:#x}", kind, tag),
+                };
+                (tag, value)
+            })
+            .collect();
+        assert_eq!(u32_at(tiff, offset + 2 + 12 * count), 0, "next IFD");
+        entries
+    }
+
+    /// 按标记遍历 jpeg 的段，直到图像数据开始
+    fn jpeg_segments(jpeg: &[u8]) -> Vec<(u8, &[u8])> {
+        let mut segments = Vec::new();
+        let mut pos = 2;
+        while jpeg[pos] == 0xff && jpeg[pos + 1] != 0xda {
+            let len = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+            segments.push((jpeg[pos + 1], &jpeg[pos + 4..pos + 2 + len]));
+            pos += 2 + len;
+        }
+        segments
+    }
+
+    fn encode_jpeg() -> Vec<u8> {
+        let pixels: Vec<u8> = (0..8 * 8).flat_map(|i| [i as u8 * 4, 128, 255]).collect();
+        let mut jpeg = Vec::new();
+        JpegEncoder::new(&mut jpeg)
+            .encode(&pixels, 8, 8, image::ExtendedColorType::Rgb8)
+            .unwrap();
+        jpeg
+    }
+
+    #[test]
+    fn exif_decodes() {
+        let metadata = metadata();
+        let tiff = metadata.exif();
+        assert_eq!(&tiff[..8], b"II*\0\x08\0\0\0");
+
+        let ifd0 = read_ifd(&tiff, 8);
+        let tags: Vec<u16> = ifd0.iter().map(|(tag, _)| *tag).collect();
+        assert_eq!(tags, [0x010e, 0x0131, 0x0132, 0x8769]);
+        assert_eq!(
+            ifd0[0].1,
+            "screenshot of 100,50 600x200 on DP-1,eDP-1 (scale 1,1.5) under <sway & \"co\">"
+        );
+        assert_eq!(ifd0[1].1, CaptureMetadata::software());
+        assert_eq!(ifd0[2].1, "2025:04:01 12:30:45");
+
+        let exif_ifd = read_ifd(&tiff, ifd0[3].1.parse().unwrap());
+        assert_eq!(exif_ifd[0], (0x9003, "2025:04:01 12:30:45".to_string()));
+        assert_eq!(
+            exif_ifd[1],
+            (0x9011, metadata.timestamp.format("%:z").to_string())
+        );
+    }
+
+    #[test]
+    fn exif_drops_non_ascii() {
+        let mut metadata = metadata();
+        metadata.compositor = Some("合成器 niri".to_string());
+        let tiff = metadata.exif();
+        assert!(read_ifd(&tiff, 8)[0].1.ends_with(" under  niri"));
+    }
+
+    #[test]
+    fn xmp_escapes_attributes() {
+        let xmp = metadata().xmp();
+        assert!(xmp.starts_with("<?xpacket begin=\"\u{feff}\""));
+        assert!(xmp.ends_with("<?xpacket end=\"r\"?>"));
+        for attribute in [
+            "xmp:CreateDate=\"2025-04-01T12:30:45",
+            "foamshot:Outputs=\"DP-1,eDP-1\"",
+            "foamshot:Scales=\"1,1.5\"",
+            "foamshot:Geometry=\"100,50 600x200\"",
+            "foamshot:Compositor=\"&lt;sway &amp; &quot;co&quot;&gt;\"",
+        ] {
+            assert!(xmp.contains(attribute), "{}", attribute);
+        }
+    }
+
+    #[test]
+    fn jpeg_keeps_jfif_first_and_decodes() {
+        let metadata = metadata();
+        let jpeg = metadata.insert_into_jpeg(&encode_jpeg()).unwrap();
+
+        let segments = jpeg_segments(&jpeg);
+        let markers: Vec<u8> = segments.iter().take(3).map(|(m, _)| *m).collect();
+        assert_eq!(markers, [0xe0, 0xe1, 0xe1]);
+        assert!(segments[0].1.starts_with(b"JFIF\0"));
+        assert_eq!(
+            segments[1].1,
+            [b"Exif\0\0".as_slice(), &metadata.exif()].concat()
+        );
+        let xmp = segments[2]
+            .1
+            .strip_prefix(b"http://ns.adobe.com/xap/1.0/\0".as_slice())
+            .unwrap();
+        assert_eq!(std::str::from_utf8(xmp).unwrap(), metadata.xmp());
+
+        let mut decoder = JpegDecoder::new(std::io::Cursor::new(&jpeg)).unwrap();
+        assert_eq!(decoder.exif_metadata().unwrap(), Some(metadata.exif()));
+        assert_eq!(decoder.dimensions(), (8, 8));
+        assert!(image::load_from_memory(&jpeg).is_ok());
+    }
+
+    #[test]
+    fn jpeg_without_jfif() {
+        let jpeg = encode_jpeg();
+        let app0 = 2 + u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+        let bare = [&jpeg[..2], &jpeg[2 + app0..]].concat();
+
+        let with_metadata = metadata().insert_into_jpeg(&bare).unwrap();
+        assert_eq!(jpeg_segments(&with_metadata)[0].0, 0xe1);
+        assert!(image::load_from_memory(&with_metadata).is_ok());
+        assert!(metadata().insert_into_jpeg(b"\x89PNG").is_err());
+    }
+}
@@ -1,9 +1,16 @@
 //! INFO: Provides wrappers for output to fs
+mod bmp;
 mod common;
+pub mod encoder;
 mod jpg;
+pub mod metadata;
 mod png;
+mod pnm;
+mod qoi;
+mod sidecar;
+mod webp;
 mod wl_clipboard;
 
-pub use jpg::save_to_jpg;
-pub use png::save_to_png;
+pub use encoder::save_image;
+pub use sidecar::save_sidecar;
 pub use wl_clipboard::save_to_wl_clipboard;
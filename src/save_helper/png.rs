@@ -1,36 +1,136 @@
+use crate::config::{PngCompression, PngFilter};
 use crate::wayland_ctx::WaylandCtx;
-use cairo::ImageSurface;
-use log::warn;
+use png::{AdaptiveFilterType, BitDepth, ColorType, Compression, FilterType};
 use std::io::Write;
 
-use super::common::{calculate_capture_info, create_final_surface, process_all_outputs};
+use super::common::{build_final_rgba16, build_final_surface, surface_to_rgba8};
+use super::metadata::CaptureMetadata;
 
-pub fn save_to_png(wl_ctx: &mut WaylandCtx) -> Result<ImageSurface, Box<dyn std::error::Error>> {
-    let capture_info = match calculate_capture_info(wl_ctx)? {
-        Some(info) => info,
-        None => {
-            warn!("未找到有效截图区域");
-            return Err("未找到有效截图区域".into());
-        }
+pub fn encode_png(
+    wl_ctx: &mut WaylandCtx,
+    writer: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let metadata = wl_ctx
+        .config
+        .metadata
+        .then(|| CaptureMetadata::collect(wl_ctx));
+    let (width, height, bit_depth, data) = if wl_ctx.config.png_16bit {
+        // NOTE: 16 位 png 不经过 cairo，直接从原始格式解码
+        let image = build_final_rgba16(wl_ctx)?;
+        // png 中 16 位的采样为大端序
+        let bytes: Vec<u8> = image
+            .as_raw()
+            .iter()
+            .flat_map(|c| c.to_be_bytes())
+            .collect();
+        (image.width(), image.height(), BitDepth::Sixteen, bytes)
+    } else {
+        let mut final_surface = build_final_surface(wl_ctx)?;
+        let (width, height, rgba) = surface_to_rgba8(&mut final_surface)?;
+        (width, height, BitDepth::Eight, rgba)
     };
 
-    let final_surface = create_final_surface(capture_info.total_width, capture_info.total_height)?;
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(bit_depth);
+    encoder.set_compression(match wl_ctx.config.png_compression {
+        PngCompression::Fast => Compression::Fast,
+        PngCompression::Default => Compression::Default,
+        PngCompression::Best => Compression::Best,
+    });
+    match wl_ctx.config.png_filter {
+        PngFilter::Adaptive => encoder.set_adaptive_filter(AdaptiveFilterType::Adaptive),
+        filter => encoder.set_filter(match filter {
+            PngFilter::None => FilterType::NoFilter,
+            PngFilter::Sub => FilterType::Sub,
+            PngFilter::Up => FilterType::Up,
+            PngFilter::Avg => FilterType::Avg,
+            _ => FilterType::Paeth,
+        }),
+    }
+    if let Some(metadata) = &metadata {
+        add_text_chunks(&mut encoder, metadata)?;
+    }
 
-    process_all_outputs(wl_ctx, &capture_info, &final_surface)?;
+    encoder
+        .write_header()
+        .and_then(|mut w| w.write_image_data(&data))
+        .map_err(|e| format!("写入PNG失败: {}", e))?;
 
-    let output_path = wl_ctx.config.output_path.clone();
+    Ok(())
+}
 
-    let mut file = std::fs::OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(output_path.clone())
-        .map_err(|e| format!("创建文件失败: {}", e))?;
+/// NOTE: ASCII 的内容写入 tEXt，其余写入 UTF-8 的 iTXt
+fn add_text_chunks<W: Write>(
+    encoder: &mut png::Encoder<W>,
+    metadata: &CaptureMetadata,
+) -> Result<(), String> {
+    for (keyword, text) in metadata.text_entries() {
+        let keyword = keyword.to_string();
+        if text.is_ascii() {
+            encoder.add_text_chunk(keyword, text)
+        } else {
+            encoder.add_itxt_chunk(keyword, text)
+        }
+        .map_err(|e| format!("写入PNG元数据失败: {}", e))?;
+    }
+    Ok(())
+}
 
-    final_surface
-        .write_to_png(&mut file)
-        .map_err(|e| format!("写入PNG失败: {}", e))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+
+    fn metadata() -> CaptureMetadata {
+        CaptureMetadata {
+            timestamp: Local.with_ymd_and_hms(2025, 4, 1, 12, 30, 45).unwrap(),
+            outputs: vec![("DP-1".to_string(), 1.0), ("eDP-1".to_string(), 1.5)],
+            geometry: Some("100,50 600x200".to_string()),
+            compositor: Some("合成器".to_string()),
+        }
+    }
+
+    #[test]
+    fn text_chunks_decode() {
+        let metadata = metadata();
+        let pixels = [255u8, 0, 0, 255, 0, 0, 255, 128];
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 2, 1);
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(BitDepth::Eight);
+        add_text_chunks(&mut encoder, &metadata).unwrap();
+        encoder
+            .write_header()
+            .and_then(|mut w| w.write_image_data(&pixels))
+            .unwrap();
 
-    file.flush().map_err(|e| format!("刷新文件失败: {}", e))?;
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let info = reader.info();
+        let text: Vec<_> = info
+            .uncompressed_latin1_text
+            .iter()
+            .map(|chunk| (chunk.keyword.as_str(), chunk.text.clone()))
+            .collect();
+        assert_eq!(
+            text,
+            [
+                ("Software", CaptureMetadata::software()),
+                ("Creation Time", metadata.timestamp.to_rfc2822()),
+                ("Outputs", "DP-1,eDP-1".to_string()),
+                ("Scales", "1,1.5".to_string()),
+                ("Geometry", "100,50 600x200".to_string()),
+            ]
+        );
+        let utf8: Vec<_> = info
+            .utf8_text
+            .iter()
+            .map(|chunk| (chunk.keyword.clone(), chunk.get_text().unwrap()))
+            .collect();
+        assert_eq!(utf8, [("Compositor".to_string(), "合成器".to_string())]);
 
-    Ok(final_surface)
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        assert_eq!(buf, pixels);
+    }
 }
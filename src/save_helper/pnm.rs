@@ -0,0 +1,38 @@
+use crate::wayland_ctx::WaylandCtx;
+use image::ImageEncoder;
+use image::codecs::pnm::{PnmEncoder, PnmSubtype, SampleEncoding};
+use std::io::Write;
+
+use super::common::{build_final_surface, surface_to_rgb8, surface_to_rgba8};
+
+/// 二进制 ppm (P6)，不包含 alpha
+pub fn encode_ppm(
+    wl_ctx: &mut WaylandCtx,
+    writer: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut final_surface = build_final_surface(wl_ctx)?;
+    let (width, height, rgb) = surface_to_rgb8(&mut final_surface)?;
+
+    PnmEncoder::new(writer)
+        .with_subtype(PnmSubtype::Pixmap(SampleEncoding::Binary))
+        .write_image(&rgb, width, height, image::ExtendedColorType::Rgb8)
+        .map_err(|e| format!("PPM编码失败: {}", e))?;
+
+    Ok(())
+}
+
+/// pam (P7)，保留 alpha
+pub fn encode_pam(
+    wl_ctx: &mut WaylandCtx,
+    writer: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut final_surface = build_final_surface(wl_ctx)?;
+    let (width, height, rgba) = surface_to_rgba8(&mut final_surface)?;
+
+    PnmEncoder::new(writer)
+        .with_subtype(PnmSubtype::ArbitraryMap)
+        .write_image(&rgba, width, height, image::ExtendedColorType::Rgba8)
+        .map_err(|e| format!("PAM编码失败: {}", e))?;
+
+    Ok(())
+}
@@ -0,0 +1,20 @@
+use crate::wayland_ctx::WaylandCtx;
+use image::ImageEncoder;
+use image::codecs::qoi::QoiEncoder;
+use std::io::Write;
+
+use super::common::{build_final_surface, surface_to_rgba8};
+
+pub fn encode_qoi(
+    wl_ctx: &mut WaylandCtx,
+    writer: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut final_surface = build_final_surface(wl_ctx)?;
+    let (width, height, rgba) = surface_to_rgba8(&mut final_surface)?;
+
+    QoiEncoder::new(writer)
+        .write_image(&rgba, width, height, image::ExtendedColorType::Rgba8)
+        .map_err(|e| format!("QOI编码失败: {}", e))?;
+
+    Ok(())
+}
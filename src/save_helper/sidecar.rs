@@ -0,0 +1,339 @@
+//! INFO: `--sidecar` writes a json file next to the saved image, describing how its pixels map back to the desktop
+use chrono::{DateTime, Local, SecondsFormat};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use wayland_client::protocol::wl_output::Transform;
+
+use crate::select_rect::SubRect;
+use crate::wayland_ctx::WaylandCtx;
+
+use super::common::{Placement, calculate_capture_info};
+use super::encoder;
+
+/// 按 json 转义字符串并加上引号
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn transform_name(transform: Transform) -> &'static str {
+    match transform {
+        Transform::_90 => "90",
+        Transform::_180 => "180",
+        Transform::_270 => "270",
+        Transform::Flipped => "flipped",
+        Transform::Flipped90 => "flipped-90",
+        Transform::Flipped180 => "flipped-180",
+        Transform::Flipped270 => "flipped-270",
+        _ => "normal",
+    }
+}
+
+/// sidecar 中一个输出的子区域
+struct SidecarSubrect<'a> {
+    monitor: &'a str,
+    monitor_id: usize,
+    scale: f64,
+    transform: Transform,
+    rect: &'a SubRect,
+    /// 子区域左上角的全局逻辑坐标
+    logical: (f64, f64),
+    placement: Placement,
+    captured_at: Option<DateTime<Local>>,
+}
+
+/// sidecar 的全部内容，与 `WaylandCtx` 分离以便单独生成 json
+struct Sidecar<'a> {
+    image: &'a Path,
+    format: &'a str,
+    saved_at: DateTime<Local>,
+    cursor: bool,
+    frozen: bool,
+    /// 画布左上角的全局逻辑坐标与缩放，窗口与 portal 截图时为空
+    origin: Option<(f64, f64, f64)>,
+    total_width: u32,
+    total_height: u32,
+    subrects: Vec<SidecarSubrect<'a>>,
+}
+
+fn timestamp(t: &DateTime<Local>) -> String {
+    json_string(&t.to_rfc3339_opts(SecondsFormat::Millis, false))
+}
+
+impl Sidecar<'_> {
+    fn to_json(&self) -> String {
+        let (min_x, min_y, scale) = match self.origin {
+            Some((x, y, scale)) => (x.to_string(), y.to_string(), scale.to_string()),
+            None => ("null".into(), "null".into(), "null".into()),
+        };
+        let subrects = if self.subrects.is_empty() {
+            "[]".to_string()
+        } else {
+            let entries: Vec<String> = self
+                .subrects
+                .iter()
+                .map(|s| {
+                    format!(
+                        concat!(
+                            "    {{\"monitor\": {}, \"monitor_id\": {}, \"scale\": {}, \"transform\": \"{}\",\n",
+                            "     \"x\": {}, \"y\": {}, \"width\": {}, \"height\": {},\n",
+                            "     \"logical_x\": {}, \"logical_y\": {},\n",
+                            "     \"placement\": {{\"x\": {}, \"y\": {}, \"width\": {}, \"height\": {}}},\n",
+                            "     \"captured_at\": {}}}"
+                        ),
+                        json_string(s.monitor),
+                        s.monitor_id,
+                        s.scale,
+                        transform_name(s.transform),
+                        s.rect.relative_min_x,
+                        s.rect.relative_min_y,
+                        s.rect.width,
+                        s.rect.height,
+                        s.logical.0,
+                        s.logical.1,
+                        s.placement.x,
+                        s.placement.y,
+                        s.placement.width,
+                        s.placement.height,
+                        s.captured_at
+                            .as_ref()
+                            .map_or("null".to_string(), timestamp)
+                    )
+                })
+                .collect();
+            format!("[\n{}\n  ]", entries.join(",\n"))
+        };
+
+        let fields = [
+            ("image", json_string(&self.image.to_string_lossy())),
+            ("format", json_string(self.format)),
+            ("saved_at", timestamp(&self.saved_at)),
+            ("cursor", self.cursor.to_string()),
+            ("frozen", self.frozen.to_string()),
+            (
+                "capture",
+                format!(
+                    "{{\"min_x\": {}, \"min_y\": {}, \"scale\": {}, \"total_width\": {}, \"total_height\": {}}}",
+                    min_x, min_y, scale, self.total_width, self.total_height
+                ),
+            ),
+            ("subrects", subrects),
+        ];
+        let body: Vec<String> = fields
+            .into_iter()
+            .map(|(key, value)| format!("  \"{}\": {}", key, value))
+            .collect();
+        format!("{{\n{}\n}}\n", body.join(",\n"))
+    }
+}
+
+/// 将截图信息写入与图片同名的 `.json`，返回写入的路径
+/// NOTE: 坐标为全局逻辑坐标，画布上的像素 (px, py) 对应 `min_x + px / scale`, `min_y + py / scale`
+/// 窗口与 portal 截图没有输出与选区，`capture` 只包含画布尺寸
+pub fn save_sidecar(wl_ctx: &WaylandCtx) -> Result<PathBuf, Box<dyn Error>> {
+    let config = &wl_ctx.config;
+    let path = config.output_path.with_extension("json");
+    let mut sidecar = Sidecar {
+        image: &config.output_path,
+        format: encoder::by_type(config.image_type).name,
+        saved_at: Local::now(),
+        cursor: config.cursor,
+        frozen: wl_ctx.current_freeze,
+        origin: None,
+        total_width: 0,
+        total_height: 0,
+        subrects: Vec::new(),
+    };
+
+    if let Some(single) = wl_ctx.single_canvas.as_ref() {
+        sidecar.total_width = single.info.width as u32;
+        sidecar.total_height = single.info.height as u32;
+    } else {
+        let info = calculate_capture_info(wl_ctx)?.ok_or("未找到有效截图区域")?;
+        let outputs = wl_ctx.foam_outputs.as_ref().ok_or("未初始化输出设备")?;
+        sidecar.origin = Some((info.min_x, info.min_y, info.scale));
+        sidecar.total_width = info.total_width;
+        sidecar.total_height = info.total_height;
+        sidecar.subrects = info
+            .monitor_ids
+            .iter()
+            .zip(&info.placements)
+            .filter_map(|(id, placement)| {
+                let output = outputs.get(id)?;
+                let rect = output.subrect.as_ref()?;
+                let scale = output.buffer_scale();
+                Some(SidecarSubrect {
+                    monitor: &output.name,
+                    monitor_id: *id,
+                    scale,
+                    transform: output.output_transform(),
+                    rect,
+                    logical: (
+                        output.global_x as f64 + rect.relative_min_x as f64 / scale,
+                        output.global_y as f64 + rect.relative_min_y as f64 / scale,
+                    ),
+                    placement: *placement,
+                    captured_at: wl_ctx.scm.captured_at.get(id).copied(),
+                })
+            })
+            .collect();
+    }
+
+    std::fs::write(&path, sidecar.to_json()).map_err(|e| format!("写入 sidecar 失败: {}", e))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::{Value, json};
+
+    fn parse(text: &str) -> Value {
+        serde_json::from_str(text).unwrap_or_else(|e| panic!("{}:\n{}", e, text))
+    }
+
+    fn sidecar<'a>(image: &'a Path, subrects: Vec<SidecarSubrect<'a>>) -> Sidecar<'a> {
+        Sidecar {
+            image,
+            format: "png",
+            saved_at: Local.with_ymd_and_hms(2025, 4, 1, 12, 30, 45).unwrap(),
+            cursor: false,
+            frozen: true,
+            origin: None,
+            total_width: 640,
+            total_height: 480,
+            subrects,
+        }
+    }
+
+    fn rfc3339(t: DateTime<Local>) -> String {
+        t.to_rfc3339_opts(SecondsFormat::Millis, false)
+    }
+
+    #[test]
+    fn single_canvas_has_no_origin() {
+        let image = Path::new("/tmp/shot.png");
+        let sidecar = sidecar(image, Vec::new());
+        assert_eq!(
+            parse(&sidecar.to_json()),
+            json!({
+                "image": "/tmp/shot.png",
+                "format": "png",
+                "saved_at": rfc3339(sidecar.saved_at),
+                "cursor": false,
+                "frozen": true,
+                "capture": {
+                    "min_x": null,
+                    "min_y": null,
+                    "scale": null,
+                    "total_width": 640,
+                    "total_height": 480,
+                },
+                "subrects": [],
+            })
+        );
+    }
+
+    #[test]
+    fn subrects_roundtrip() {
+        let image = Path::new("/tmp/\"quoted\"\\dir\n/shot.png");
+        let left = SubRect::new(0, 100, 50, 300, 200);
+        let right = SubRect::new(1, 0, 75, 450, 300);
+        let captured_at = Local.with_ymd_and_hms(2025, 4, 1, 12, 30, 44).unwrap();
+        let subrects = vec![
+            SidecarSubrect {
+                monitor: "DP-1",
+                monitor_id: 0,
+                scale: 1.0,
+                transform: Transform::Normal,
+                rect: &left,
+                logical: (100.0, 50.0),
+                placement: Placement {
+                    x: 0,
+                    y: 0,
+                    width: 300,
+                    height: 200,
+                },
+                captured_at: Some(captured_at),
+            },
+            SidecarSubrect {
+                monitor: "eDP-1 \"内置\"",
+                monitor_id: 1,
+                scale: 1.5,
+                transform: Transform::Flipped90,
+                rect: &right,
+                logical: (1920.0, 50.0),
+                placement: Placement {
+                    x: 300,
+                    y: 0,
+                    width: 300,
+                    height: 200,
+                },
+                captured_at: None,
+            },
+        ];
+        let mut sidecar = sidecar(image, subrects);
+        sidecar.origin = Some((100.0, 50.5, 1.0));
+        sidecar.total_width = 600;
+        sidecar.total_height = 200;
+        let value = parse(&sidecar.to_json());
+
+        assert_eq!(value["image"], image.to_str().unwrap());
+        assert_eq!(
+            value["capture"],
+            json!({
+                "min_x": 100,
+                "min_y": 50.5,
+                "scale": 1,
+                "total_width": 600,
+                "total_height": 200,
+            })
+        );
+        assert_eq!(
+            value["subrects"],
+            json!([
+                {
+                    "monitor": "DP-1",
+                    "monitor_id": 0,
+                    "scale": 1,
+                    "transform": "normal",
+                    "x": 100, "y": 50, "width": 300, "height": 200,
+                    "logical_x": 100, "logical_y": 50,
+                    "placement": {"x": 0, "y": 0, "width": 300, "height": 200},
+                    "captured_at": rfc3339(captured_at),
+                },
+                {
+                    "monitor": "eDP-1 \"内置\"",
+                    "monitor_id": 1,
+                    "scale": 1.5,
+                    "transform": "flipped-90",
+                    "x": 0, "y": 75, "width": 450, "height": 300,
+                    "logical_x": 1920, "logical_y": 50,
+                    "placement": {"x": 300, "y": 0, "width": 300, "height": 200},
+                    "captured_at": null,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn escapes_control_characters() {
+        assert_eq!(json_string("a\u{1}b"), "\"a\\u0001b\"");
+        let text = "tab\t \"quote\" back\\slash \u{1f}";
+        assert_eq!(parse(&json_string(text)), text);
+    }
+}
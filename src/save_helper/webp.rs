@@ -0,0 +1,139 @@
+use crate::wayland_ctx::WaylandCtx;
+use image_webp::{ColorType, WebPEncoder};
+use std::io::Write;
+
+use super::common::{build_final_surface, surface_to_rgba8};
+use super::metadata::CaptureMetadata;
+
+/// 未指定 `--quality` 时使用 image-webp 无损编码，否则使用 libwebp 按给定质量有损编码
+pub fn encode_webp(
+    wl_ctx: &mut WaylandCtx,
+    writer: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let metadata = wl_ctx
+        .config
+        .metadata
+        .then(|| CaptureMetadata::collect(wl_ctx));
+    let mut final_surface = build_final_surface(wl_ctx)?;
+    let (width, height, rgba) = surface_to_rgba8(&mut final_surface)?;
+
+    let Some(quality) = wl_ctx.config.quality else {
+        let mut encoder = WebPEncoder::new(writer);
+        if let Some(metadata) = &metadata {
+            encoder.set_exif_metadata(metadata.exif());
+            encoder.set_xmp_metadata(metadata.xmp().into_bytes());
+        }
+        encoder
+            .encode(&rgba, width, height, ColorType::Rgba8)
+            .map_err(|e| format!("WebP编码失败: {}", e))?;
+        return Ok(());
+    };
+
+    let webp = encode_lossy(&rgba, width, height, quality)?;
+    let webp = match &metadata {
+        Some(metadata) => metadata.insert_into_webp(&webp, width, height)?,
+        None => webp,
+    };
+    writer
+        .write_all(&webp)
+        .map_err(|e| format!("写入WebP失败: {}", e))?;
+    Ok(())
+}
+
+/// libwebp 有损编码，`quality` 为 1-100
+fn encode_lossy(rgba: &[u8], width: u32, height: u32, quality: u8) -> Result<Vec<u8>, String> {
+    let webp = webp::Encoder::from_rgba(rgba, width, height)
+        .encode_simple(false, quality as f32)
+        .map_err(|e| format!("WebP编码失败: {:?}", e))?;
+    Ok(webp.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+    use image_webp::WebPDecoder;
+    use std::io::Cursor;
+
+    fn metadata() -> CaptureMetadata {
+        CaptureMetadata {
+            timestamp: Local.with_ymd_and_hms(2025, 4, 1, 12, 30, 45).unwrap(),
+            outputs: vec![("DP-1".to_string(), 1.0)],
+            geometry: Some("0,0 9x5".to_string()),
+            compositor: None,
+        }
+    }
+
+    /// 左半红右半蓝，`alpha` 为右下角像素的透明度
+    fn image(alpha: u8) -> Vec<u8> {
+        let (w, h) = (9, 5);
+        let mut rgba: Vec<u8> = (0..w * h)
+            .flat_map(|i| {
+                if i % w < 4 {
+                    [255, 0, 0, 255]
+                } else {
+                    [0, 0, 255, 255]
+                }
+            })
+            .collect();
+        let len = rgba.len();
+        rgba[len - 1] = alpha;
+        rgba
+    }
+
+    fn decode(webp: &[u8]) -> (WebPDecoder<Cursor<&[u8]>>, Vec<u8>) {
+        let mut decoder = WebPDecoder::new(Cursor::new(webp)).unwrap();
+        let mut buf = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut buf).unwrap();
+        (decoder, buf)
+    }
+
+    #[test]
+    fn lossy_webp_decodes_with_metadata() {
+        let metadata = metadata();
+        // 不透明时 libwebp 输出简单格式，带透明度时输出 VP8X 与 ALPH
+        for alpha in [255, 0] {
+            let webp = encode_lossy(&image(alpha), 9, 5, 90).unwrap();
+            let webp = metadata.insert_into_webp(&webp, 9, 5).unwrap();
+            assert_eq!(
+                u32::from_le_bytes(webp[4..8].try_into().unwrap()) as usize,
+                webp.len() - 8
+            );
+
+            let (mut decoder, pixels) = decode(&webp);
+            assert!(decoder.is_lossy());
+            assert_eq!(decoder.dimensions(), (9, 5));
+            assert_eq!(decoder.has_alpha(), alpha != 255);
+            assert_eq!(decoder.exif_metadata().unwrap(), Some(metadata.exif()));
+            assert_eq!(
+                decoder.xmp_metadata().unwrap(),
+                Some(metadata.xmp().into_bytes())
+            );
+            let bpp = if alpha == 255 { 3 } else { 4 };
+            let first = &pixels[..3];
+            let last_rgb = &pixels[(9 * 5 - 2) * bpp..][..3];
+            assert!(first[0] > 200 && first[2] < 60, "{:?}", first);
+            assert!(last_rgb[0] < 60 && last_rgb[2] > 200, "{:?}", last_rgb);
+        }
+    }
+
+    #[test]
+    fn lower_quality_is_smaller() {
+        let rgba: Vec<u8> = (0..64 * 64u32)
+            .flat_map(|i| [(i * 7) as u8, (i * 13) as u8, (i / 64) as u8, 255])
+            .collect();
+        let best = encode_lossy(&rgba, 64, 64, 100).unwrap();
+        let low = encode_lossy(&rgba, 64, 64, 10).unwrap();
+        assert!(low.len() < best.len(), "{} >= {}", low.len(), best.len());
+    }
+
+    #[test]
+    fn rejects_invalid_webp() {
+        assert!(
+            metadata()
+                .insert_into_webp(b"RIFF\0\0\0\0WAVE", 1, 1)
+                .is_err()
+        );
+        assert!(metadata().insert_into_webp(b"RIFF", 1, 1).is_err());
+    }
+}
@@ -1,4 +1,6 @@
 use crate::wayland_ctx::WaylandCtx;
+
+use super::encoder;
 use std::io::Write;
 use std::process::{Command, Stdio};
 
@@ -13,7 +15,7 @@ pub fn save_to_wl_clipboard(wl_ctx: &mut WaylandCtx) -> Result<(), Box<dyn std::
 
     let mut child = Command::new("wl-copy")
         .arg("--type")
-        .arg("image/png")
+        .arg(encoder::by_type(wl_ctx.config.image_type).mime)
         .stdin(Stdio::piped())
         .spawn()
         .map_err(|e| format!("启动wl-copy失败: {}", e))?;
@@ -30,6 +30,63 @@ impl SelectRect {
             rect_origin: None,
         }
     }
+    /// 解析 slurp/grim 格式的 `X,Y WxH`，坐标为全局逻辑坐标
+    pub fn from_geometry(geometry: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid geometry \"{}\", expected \"X,Y WxH\"", geometry);
+        let (pos, size) = geometry.trim().split_once(' ').ok_or_else(invalid)?;
+        let (x, y) = pos.split_once(',').ok_or_else(invalid)?;
+        let (w, h) = size.trim().split_once('x').ok_or_else(invalid)?;
+        let parse = |v: &str| v.trim().parse::<i32>().map_err(|_| invalid());
+        let (x, y, w, h) = (parse(x)?, parse(y)?, parse(w)?, parse(h)?);
+        if w <= 0 || h <= 0 {
+            return Err(format!("geometry \"{}\" has an empty size", geometry));
+        }
+        Ok(Self::new(x, y, x + w, y + h))
+    }
+
+    /// 按格式输出矩形，`slurp` 为 `X,Y WxH`，`json` 为一行 json，其余视为模板
+    /// 模板支持 %x %y %w %h %o %s 与 %%，与 slurp 的 -f 一致
+    pub fn format_geometry(&self, format: &str, output: &str, scale: f64) -> String {
+        let (x, y, w, h) = (self.sx, self.sy, self.ex - self.sx, self.ey - self.sy);
+        match format {
+            "slurp" => format!("{},{} {}x{}", x, y, w, h),
+            "json" => format!(
+                "{{\"x\":{},\"y\":{},\"width\":{},\"height\":{},\"output\":\"{}\",\"scale\":{}}}",
+                x,
+                y,
+                w,
+                h,
+                output.replace('\\', "\\\\").replace('"', "\\\""),
+                scale
+            ),
+            template => {
+                let mut out = String::new();
+                let mut chars = template.chars();
+                while let Some(c) = chars.next() {
+                    if c != '%' {
+                        out.push(c);
+                        continue;
+                    }
+                    match chars.next() {
+                        Some('x') => out.push_str(&x.to_string()),
+                        Some('y') => out.push_str(&y.to_string()),
+                        Some('w') => out.push_str(&w.to_string()),
+                        Some('h') => out.push_str(&h.to_string()),
+                        Some('o') => out.push_str(output),
+                        Some('s') => out.push_str(&scale.to_string()),
+                        Some('%') => out.push('%'),
+                        Some(other) => {
+                            out.push('%');
+                            out.push(other);
+                        }
+                        None => out.push('%'),
+                    }
+                }
+                out
+            }
+        }
+    }
+
     /// NOTE: 需要返回新的Action
     pub fn edit(&mut self, start_pos: (f64, f64), end_pos: (f64, f64), act: Action) -> Action {
         // 检查是否需要重置移动状态：如果不是移动操作，或者是新的移动开始（start_pos 变化）
@@ -242,3 +299,98 @@ impl SubRect {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corners(rect: &SelectRect) -> (i32, i32, i32, i32) {
+        (rect.sx, rect.sy, rect.ex, rect.ey)
+    }
+
+    #[test]
+    fn parses_valid_geometry() {
+        let cases = [
+            ("10,20 300x200", (10, 20, 310, 220)),
+            ("  10,20  300x200\n", (10, 20, 310, 220)),
+            ("-10,-20 30x40", (-10, -20, 20, 20)),
+            ("-1920,0 1920x1080", (-1920, 0, 0, 1080)),
+        ];
+        for (geometry, expected) in cases {
+            let rect = SelectRect::from_geometry(geometry).unwrap();
+            assert_eq!(corners(&rect), expected, "{:?}", geometry);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_geometry() {
+        for geometry in [
+            "",
+            "10,20",
+            "10 20x30",
+            "10,20 30",
+            "a,20 30x40",
+            "10,20 30x",
+            "10,20 30x40x5",
+            "10;20 30x40",
+            "1.5,20 30x40",
+        ] {
+            let err = SelectRect::from_geometry(geometry).unwrap_err();
+            assert!(
+                err.starts_with("invalid geometry"),
+                "{:?}: {}",
+                geometry,
+                err
+            );
+        }
+        for geometry in ["10,20 0x40", "10,20 30x0", "10,20 -5x40"] {
+            let err = SelectRect::from_geometry(geometry).unwrap_err();
+            assert!(err.contains("empty size"), "{:?}: {}", geometry, err);
+        }
+    }
+
+    #[test]
+    fn formats_slurp_and_roundtrips() {
+        let rect = SelectRect::new(-10, 20, 290, 220);
+        let slurp = rect.format_geometry("slurp", "DP-1", 1.0);
+        assert_eq!(slurp, "-10,20 300x200");
+        let parsed = SelectRect::from_geometry(&slurp).unwrap();
+        assert_eq!(corners(&parsed), corners(&rect));
+    }
+
+    #[test]
+    fn formats_json() {
+        let rect = SelectRect::new(-10, 20, 290, 220);
+        assert_eq!(
+            rect.format_geometry("json", "DP-1", 1.5),
+            r#"{"x":-10,"y":20,"width":300,"height":200,"output":"DP-1","scale":1.5}"#
+        );
+        // 输出名中的引号与反斜杠需要转义
+        assert_eq!(
+            rect.format_geometry("json", r#"a"b\c"#, 2.0),
+            r#"{"x":-10,"y":20,"width":300,"height":200,"output":"a\"b\\c","scale":2}"#
+        );
+    }
+
+    #[test]
+    fn formats_template() {
+        let rect = SelectRect::new(-10, 20, 290, 220);
+        let cases = [
+            ("%x %y %w %h", "-10 20 300 200"),
+            ("%o@%s", "HDMI-A-1@1.25"),
+            ("%wx%h+%x+%y", "300x200+-10+20"),
+            ("100%%", "100%"),
+            ("%q %", "%q %"),
+            ("plain", "plain"),
+            ("", ""),
+        ];
+        for (template, expected) in cases {
+            assert_eq!(
+                rect.format_geometry(template, "HDMI-A-1", 1.25),
+                expected,
+                "{:?}",
+                template
+            );
+        }
+    }
+}
@@ -1,28 +1,40 @@
 //! INFO: common context
 use cairo::{Context, ImageSurface};
-use log::debug;
-use smithay_client_toolkit::shm::{self};
+use log::{debug, warn};
+use smithay_client_toolkit::shm::{self, slot::SlotPool};
+use std::collections::BTreeMap;
+use std::os::fd::AsFd;
 use wayland_client::{
-    QueueHandle,
-    protocol::{wl_compositor, wl_keyboard, wl_pointer, wl_seat},
+    Proxy, QueueHandle,
+    protocol::{
+        wl_buffer::WlBuffer, wl_compositor, wl_keyboard, wl_pointer, wl_seat, wl_shm::Format,
+    },
 };
 use wayland_protocols::{
     wp::{
         cursor_shape::v1::client::wp_cursor_shape_device_v1::Shape,
         fractional_scale::v1::client::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+        linux_dmabuf::zv1::client::{
+            zwp_linux_buffer_params_v1::Flags, zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
+        },
         viewporter::client::wp_viewporter,
     },
     xdg::xdg_output::zv1::client::zxdg_output_manager_v1,
 };
-use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1;
+use wayland_protocols_wlr::layer_shell::v1::client::{
+    zwlr_layer_shell_v1, zwlr_layer_surface_v1::KeyboardInteractivity,
+};
 
 use crate::{
     config::{self, FoamConfig},
-    foamcore::FoamShot,
-    monitors,
+    dmabuf::{self, DmabufBuffer, UdmabufAllocator},
+    foamcore::{FoamShot, UserTarget},
+    foreign_toplevel::{ToplevelHelper, WindowPicker},
+    frame_queue::FrameQueue,
+    monitors, pixel_format,
     pointer_helper::PointerHelper,
     select_rect::SelectRect,
-    zwlr_screencopy_mode,
+    zwlr_screencopy_mode::{self, CanvasInfo, SingleCanvas},
 };
 
 #[derive(Default)]
@@ -49,8 +61,8 @@ pub struct WaylandCtx {
 
     pub current_freeze: bool,
 
-    /// 每个输出设备一个
-    pub foam_outputs: Option<Vec<monitors::FoamMonitors>>,
+    /// 每个输出设备一个，以 wl_output 的 registry name 为 key，热插拔时保持不变
+    pub foam_outputs: Option<BTreeMap<usize, monitors::FoamMonitors>>,
     pub layer_ready: usize,
 
     /// 光标管理器
@@ -59,21 +71,79 @@ pub struct WaylandCtx {
     pub config: config::FoamConfig,
     pub scm: zwlr_screencopy_mode::ZwlrScreencopyMode,
     pub global_rect: Option<SelectRect>,
-    // pub fq: FrameQueue,
+    /// 录制模式下缓存的帧
+    pub fq: FrameQueue,
+    /// 窗口捕获使用的顶层窗口列表
+    pub toplevel_helper: ToplevelHelper,
+    /// `--window` 不带参数时的交互式窗口选择
+    pub window_picker: Option<WindowPicker>,
+    /// 不属于任何输出的画布，存在时直接输出，不再合成各个输出上的子区域
+    pub single_canvas: Option<SingleCanvas>,
+    /// linux-dmabuf，仅在 --dmabuf 时绑定
+    pub dmabuf: Option<(ZwpLinuxDmabufV1, u32)>,
+    /// udmabuf 设备，首次使用 dmabuf 时打开
+    pub udmabuf: Option<UdmabufAllocator>,
 }
 
 impl WaylandCtx {
     pub fn new(shm: shm::Shm, qh: QueueHandle<FoamShot>, config: FoamConfig) -> Self {
+        let mut scm = zwlr_screencopy_mode::ZwlrScreencopyMode::new();
+        // NOTE: 窗口捕获只能使用 ext 后端
+        scm.prefer_wlr = config.dmabuf && config.window.is_none();
+        scm.keep_deep = config.png_16bit;
         Self {
             qh: Some(qh),
-            // fq: FrameQueue::new(SlotPool::new(256 * 256, &shm).ok()),
+            fq: FrameQueue::new(SlotPool::new(256 * 256 * 4, &shm).ok()),
             shm: Some(shm),
-            foam_outputs: Some(Vec::new()),
-            config: config::FoamConfig::new(),
+            foam_outputs: Some(BTreeMap::new()),
             current_freeze: config.freeze,
+            config,
+            scm,
             ..Default::default()
         }
     }
+
+    /// 守护进程在每次截图后调用，销毁本次会话的 layer 与 surface 并清空选区和画布
+    /// 已绑定的 global 与输出信息保持不变
+    pub fn reset_session(&mut self) {
+        let compositor = self.compositor.as_ref().map(|(c, _)| c);
+        let qh = self.qh.as_ref().unwrap();
+        for (&id, foam_output) in self.foam_outputs.as_mut().unwrap().iter_mut() {
+            if let Some(layer) = foam_output.layer_surface.take() {
+                layer.destroy();
+            }
+            // NOTE: surface 上可能还有上次的 buffer 与穿透的输入区域，直接换一个新的
+            foam_output.scale.take();
+            if let Some(surface) = foam_output.surface.take() {
+                surface.destroy();
+            }
+            foam_output.surface = compositor.map(|c| c.create_surface(qh, id));
+            foam_output.base_buffer = None;
+            foam_output.subrect = None;
+            foam_output.last_rect = None;
+        }
+
+        self.current_index = None;
+        self.unknown_index = None;
+        self.layer_ready = 0;
+        self.global_rect = None;
+        self.single_canvas = None;
+        self.pointer_helper.reset();
+        self.fq.reset();
+        self.toplevel_helper.toplevels.retain(|t| !t.closed);
+        self.window_picker = None;
+
+        self.scm.reset();
+    }
+
+    /// 换用新的配置，守护进程在每次截图前调用
+    pub fn set_config(&mut self, config: FoamConfig) {
+        self.scm.prefer_wlr = config.dmabuf && config.window.is_none();
+        self.scm.keep_deep = config.png_16bit;
+        self.scm.select_backend();
+        self.current_freeze = config.freeze;
+        self.config = config;
+    }
     pub fn set_one_max(&mut self, target: usize) {
         // // 遍历 Vec 的索引和元素
         // for (index, foam_output) in self.foam_outputs.as_mut().unwrap().iter_mut().enumerate() {
@@ -89,8 +159,7 @@ impl WaylandCtx {
             .as_mut()
             .unwrap()
             .iter_mut()
-            .enumerate()
-            .for_each(|(index, foam_output)| {
+            .for_each(|(&index, foam_output)| {
                 if index == target {
                     foam_output.max_rect();
                 } else {
@@ -99,6 +168,15 @@ impl WaylandCtx {
             });
     }
 
+    /// 所有输出都设置为完整的子区域
+    pub fn set_all_max(&mut self) {
+        self.foam_outputs
+            .as_mut()
+            .unwrap()
+            .values_mut()
+            .for_each(|foam_output| foam_output.max_rect());
+    }
+
     pub fn set_cursor_shape(
         &mut self,
         shape: Shape,
@@ -109,38 +187,110 @@ impl WaylandCtx {
         Ok(())
     }
 
+    /// 为每个输出创建 layer，viewporter 与 fractional scale 缺失时回退到整数缩放
     pub fn init_base_layers(&mut self) {
+        let viewporter = self.viewporter.as_ref().map(|(v, _)| v);
+        let fractional_manager = self.fractional_manager.as_ref().map(|(f, _)| f);
         self.foam_outputs
             .as_mut()
             .unwrap()
-            .iter_mut()
+            .values_mut()
             .for_each(|v| {
                 v.init_layer(
                     &self.layer_shell.as_ref().unwrap().0,
                     self.qh.as_ref().unwrap(),
-                    self.viewporter.clone().unwrap().0,
-                    // WARN: THIS WAY IT REQUIRES THE COMPOSITOR TO IMPLEMENT FRACTIONAL SCALE
-                    // OR ELSE THE UNWRAP IS GONNA CRASH THE PROGRAM
-                    // TODO: Add fallback
-                    self.fractional_manager.clone(),
+                    viewporter,
+                    fractional_manager,
                 );
             });
     }
 
+    /// 会话中途加入的输出在信息收集完成后创建 layer，未进入会话或已创建时忽略
+    pub fn init_hotplug_layer(&mut self, udata: usize) {
+        let Some((layer_shell, _)) = self.layer_shell.as_ref() else {
+            return;
+        };
+        let outputs = self.foam_outputs.as_mut().unwrap();
+        // 其它输出已有 layer 说明会话已经开始
+        if !outputs.values().any(|m| m.layer_surface.is_some()) {
+            return;
+        }
+        let Some(foam_output) = outputs.get_mut(&udata) else {
+            return;
+        };
+        // xdg_output 存在时需要等待逻辑尺寸
+        if foam_output.layer_surface.is_some()
+            || foam_output.surface.is_none()
+            || foam_output.width <= 0
+            || (self.xdg_output_manager.is_some() && foam_output.logical_width <= 0)
+        {
+            return;
+        }
+        debug!("output {} added during session, create layer", udata);
+        foam_output.init_layer(
+            layer_shell,
+            self.qh.as_ref().unwrap(),
+            self.viewporter.as_ref().map(|(v, _)| v),
+            self.fractional_manager.as_ref().map(|(f, _)| f),
+        );
+    }
+
+    pub fn has_output(&self, udata: usize) -> bool {
+        self.foam_outputs
+            .as_ref()
+            .is_some_and(|outputs| outputs.contains_key(&udata))
+    }
+
+    /// 输出被移除时销毁其 layer 与 surface，清理画布并修正指向它的索引
+    /// 返回 false 表示不是已知的输出
+    pub fn remove_output(&mut self, udata: usize) -> bool {
+        let Some(mut foam_output) = self.foam_outputs.as_mut().unwrap().remove(&udata) else {
+            return false;
+        };
+        if let Some(layer) = foam_output.layer_surface.take() {
+            layer.destroy();
+        }
+        // viewport 与 fractional scale 需要先于 surface 销毁
+        foam_output.scale.take();
+        if let Some(surface) = foam_output.surface.take() {
+            surface.destroy();
+        }
+        if let Some(output) = foam_output.output.take()
+            && output.version() >= 3
+        {
+            output.release();
+        }
+        self.scm.remove_output(udata);
+
+        // NOTE: 指向被移除输出的索引改为任一剩余输出，坐标均以全局坐标换算，不影响选区
+        let fallback = self.foam_outputs.as_ref().unwrap().keys().next().copied();
+        for index in [
+            &mut self.current_index,
+            &mut self.unknown_index,
+            &mut self.pointer_helper.start_index,
+            &mut self.pointer_helper.end_index,
+        ] {
+            if *index == Some(udata) {
+                *index = fallback;
+            }
+        }
+        true
+    }
+
     /// 重新将缓冲区附加到surface，生成新的一帧，此处仅可附加 `freeze`/`no_freeze` 两种的内容
+    /// 会话中途加入的输出没有冻结画面，使用 `no_freeze`
     pub fn attach_with_udata(&mut self, udata: usize) {
-        let mut foam_output = self.foam_outputs.as_mut().unwrap().get_mut(udata);
-        if self.current_freeze {
-            let base_canvas = self
-                .scm
-                .base_canvas
-                .as_mut()
-                .unwrap()
-                .get_mut(&udata)
-                .unwrap();
-            foam_output.as_mut().unwrap().freeze_attach(base_canvas);
-        } else {
-            foam_output.as_mut().unwrap().no_freeze_attach();
+        let Some(foam_output) = self.foam_outputs.as_mut().unwrap().get_mut(&udata) else {
+            return;
+        };
+        let base_canvas = self
+            .scm
+            .base_canvas
+            .as_ref()
+            .and_then(|canvas| canvas.get(&udata));
+        match base_canvas {
+            Some(base_canvas) if self.current_freeze => foam_output.freeze_attach(base_canvas),
+            _ => foam_output.no_freeze_attach(),
         }
     }
 
@@ -160,33 +310,94 @@ impl WaylandCtx {
         self.foam_outputs
             .as_mut()
             .unwrap()
-            .iter_mut()
+            .values_mut()
             .for_each(|v| {
                 v.clean_attach();
             });
     }
 
-    pub fn no_freeze_attach_with_udata(&mut self, udata: usize) {
+    /// 录制开始时调用，清空所有输出上的遮罩并让鼠标事件穿透，键盘仍由 layer 接管用于停止录制
+    pub fn set_passthrough(&mut self) {
+        let compositor = &self.compositor.as_ref().unwrap().0;
+        let qh = self.qh.as_ref().unwrap();
         self.foam_outputs
             .as_mut()
             .unwrap()
-            .iter_mut()
-            .find(|m| m.id == udata)
+            .values_mut()
+            .for_each(|v| {
+                v.passthrough_attach(compositor, qh);
+            });
+    }
+
+    /// 延迟截图时在所有输出上显示倒计时
+    pub fn countdown_attach(&mut self, text: &str) {
+        self.foam_outputs
+            .as_mut()
+            .unwrap()
+            .values_mut()
+            .filter(|v| v.layer_surface.is_some())
+            .for_each(|v| v.countdown_attach(text));
+    }
+
+    /// 交互式选择窗口时重绘所有输出上的窗口列表
+    pub fn picker_attach(&mut self) {
+        let Some(picker) = self.window_picker.as_ref() else {
+            return;
+        };
+        self.foam_outputs
+            .as_mut()
+            .unwrap()
+            .values_mut()
+            .filter(|v| v.layer_surface.is_some())
+            .for_each(|v| v.picker_attach(picker));
+    }
+
+    /// 交互式选择窗口时绘制单个输出上的窗口列表，用于 layer configure
+    pub fn picker_attach_with_udata(&mut self, udata: usize) {
+        let Some(picker) = self.window_picker.as_ref() else {
+            return;
+        };
+        if let Some(foam_output) = self.foam_outputs.as_mut().unwrap().get_mut(&udata) {
+            foam_output.picker_attach(picker);
+        }
+    }
+
+    /// 释放键盘，使延迟期间可以操作其它窗口
+    pub fn release_keyboard(&mut self) {
+        self.foam_outputs
+            .as_mut()
+            .unwrap()
+            .values()
+            .filter_map(|v| v.layer_surface.as_ref())
+            .for_each(|layer| layer.set_keyboard_interactivity(KeyboardInteractivity::None));
+    }
+
+    /// 是否已经创建了 layer
+    pub fn has_layers(&self) -> bool {
+        self.foam_outputs
+            .as_ref()
+            .is_some_and(|outputs| outputs.values().any(|v| v.layer_surface.is_some()))
+    }
+
+    pub fn no_freeze_attach_with_udata(&mut self, udata: usize) {
+        self.foam_outputs
             .as_mut()
             .unwrap()
+            .get_mut(&udata)
+            .unwrap()
             .clean_attach();
     }
 
     /// 所有输出设备发起全屏捕获请求
     pub fn request_screencopy(&mut self) {
         debug!("发起屏幕copy请求");
+        self.scm.pending.clear();
         // 遍历所有 outputs
         self.foam_outputs
             .as_mut()
             .unwrap()
             .iter_mut()
-            .enumerate()
-            .for_each(|(i, m)| {
+            .for_each(|(&i, m)| {
                 self.scm.request_copy_one(
                     self.config.cursor,
                     m.output.as_ref().unwrap(),
@@ -195,6 +406,52 @@ impl WaylandCtx {
                 );
             });
     }
+    /// 选区已知时只对与选区相交的输出发起区域捕获，减少 copy 的数据量
+    pub fn request_screencopy_region(&mut self) {
+        debug!("发起区域copy请求");
+        self.scm.pending.clear();
+        let cursor = self.config.cursor;
+        let qh = self.qh.as_ref().unwrap();
+        for (&i, m) in self.foam_outputs.as_ref().unwrap().iter() {
+            let Some(rect) = m.subrect.as_ref() else {
+                continue;
+            };
+            // 子矩形为物理坐标，capture_output_region 需要逻辑坐标，向外取整保证覆盖选区
+            let scale = m.buffer_scale();
+            let lx = (rect.relative_min_x as f64 / scale).floor() as i32;
+            let ly = (rect.relative_min_y as f64 / scale).floor() as i32;
+            let lr = ((rect.relative_min_x + rect.width) as f64 / scale).ceil() as i32;
+            let lb = ((rect.relative_min_y + rect.height) as f64 / scale).ceil() as i32;
+            let origin = (
+                (lx as f64 * scale).round() as i32,
+                (ly as f64 * scale).round() as i32,
+            );
+            self.scm.request_copy_region(
+                cursor,
+                m.output.as_ref().unwrap(),
+                (lx, ly, lr - lx, lb - ly),
+                origin,
+                qh,
+                i,
+            );
+        }
+    }
+
+    /// 连续截图保存后清空选区，保留指针当前位置以便直接开始下一次选择
+    pub fn clear_selection(&mut self) {
+        self.global_rect = None;
+        self.pointer_helper.g_start_pos = None;
+        self.pointer_helper.g_end_pos = None;
+        self.foam_outputs
+            .as_mut()
+            .unwrap()
+            .values_mut()
+            .for_each(|v| {
+                v.subrect = None;
+                v.last_rect = None;
+            });
+    }
+
     /// 通过 pointer_helper 坐标计算全局父矩形
     pub fn compute_global_rect(&mut self) {
         // 解包起始位置和当前位置
@@ -217,9 +474,30 @@ impl WaylandCtx {
     }
     /// 根据父矩形计算每个输出上的子矩形，如果存在，对应输出的surface请求下一帧
     pub fn process_subrects_and_send(&mut self) {
-        let foam_outputs = self.foam_outputs.as_mut().unwrap();
-        let rect = self.global_rect.as_ref().unwrap();
+        self.compute_subrects();
+        let qh = self.qh.as_ref().unwrap();
+        for output in self.foam_outputs.as_mut().unwrap().values_mut() {
+            if let Some(surface) = &mut output.surface {
+                surface.frame(qh, output.id);
+                // surface.damage_buffer(0, 0, output.width, output.height);
+                surface.commit();
+            }
+        }
+    }
 
+    /// 由全局逻辑坐标的矩形计算每个输出上的子矩形，与 `process_subrects_and_send` 相同但不提交 surface
+    /// 返回是否与任一输出相交
+    pub fn process_logical_subrects(&mut self, rect: SelectRect) -> bool {
+        self.global_rect = Some(rect);
+        self.compute_subrects()
+    }
+
+    /// NOTE: 父矩形与各输出在全局逻辑坐标下求交集，再按各自的缩放转为物理像素的子矩形
+    /// 混合缩放时每个输出得到的都是自身 buffer 上的坐标
+    fn compute_subrects(&mut self) -> bool {
+        let Some(rect) = self.global_rect.as_ref() else {
+            return false;
+        };
         let SelectRect {
             sx: min_x,
             sy: min_y,
@@ -228,37 +506,62 @@ impl WaylandCtx {
             ..
         } = *rect;
 
-        for output in foam_outputs {
+        let mut found = false;
+        for output in self.foam_outputs.as_mut().unwrap().values_mut() {
+            let (logical_width, logical_height) = output.logical_size();
             // 计算与当前输出的交集区域
             let intersect_left = output.global_x.max(min_x);
             let intersect_top = output.global_y.max(min_y);
-            let intersect_right = (output.global_x + output.width).min(max_x);
-            let intersect_bottom = (output.global_y + output.height).min(max_y);
+            let intersect_right = (output.global_x + logical_width).min(max_x);
+            let intersect_bottom = (output.global_y + logical_height).min(max_y);
 
             // 判断有效交集区域
             if intersect_left < intersect_right && intersect_top < intersect_bottom {
-                // 计算相对于输出的局部坐标
-                let local_x = intersect_left - output.global_x;
-                let local_y = intersect_top - output.global_y;
-                let width = intersect_right - intersect_left;
-                let height = intersect_bottom - intersect_top;
+                // 计算相对于输出的物理坐标
+                let scale = output.buffer_scale();
+                let to_physical = |v: i32, max: i32| ((v as f64 * scale).round() as i32).min(max);
+                let x = to_physical(intersect_left - output.global_x, output.width);
+                let y = to_physical(intersect_top - output.global_y, output.height);
+                let right = to_physical(intersect_right - output.global_x, output.width);
+                let bottom = to_physical(intersect_bottom - output.global_y, output.height);
 
                 // 更新输出状态
-                output.new_subrect(local_x, local_y, width, height);
+                output.new_subrect(x, y, right - x, bottom - y);
+                found |= output.subrect.is_some();
             } else {
                 // 清理无效区域
                 output.subrect = None;
-                // TODO: 这里应该不用显示设置为false
-                // output.need_redraw = false;
             }
+        }
+        found
+    }
 
-            if let Some(surface) = &mut output.surface {
-                let qh = self.qh.as_ref().unwrap();
-                surface.frame(qh, output.id);
-                // surface.damage_buffer(0, 0, output.width, output.height);
-                surface.commit();
+    /// 将各输出上的子矩形换算回全局逻辑坐标，返回其外接矩形与相交面积最大的输出
+    pub fn logical_selection(&self) -> Option<(SelectRect, usize)> {
+        let mut bounds: Option<(i32, i32, i32, i32)> = None;
+        let mut best: Option<(i64, usize)> = None;
+        for (&id, output) in self.foam_outputs.as_ref()?.iter() {
+            let Some(rect) = output.subrect.as_ref() else {
+                continue;
+            };
+            let scale = output.buffer_scale();
+            let to_logical = |v: i32| (v as f64 / scale).round() as i32;
+            let left = output.global_x + to_logical(rect.relative_min_x);
+            let top = output.global_y + to_logical(rect.relative_min_y);
+            let right = output.global_x + to_logical(rect.relative_min_x + rect.width);
+            let bottom = output.global_y + to_logical(rect.relative_min_y + rect.height);
+            bounds = Some(match bounds {
+                Some((l, t, r, b)) => (l.min(left), t.min(top), r.max(right), b.max(bottom)),
+                None => (left, top, right, bottom),
+            });
+
+            let area = rect.width as i64 * rect.height as i64;
+            if best.is_none_or(|(a, _)| area > a) {
+                best = Some((area, id));
             }
         }
+        let (sx, sy, ex, ey) = bounds?;
+        Some((SelectRect::new(sx, sy, ex, ey), best?.1))
     }
 
     /// 计算一个最小矩形可以覆盖显示器坐标系中所有输出
@@ -273,7 +576,7 @@ impl WaylandCtx {
         let mut max_y = i32::MIN;
 
         // 遍历每个显示器，更新边界
-        for output in outputs {
+        for output in outputs.values() {
             min_x = min_x.min(output.global_x);
             min_y = min_y.min(output.global_y);
             max_x = max_x.max(output.global_x + output.width);
@@ -296,7 +599,7 @@ impl WaylandCtx {
 
         // 遍历每个显示器，生成对应的子 Surface
         if let Some(outputs) = &mut self.foam_outputs {
-            for output in outputs.iter_mut() {
+            for output in outputs.values_mut() {
                 // 计算子 Surface 的位置和大小
                 let x = output.global_x - x;
                 let y = output.global_y - y;
@@ -333,22 +636,164 @@ impl WaylandCtx {
             .as_mut()
             .unwrap()
             .iter_mut()
-            .enumerate()
             .for_each(|(i, v)| {
                 if v.last_rect == v.subrect {
                     return;
                 }
                 v.last_rect = v.subrect.clone();
-                let base_canvas = self.scm.base_canvas.as_mut().unwrap().get_mut(&i).unwrap();
-
-                v.update_select_subrect(base_canvas, self.current_freeze);
+                // 非冻结模式下不需要画布，也可能尚未进行过 copy
+                // 会话中途加入的输出没有冻结画面，同样按非冻结处理
+                match self.scm.base_canvas.as_ref().unwrap().get(i) {
+                    Some(base_canvas) if self.current_freeze => {
+                        v.update_select_subrect(base_canvas, true)
+                    }
+                    _ => v.update_select_subrect(&[], false),
+                }
             });
     }
 
-    pub fn storage_copy_canvas(&mut self) {
-        for (i, v) in self.foam_outputs.as_mut().unwrap().iter_mut().enumerate() {
+    /// 为一次 copy 创建 shm buffer，截图时写入对应输出的 pool，录制时写入 `FrameQueue`
+    pub fn create_copy_buffer(
+        &mut self,
+        udata: usize,
+        width: i32,
+        height: i32,
+        stride: i32,
+        format: Format,
+        target: &UserTarget,
+    ) -> Result<WlBuffer, String> {
+        match target {
+            UserTarget::Shot => {
+                let current = self
+                    .foam_outputs
+                    .as_mut()
+                    .and_then(|outputs| outputs.get_mut(&udata))
+                    .ok_or_else(|| format!("output {} was removed", udata))?;
+                let shm = self.shm.as_mut().ok_or("wl_shm is not bound")?;
+                let mut pool = SlotPool::new(stride as usize * height as usize, shm)
+                    .map_err(|e| format!("failed to create pool: {}", e))?;
+                let (buffer, canvas) = pool
+                    .create_buffer(width, height, stride, format)
+                    .map_err(|e| format!("failed to create buffer: {}", e))?;
+                canvas.fill(0);
+                current.pool = Some(pool);
+                let wl_buffer = buffer.wl_buffer().clone();
+                let info = CanvasInfo {
+                    width,
+                    height,
+                    stride,
+                    format,
+                };
+                self.scm.dmabuf_buffers.remove(&udata);
+                self.scm.insert_buffer(udata, buffer, info)?;
+                Ok(wl_buffer)
+            }
+            UserTarget::Recorder => self.fq.new_buffer(width, height, stride, format),
+        }
+    }
+
+    /// 在 `BufferDone` 时根据合成器给出的参数创建 copy 使用的 buffer
+    /// 开启 --dmabuf 且合成器提供了 dmabuf 参数时优先使用 dmabuf，失败时回退到 shm
+    pub fn attach_copy_buffer(&mut self, udata: usize, target: &UserTarget) -> Option<WlBuffer> {
+        let formats = self.scm.frame_formats.remove(&udata).unwrap_or_default();
+        if self.config.dmabuf
+            && let Some((fourcc, width, height)) = formats.dmabuf
+            && let Some(buffer) = self.create_dmabuf_buffer(udata, fourcc, width, height, target)
+        {
+            return Some(buffer);
+        }
+
+        let (width, height, stride, format) = formats.shm?;
+        self.create_copy_buffer(udata, width, height, stride, format, target)
+            .inspect_err(|e| warn!("output {}: {}", udata, e))
+            .ok()
+    }
+
+    /// 创建 udmabuf 支持的 wl_buffer，不支持的格式或设备不可用时返回 None
+    fn create_dmabuf_buffer(
+        &mut self,
+        udata: usize,
+        fourcc: u32,
+        width: i32,
+        height: i32,
+        target: &UserTarget,
+    ) -> Option<WlBuffer> {
+        let format = dmabuf::fourcc_to_shm(fourcc)?;
+        let stride = width * pixel_format::bytes_per_pixel(format)? as i32;
+
+        // 录制时尺寸不变则复用上一帧的 dmabuf
+        if let UserTarget::Recorder = target
+            && let Some(d) = self.fq.current_dmabuf.as_ref()
+            && (d.width, d.height, d.format) == (width, height, format)
+        {
+            let buffer = d.buffer.clone();
+            self.fq.use_dmabuf(None);
+            return Some(buffer);
+        }
+
+        let (linux_dmabuf, _) = self.dmabuf.as_ref()?;
+        if self.udmabuf.is_none() {
+            match UdmabufAllocator::open() {
+                Ok(allocator) => self.udmabuf = Some(allocator),
+                Err(e) => {
+                    warn!("failed to open /dev/udmabuf: {}, fallback to shm", e);
+                    self.config.dmabuf = false;
+                    return None;
+                }
+            }
+        }
+        let mem = match self
+            .udmabuf
+            .as_ref()
+            .unwrap()
+            .allocate(stride as usize * height as usize)
+        {
+            Ok(mem) => mem,
+            Err(e) => {
+                warn!("failed to allocate udmabuf: {}, fallback to shm", e);
+                return None;
+            }
+        };
+
+        let qh = self.qh.as_ref().unwrap();
+        let params = linux_dmabuf.create_params(qh, ());
+        params.add(
+            mem.dmabuf.as_fd(),
+            0,
+            0,
+            stride as u32,
+            (dmabuf::DRM_FORMAT_MOD_LINEAR >> 32) as u32,
+            dmabuf::DRM_FORMAT_MOD_LINEAR as u32,
+        );
+        let buffer = params.create_immed(width, height, fourcc, Flags::empty(), qh, ());
+        params.destroy();
+
+        let dmabuf_buffer = DmabufBuffer {
+            buffer: buffer.clone(),
+            mem,
+            width,
+            height,
+            stride,
+            format,
+        };
+        match target {
+            UserTarget::Shot => self.scm.insert_dmabuf(udata, dmabuf_buffer),
+            UserTarget::Recorder => self.fq.use_dmabuf(Some(dmabuf_buffer)),
+        }
+        Some(buffer)
+    }
+
+    /// 存储本轮 copy 到的画布
+    pub fn storage_copy_canvas(&mut self) -> Result<(), String> {
+        let pending = std::mem::take(&mut self.scm.pending);
+        for i in pending {
+            let Some(v) = self.foam_outputs.as_mut().unwrap().get_mut(&i) else {
+                continue;
+            };
+            let output_transform = v.output_transform();
             let pool = v.pool.as_mut().unwrap();
-            self.scm.insert_canvas(i, pool);
+            self.scm.insert_canvas(i, pool, output_transform)?;
         }
+        Ok(())
     }
 }
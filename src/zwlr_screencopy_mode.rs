@@ -1,31 +1,246 @@
 //! INFO: dispatch package for screen capture
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use smithay_client_toolkit::shm::slot::{Buffer, SlotPool};
-use wayland_client::{QueueHandle, protocol::wl_output};
+use chrono::{DateTime, Local};
+use log::info;
+use smithay_client_toolkit::shm::{
+    Shm,
+    slot::{Buffer, SlotPool},
+};
+use wayland_client::{
+    QueueHandle,
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_output::{self, Transform},
+        wl_shm::Format,
+    },
+};
+use wayland_protocols::ext::{
+    image_capture_source::v1::client::{
+        ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1,
+        ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+    },
+    image_copy_capture::v1::client::{
+        ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1,
+        ext_image_copy_capture_session_v1::ExtImageCopyCaptureSessionV1,
+    },
+};
 use wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_manager_v1;
 
-use crate::foamcore::FoamShot;
+use crate::{
+    capture_backend::{CaptureBackend, ExtImageCopyBackend, WlrScreencopyBackend},
+    dmabuf::DmabufBuffer,
+    foamcore::FoamShot,
+    frame_transform::FrameTransform,
+    pixel_format, protocols,
+};
 
 #[derive(Default)]
 /// NOTE: 统一管理所有输出的screen copy
 pub struct ZwlrScreencopyMode {
+    /// 当前使用的捕获后端，由 `select_backend` 在 registry 阶段选择
+    pub backend: Option<Box<dyn CaptureBackend>>,
+    /// 区域捕获使用的后端，None 时使用 `backend`
+    pub region_backend: Option<Box<dyn CaptureBackend>>,
+    /// u32 is wl_registry name
     pub manager: Option<(zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1, u32)>,
+    pub ext_source_manager: Option<(ExtOutputImageCaptureSourceManagerV1, u32)>,
+    pub ext_copy_manager: Option<(ExtImageCopyCaptureManagerV1, u32)>,
+    pub ext_toplevel_source_manager: Option<(ExtForeignToplevelImageCaptureSourceManagerV1, u32)>,
+    /// 窗口捕获完成的计数
     pub copy_ready: usize,
+    /// 本轮 copy 请求的输出索引，全部 ready 后才存储画布
+    pub pending: Vec<usize>,
+    /// 本轮已经 ready 的输出，输出中途被移除时不会误计数
+    pub ready: HashSet<usize>,
+    /// 每个输出最近一次 copy 完成的时间
+    pub captured_at: HashMap<usize, DateTime<Local>>,
     pub base_buffers: Option<HashMap<usize, Buffer>>,
     pub base_canvas: Option<HashMap<usize, Vec<u8>>>,
+    /// 每个输出画布的尺寸与格式
+    pub canvas_info: HashMap<usize, CanvasInfo>,
+    /// 每个输出画布左上角在输出上的物理坐标，区域捕获时不为 (0, 0)
+    pub canvas_origin: HashMap<usize, (i32, i32)>,
+    /// 窗口捕获使用的 buffer，不属于任何输出
+    pub window_buffer: Option<(SlotPool, Buffer, CanvasInfo)>,
+    /// 使用 dmabuf 接收的输出 buffer，存在时优先于 `base_buffers` 读取
+    pub dmabuf_buffers: HashMap<usize, DmabufBuffer>,
+    /// wlr-screencopy 在 `BufferDone` 之前给出的 buffer 参数
+    pub frame_formats: HashMap<usize, FrameFormats>,
+    /// 优先使用 wlr-screencopy，只有它支持 dmabuf
+    pub prefer_wlr: bool,
+    /// 每个输出本轮 copy 的 buffer 变换，存储画布时转为正向
+    pub frame_transform: HashMap<usize, FrameTransform>,
+    /// 窗口捕获的 buffer 变换
+    pub window_transform: FrameTransform,
+    /// 是否保留转换前的画布
+    pub keep_deep: bool,
+    /// 转换为 `Argb8888` 之前的正向画布，仅在 `keep_deep` 时存储
+    pub deep_canvas: HashMap<usize, SingleCanvas>,
+    /// 录制时复用的 ext capture session，每帧只创建新的 frame
+    pub recorder_session: Option<ExtImageCopyCaptureSessionV1>,
+}
+
+/// NOTE: 合成器为一次 copy 提供的 buffer 参数，在 `BufferDone` 时选择其一
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameFormats {
+    /// (width, height, stride, format)
+    pub shm: Option<(i32, i32, i32, Format)>,
+    /// (fourcc, width, height)
+    pub dmabuf: Option<(u32, i32, i32)>,
+}
+
+/// NOTE: 画布的尺寸与格式
+#[derive(Debug, Clone, Copy)]
+pub struct CanvasInfo {
+    pub width: i32,
+    pub height: i32,
+    pub stride: i32,
+    pub format: Format,
+}
+
+/// NOTE: 不属于任何输出的单张画布，例如窗口捕获的结果
+#[derive(Debug, Clone)]
+pub struct SingleCanvas {
+    pub data: Vec<u8>,
+    pub info: CanvasInfo,
 }
 
 impl ZwlrScreencopyMode {
-    pub fn new(manager: (zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1, u32)) -> Self {
+    pub fn new() -> Self {
         Self {
-            manager: Some(manager),
-            copy_ready: 0,
             base_buffers: Some(HashMap::new()),
             base_canvas: Some(HashMap::new()),
+            ..Default::default()
         }
     }
+
+    /// 根据已绑定的 global 选择后端，ext-image-copy-capture 优先，wlr-screencopy 作为回退
+    /// `prefer_wlr` 时顺序相反
+    /// NOTE: ext 后端没有区域捕获，同时绑定了 wlr-screencopy 时区域捕获改用它，避免复制整个输出
+    pub fn select_backend(&mut self) {
+        let wlr = self
+            .manager
+            .as_ref()
+            .map(|(manager, _)| WlrScreencopyBackend {
+                manager: manager.clone(),
+            });
+        let ext = match (&self.ext_source_manager, &self.ext_copy_manager) {
+            (Some((source_manager, _)), Some((copy_manager, _))) => Some(ExtImageCopyBackend {
+                source_manager: source_manager.clone(),
+                copy_manager: copy_manager.clone(),
+                toplevel_source_manager: self
+                    .ext_toplevel_source_manager
+                    .as_ref()
+                    .map(|(m, _)| m.clone()),
+            }),
+            _ => None,
+        };
+        let (backend, region_backend): (Option<Box<dyn CaptureBackend>>, _) = match (ext, wlr) {
+            (ext, Some(wlr)) if self.prefer_wlr || ext.is_none() => (Some(Box::new(wlr)), None),
+            (Some(ext), wlr) => (
+                Some(Box::new(ext)),
+                wlr.map(|wlr| Box::new(wlr) as Box<dyn CaptureBackend>),
+            ),
+            _ => (None, None),
+        };
+        self.backend = backend;
+        self.region_backend = region_backend;
+        if let Some(backend) = self.backend.as_ref() {
+            info!("capture backend: {}", backend.name());
+        }
+        if let Some(backend) = self.region_backend.as_ref() {
+            info!("region capture backend: {}", backend.name());
+        }
+    }
+
+    /// global 被移除时调用，如果属于捕获后端则重新选择，返回是否匹配
+    pub fn remove_global(&mut self, name: u32) -> bool {
+        let matched = if self.manager.as_ref().is_some_and(|(_, n)| *n == name) {
+            self.manager = None;
+            true
+        } else if self
+            .ext_source_manager
+            .as_ref()
+            .is_some_and(|(_, n)| *n == name)
+        {
+            self.ext_source_manager = None;
+            true
+        } else if self
+            .ext_copy_manager
+            .as_ref()
+            .is_some_and(|(_, n)| *n == name)
+        {
+            self.ext_copy_manager = None;
+            true
+        } else if self
+            .ext_toplevel_source_manager
+            .as_ref()
+            .is_some_and(|(_, n)| *n == name)
+        {
+            self.ext_toplevel_source_manager = None;
+            true
+        } else {
+            false
+        };
+        if matched {
+            self.select_backend();
+        }
+        matched
+    }
+
+    /// 输出的 copy 完成，记录完成的时间
+    pub fn mark_ready(&mut self, udata: usize) {
+        self.ready.insert(udata);
+        self.captured_at.insert(udata, Local::now());
+    }
+
+    /// 清空上次会话的 buffer、画布与请求，保留已绑定的 manager
+    pub fn reset(&mut self) {
+        self.copy_ready = 0;
+        self.pending.clear();
+        self.ready.clear();
+        self.captured_at.clear();
+        self.base_buffers = Some(HashMap::new());
+        self.base_canvas = Some(HashMap::new());
+        self.canvas_info.clear();
+        self.canvas_origin.clear();
+        self.window_buffer = None;
+        self.dmabuf_buffers.clear();
+        self.frame_formats.clear();
+        self.frame_transform.clear();
+        self.window_transform = FrameTransform::default();
+        self.deep_canvas.clear();
+        self.stop_recorder_session();
+    }
+
+    /// 销毁录制复用的 session
+    pub fn stop_recorder_session(&mut self) {
+        if let Some(session) = self.recorder_session.take() {
+            protocols::destroy_session(&session);
+        }
+    }
+
+    /// 输出被移除时清理其 buffer、画布与本轮请求
+    pub fn remove_output(&mut self, udata: usize) {
+        if let Some(buffers) = self.base_buffers.as_mut() {
+            buffers.remove(&udata);
+        }
+        if let Some(canvas) = self.base_canvas.as_mut() {
+            canvas.remove(&udata);
+        }
+        self.canvas_info.remove(&udata);
+        self.canvas_origin.remove(&udata);
+        self.dmabuf_buffers.remove(&udata);
+        self.frame_formats.remove(&udata);
+        self.frame_transform.remove(&udata);
+        self.deep_canvas.remove(&udata);
+        self.ready.remove(&udata);
+        self.captured_at.remove(&udata);
+        self.pending.retain(|&i| i != udata);
+    }
+
     pub fn request_copy_one(
         &mut self,
         cursor: bool,
@@ -33,24 +248,124 @@ impl ZwlrScreencopyMode {
         qh: &QueueHandle<FoamShot>,
         udata: usize,
     ) {
-        let manager = if let Some((ref manager, _)) = self.manager {
-            manager
-        } else {
+        let Some(backend) = self.backend.as_ref() else {
+            return;
+        };
+        backend.capture_output(cursor, output, qh, udata);
+        self.frame_transform.remove(&udata);
+        self.pending.push(udata);
+        self.canvas_origin.insert(udata, (0, 0));
+    }
+
+    /// 请求一个输出上的部分区域
+    /// `region` 为输出内的逻辑坐标，`origin` 为该区域左上角的物理坐标，用于之后裁剪
+    pub fn request_copy_region(
+        &mut self,
+        cursor: bool,
+        output: &wl_output::WlOutput,
+        region: (i32, i32, i32, i32),
+        origin: (i32, i32),
+        qh: &QueueHandle<FoamShot>,
+        udata: usize,
+    ) {
+        let Some(backend) = self.region_backend.as_ref().or(self.backend.as_ref()) else {
             return;
         };
-        manager.capture_output(cursor as i32, output, qh, udata);
+        let origin = if backend.capture_output_region(cursor, output, region, qh, udata) {
+            origin
+        } else {
+            (0, 0)
+        };
+        self.frame_transform.remove(&udata);
+        self.pending.push(udata);
+        self.canvas_origin.insert(udata, origin);
+    }
+
+    /// 本轮请求是否全部完成
+    pub fn all_ready(&self) -> bool {
+        self.pending.iter().all(|i| self.ready.contains(i))
     }
 
-    pub fn insert_buffer(&mut self, udata: usize, buffer: Buffer) -> Result<(), String> {
+    pub fn insert_buffer(
+        &mut self,
+        udata: usize,
+        buffer: Buffer,
+        info: CanvasInfo,
+    ) -> Result<(), String> {
+        self.canvas_info.insert(udata, info);
         match self.base_buffers.as_mut().unwrap().insert(udata, buffer) {
             Some(_) => Ok(()),
             None => Ok(()),
         }
     }
 
-    pub fn insert_canvas(&mut self, udata: usize, pool: &mut SlotPool) {
-        let buffer = self.base_buffers.as_ref().unwrap().get(&udata).unwrap();
-        let canvas = buffer.canvas(pool).unwrap().to_vec();
+    /// 为窗口捕获创建 buffer
+    pub fn create_window_buffer(
+        &mut self,
+        shm: &Shm,
+        info: CanvasInfo,
+    ) -> Result<WlBuffer, String> {
+        let mut pool = SlotPool::new(info.stride as usize * info.height as usize, shm)
+            .map_err(|e| format!("failed to create pool: {}", e))?;
+        let (buffer, canvas) = pool
+            .create_buffer(info.width, info.height, info.stride, info.format)
+            .map_err(|e| format!("failed to create buffer: {}", e))?;
+        canvas.fill(0);
+        let wl_buffer = buffer.wl_buffer().clone();
+        self.window_buffer = Some((pool, buffer, info));
+        Ok(wl_buffer)
+    }
+
+    /// 取出窗口捕获的画布，保持 buffer 的原始格式
+    pub fn take_window_canvas(&mut self) -> Option<SingleCanvas> {
+        let (mut pool, buffer, info) = self.window_buffer.take()?;
+        let data = buffer.canvas(&mut pool)?.to_vec();
+        let (data, info) =
+            std::mem::take(&mut self.window_transform).apply(data, info, Transform::Normal);
+        Some(SingleCanvas { data, info })
+    }
+
+    /// 记录使用 dmabuf 的输出 buffer
+    pub fn insert_dmabuf(&mut self, udata: usize, buffer: DmabufBuffer) {
+        self.canvas_info.insert(
+            udata,
+            CanvasInfo {
+                width: buffer.width,
+                height: buffer.height,
+                stride: buffer.stride,
+                format: buffer.format,
+            },
+        );
+        self.dmabuf_buffers.insert(udata, buffer);
+    }
+
+    /// 读取输出的 buffer 并按 `output_transform` 与本轮的 flags 转为正向画布
+    pub fn insert_canvas(
+        &mut self,
+        udata: usize,
+        pool: &mut SlotPool,
+        output_transform: Transform,
+    ) -> Result<(), String> {
+        let canvas = match self.dmabuf_buffers.get(&udata) {
+            Some(dmabuf) => dmabuf.canvas(),
+            None => {
+                let buffer = self.base_buffers.as_ref().unwrap().get(&udata).unwrap();
+                buffer.canvas(pool).unwrap().to_vec()
+            }
+        };
+        let transform = self.frame_transform.remove(&udata).unwrap_or_default();
+        let (canvas, info) = transform.apply(canvas, self.canvas_info[&udata], output_transform);
+        // NOTE: 保存 16 位 png 时需要原始精度的画布
+        if self.keep_deep {
+            let deep = SingleCanvas {
+                data: canvas.clone(),
+                info,
+            };
+            self.deep_canvas.insert(udata, deep);
+        }
+        let (canvas, info) = pixel_format::to_argb8888(canvas, info)?;
+        self.canvas_info.insert(udata, info);
         self.base_canvas.as_mut().unwrap().insert(udata, canvas);
+        Ok(())
     }
 }